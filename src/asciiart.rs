@@ -1,4 +1,7 @@
 use image2ascii::{Char2DArray, image2ascii};
+use rand::rngs::StdRng;
+use super::uifuncs::RenderColor;
+use super::cellart::image2colors;
 use super::effector::{Effector, generate_effector, EffectorType, EffectorStatus, generate_appear_effector_randomly, generate_disappear_effector_randomly};
 use super::behavior::{Behavior, generate_behavior, BehaviorType, generate_behavior_randomly};
 
@@ -14,6 +17,10 @@ pub struct AsciiArt {
     //context: AsciiArtContext,
     aa_file: String,
     aa_original: Char2DArray,
+    /// colour of each character of aa_original. None for black and white art
+    aa_colors: Option<Vec<Vec<RenderColor>>>,
+    /// the character of `silhouette`. kept to paint the art again after a resize
+    silhouette_char: Option<char>,
     aa_data: Char2DArray,
     aa_pos: (i32, i32),
     state: AsciiArtState,
@@ -30,37 +37,42 @@ pub struct AsciiArtContext {
 }
 
 impl AsciiArt {
-    pub fn from_image_easy(image_file: &str, context: &AsciiArtContext) -> Result<AsciiArt, String> {
+    /// `rng` picks the effects and the behavior
+    pub fn from_image_easy(image_file: &str, context: &AsciiArtContext, rng: &mut StdRng) -> Result<AsciiArt, String> {
         let original_data = image2ascii(&image_file, context.aa_width as u32, Option::None, Option::None).unwrap();
         let aa_wxh = (original_data.buffer[0].len(), original_data.buffer.len());
         let effector_duration = 1;
         let ret = AsciiArt {
             aa_file: String::from(image_file),
             aa_original: original_data,
+            aa_colors: None,
+            silhouette_char: None,
             aa_data: Char2DArray::new(aa_wxh.0, aa_wxh.1),
             aa_pos: (0,0),
             state: AsciiArtState::APPEAR,
             current_frame: 0,
-            appear_effector   : generate_appear_effector_randomly(effector_duration, context.framerate),
-            behavior     : generate_behavior_randomly(context.framerate, context.stage_wxh, aa_wxh),
-            disappear_effector: generate_disappear_effector_randomly(effector_duration, context.framerate),
+            appear_effector   : generate_appear_effector_randomly(effector_duration, context.framerate, rng),
+            behavior     : generate_behavior_randomly(context.framerate, context.stage_wxh, aa_wxh, rng),
+            disappear_effector: generate_disappear_effector_randomly(effector_duration, context.framerate, rng),
         };
         Ok(ret)
     }
 
-    pub fn from_image (image_file: &str, context: &AsciiArtContext, appear: EffectorType, disappear: EffectorType, behavior: BehaviorType) -> Result<AsciiArt, String> {
+    pub fn from_image (image_file: &str, context: &AsciiArtContext, appear: EffectorType, disappear: EffectorType, behavior: BehaviorType, rng: &mut StdRng) -> Result<AsciiArt, String> {
         let original_data = image2ascii(&image_file, context.aa_width as u32, Option::None, Option::None).unwrap();
         let aa_wxh = (original_data.buffer[0].len(), original_data.buffer.len());
         let effector_duration = 1;
         let ret = AsciiArt {
             aa_file: String::from(image_file),
             aa_original: original_data,
+            aa_colors: None,
+            silhouette_char: None,
             aa_data: Char2DArray::new(aa_wxh.0, aa_wxh.1),
             aa_pos: (0,0),
             state: AsciiArtState::APPEAR,
             current_frame: 0,
             appear_effector   : generate_effector(appear, effector_duration, context.framerate),
-            behavior     : generate_behavior(behavior, context.framerate, context.stage_wxh, aa_wxh),
+            behavior     : generate_behavior(behavior, context.framerate, context.stage_wxh, aa_wxh, rng),
             disappear_effector: generate_effector(disappear, effector_duration, context.framerate),
         };
         Ok(ret)
@@ -99,6 +111,47 @@ impl AsciiArt {
         }
     }
     
+    /// sample the colour of each character from the image
+    pub fn load_colors(&mut self) -> Result<(), String> {
+        let colors = image2colors(&self.aa_file, self.aa_original.width(), self.aa_original.height())?;
+        self.aa_colors = Some(colors);
+        Ok(())
+    }
+
+    pub fn get_colors(&self) -> Option<&Vec<Vec<RenderColor>>> {
+        self.aa_colors.as_ref()
+    }
+
+    /// paint every visible character with `ch`
+    pub fn silhouette(&mut self, ch: char) {
+        self.aa_colors = None;
+        self.silhouette_char = Some(ch);
+        for line in self.aa_original.buffer.iter_mut() {
+            for c in line.iter_mut().filter(|c| **c != ' ') {
+                *c = ch;
+            }
+        }
+    }
+
+    /// make the art from the image again at the width and stage size of the context.
+    /// the effects and the behavior go on from where they are
+    pub fn resize(&mut self, context: &AsciiArtContext) -> Result<(), String> {
+        self.aa_original = image2ascii(&self.aa_file, context.aa_width as u32, Option::None, Option::None)?;
+        if let Some(ch) = self.silhouette_char {
+            self.silhouette(ch);
+        } else if self.aa_colors.is_some() {
+            self.load_colors()?;
+        }
+        let aa_wxh = (self.aa_original.width(), self.aa_original.height());
+        self.aa_data = Char2DArray::new(aa_wxh.0, aa_wxh.1);
+        if let AsciiArtState::MOVE = self.state {
+            // the appear effect has finished and no longer writes the data
+            self.aa_data.copy_from(&self.aa_original);
+        }
+        self.behavior.resize(context.stage_wxh, aa_wxh);
+        Ok(())
+    }
+
     pub fn disapper(&mut self){
         self.state = AsciiArtState::DISAPPER;
     }
@@ -126,7 +179,7 @@ fn from_image_works(){
             
     };
     let appear = EffectorType::NO;
-    let mut aa = AsciiArt::from_image(&String::from(""), &context, EffectorType::NO, EffectorType::NO, BehaviorType::NO);
+    let mut aa = AsciiArt::from_image(&String::from(""), &context, EffectorType::NO, EffectorType::NO, BehaviorType::NO, &mut rand::SeedableRng::seed_from_u64(0));
     
     
 }
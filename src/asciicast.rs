@@ -0,0 +1,195 @@
+//! tee the frames of any ui backend into an asciicast v2 file which terminal players like asciinema can play
+
+use crossbeam_channel as channel;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use super::capture::SharedCapture;
+use super::cellart::Cell;
+use super::renderer::{ColorDepth, Renderer, SharedRenderStats};
+use super::uifuncs::{RenderColor, UIFuncs, UIGraphics, UIKeyEvent};
+
+/// first line of an asciicast v2 file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AsciicastHeader {
+    pub version: u32,
+    pub width: usize,
+    pub height: usize,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// writes "o" events with the seconds since the file was created
+pub struct AsciicastWriter {
+    writer: BufWriter<File>,
+    start: Instant,
+    path: String,
+}
+
+impl AsciicastWriter {
+    pub fn create(cast_file: &str, width: usize, height: usize) -> Result<Self, String> {
+        let file = File::create(cast_file).map_err(|e| format!("can not create cast file {}: {}", cast_file, e))?;
+        let mut env = BTreeMap::new();
+        env.insert(String::from("TERM"), String::from("xterm-256color"));
+        let header = AsciicastHeader {
+            version: 2,
+            width,
+            height,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            title: String::from("aquatyping"),
+            env,
+        };
+        let mut writer = AsciicastWriter { writer: BufWriter::new(file), start: Instant::now(), path: cast_file.to_string() };
+        let line = serde_json::to_string(&header).map_err(|e| e.to_string())?;
+        writer.write_line(&line)?;
+        // the player starts from a clean screen without a cursor
+        writer.output(&format!("{}{}", termion::clear::All, termion::cursor::Hide))?;
+        Ok(writer)
+    }
+
+    pub fn output(&mut self, data: &str) -> Result<(), String> {
+        let time = (self.start.elapsed().as_micros() as f64) / 1_000_000.0;
+        let line = serde_json::to_string(&(time, "o", data)).map_err(|e| e.to_string())?;
+        self.write_line(&line)
+    }
+
+    /// an "r" event. players resize the terminal to WxH
+    pub fn resize(&mut self, width: usize, height: usize) -> Result<(), String> {
+        let time = (self.start.elapsed().as_micros() as f64) / 1_000_000.0;
+        let line = serde_json::to_string(&(time, "r", format!("{}x{}", width, height))).map_err(|e| e.to_string())?;
+        self.write_line(&line)?;
+        self.output(termion::clear::All.as_ref())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.writer, "{}", line).and_then(|_| self.writer.flush())
+            .map_err(|e| format!("can not write cast file {}: {}", self.path, e))
+    }
+}
+
+pub type SharedAsciicastWriter = Arc<Mutex<AsciicastWriter>>;
+
+/// draws into the inner graphics and renders the same cells for the cast
+pub struct AsciicastGraphics {
+    inner: Box<dyn UIGraphics>,
+    renderer: RefCell<Renderer>,
+    writer: SharedAsciicastWriter,
+}
+
+impl UIGraphics for AsciicastGraphics {
+    fn draw_area(&self, color: &RenderColor, bgcolor: &RenderColor, rect: &Vec<Vec<char>>, offset: Option<(usize, usize)>) {
+        self.inner.draw_area(color, bgcolor, rect, offset);
+        self.renderer.borrow_mut().draw_area(color, bgcolor, rect, offset);
+    }
+
+    fn draw_cells(&self, rect: &[Vec<Cell>], offset: Option<(usize, usize)>) {
+        self.inner.draw_cells(rect, offset);
+        self.renderer.borrow_mut().draw_cells(rect, offset);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+        let frame = self.renderer.borrow_mut().render();
+        if !frame.is_empty() {
+            // a cast which can not be written any more does not stop the game
+            let _ = self.writer.lock().unwrap().output(&String::from_utf8_lossy(&frame));
+        }
+    }
+
+    fn resize(&self, width: usize, height: usize) {
+        self.inner.resize(width, height);
+        self.renderer.borrow_mut().set_screen_size(width, height);
+        let _ = self.writer.lock().unwrap().resize(width, height);
+    }
+}
+
+/// wraps a backend so that whatever it draws is also written to the cast file
+pub struct AsciicastUIFuncs {
+    inner: Box<dyn UIFuncs>,
+    writer: SharedAsciicastWriter,
+    color_depth: ColorDepth,
+}
+
+impl AsciicastUIFuncs {
+    /// `stage_wxh` is the size of the terminal in the cast. auto colour depth records true colour
+    pub fn create(inner: Box<dyn UIFuncs>, cast_file: &str, stage_wxh: (usize, usize), color_depth: ColorDepth) -> Result<Self, String> {
+        let writer = AsciicastWriter::create(cast_file, stage_wxh.0, stage_wxh.1)?;
+        let color_depth = match color_depth {
+            ColorDepth::Auto => ColorDepth::TrueColor,
+            depth => depth,
+        };
+        Ok(AsciicastUIFuncs { inner, writer: Arc::new(Mutex::new(writer)), color_depth })
+    }
+}
+
+impl UIFuncs for AsciicastUIFuncs {
+    fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent> {
+        self.inner.start_keyevent_thread()
+    }
+
+    fn get_graphics(&self) -> Box<dyn UIGraphics> {
+        Box::new(AsciicastGraphics {
+            inner: self.inner.get_graphics(),
+            renderer: RefCell::new(Renderer::new(self.color_depth, SharedRenderStats::default())),
+            writer: self.writer.clone(),
+        })
+    }
+
+    fn render_stats(&self) -> Option<SharedRenderStats> {
+        self.inner.render_stats()
+    }
+
+    fn capture(&self) -> Option<SharedCapture> {
+        self.inner.capture()
+    }
+
+    fn resize_events(&mut self) -> Option<channel::Receiver<(usize, usize)>> {
+        self.inner.resize_events()
+    }
+}
+
+#[test]
+fn asciicast_works() {
+    use super::capture::CaptureUIFuncs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cast_file = dir.path().join("session.cast").to_string_lossy().to_string();
+    let funcs = AsciicastUIFuncs::create(Box::new(CaptureUIFuncs::new()), &cast_file, (8, 2), ColorDepth::Auto).unwrap();
+    let graphics = funcs.get_graphics();
+    graphics.draw_area(&RenderColor::Black, &RenderColor::White, &vec![vec!['a', 'b']], None);
+    graphics.flush();
+    // nothing changed. no event is written
+    graphics.flush();
+    graphics.draw_cells(&[vec![Cell { ch: 'X', fg: RenderColor::Byte(255, 0, 0), bg: RenderColor::White }]], Some((1, 1)));
+    graphics.flush();
+
+    // the inner backend still gets every frame
+    assert_eq!(funcs.capture().unwrap().lock().unwrap().frames.len(), 3);
+
+    let text = std::fs::read_to_string(&cast_file).unwrap();
+    let mut lines = text.lines();
+    let header: AsciicastHeader = serde_json::from_str(lines.next().unwrap()).unwrap();
+    assert_eq!((header.version, header.width, header.height), (2, 8, 2));
+    let events: Vec<(f64, String, String)> = lines.map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(events.len(), 3);
+    assert!(events.iter().all(|(_, kind, _)| kind == "o"));
+    assert!(events.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert!(events[0].2.contains("\x1b[2J"));
+    assert!(events[1].2.ends_with("ab"));
+    assert!(events[1].2.contains("\x1b[48;5;7m"));
+    assert!(events[2].2.contains("\x1b[2;1H"));
+    assert!(events[2].2.contains("\x1b[38;2;255;0;0m"));
+    assert!(events[2].2.ends_with('X'));
+
+    graphics.resize(4, 1);
+    let text = std::fs::read_to_string(&cast_file).unwrap();
+    let last: (f64, String, String) = serde_json::from_str(text.lines().nth(4).unwrap()).unwrap();
+    assert_eq!((last.1.as_str(), last.2.as_str()), ("r", "4x1"));
+}
@@ -1,84 +1,359 @@
-use std::process::Command;
+use std::process::{Child, Command};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use super::speechqueue::{SpeechPriority, SpeechQueue, UtteranceHandle, UtteranceState};
+use super::sound::{SoundLibrary, SoundPlayer};
 
 pub trait AudioFuncs: Send {
-    fn speech(&self, text: &str);
-    fn speech_lang(&self, text: &str, lang: &str);
-    //fn play(filepath: &str);
+    /// queue an utterance. `handle` tells when it has been spoken, dropped or cancelled
+    fn speak(&self, text: &str, lang: &str, priority: SpeechPriority, handle: UtteranceHandle);
+    /// cancel every queued and speaking utterance
+    fn stop_speech(&self);
+    fn speech(&self, text: &str) {
+        self.speech_lang(text, "en");
+    }
+    fn speech_lang(&self, text: &str, lang: &str) {
+        self.speak(text, lang, SpeechPriority::Syllable, UtteranceHandle::new());
+    }
+    /// play a sound of the library once. unknown names are silent
+    fn play(&self, name: &str);
+    fn play_loop(&self, name: &str);
+    fn stop(&self, name: &str);
+    fn stop_all(&self);
+    /// every call made so far. None when the backend does not record
+    fn audio_log(&self) -> Option<AudioLog> {
+        None
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AudioFuncsType {
     OsCommand,
+    /// no speech and no sound for headless runs
+    Null,
+    /// records the calls instead of making a sound
+    Recording,
+}
+
+/// a call to AudioFuncs
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioCall {
+    Speak { text: String, lang: String, priority: SpeechPriority },
+    StopSpeech,
+    Play(String),
+    PlayLoop(String),
+    Stop(String),
+    StopAll,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioEvent {
+    pub call: AudioCall,
+    /// msec since the backend was created
+    pub time: u128,
+}
+
+pub type AudioLog = Arc<Mutex<Vec<AudioEvent>>>;
+
+/// one way to speak a language with a local tts command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoiceCommand {
+    /// commands which have to be installed
+    pub requires: Vec<String>,
+    /// run by `sh -c`. the text is passed as $1
+    pub script: String,
+}
+
+impl VoiceCommand {
+    fn new(requires: &[&str], script: &str) -> Self {
+        VoiceCommand {
+            requires: requires.iter().map(|s| s.to_string()).collect(),
+            script: script.to_string(),
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.requires.iter().all(|program| is_installed(program))
+    }
+}
+
+/// tts commands used on linux
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpeechConfig {
+    /// candidates for each language. the first installed one is used
+    pub voices: BTreeMap<String, Vec<VoiceCommand>>,
+    /// tts processes running at the same time at most
+    pub max_concurrent: usize,
+}
+
+impl Default for SpeechConfig {
+    fn default() -> Self {
+        let mut voices = BTreeMap::new();
+        voices.insert(String::from("en"), vec![
+            VoiceCommand::new(&["espeak-ng"], r#"espeak-ng -v en "$1""#),
+            VoiceCommand::new(&["espeak"], r#"espeak -v en "$1""#),
+            VoiceCommand::new(&["festival"], r#"echo "$1" | festival --tts"#),
+        ]);
+        voices.insert(String::from("ja"), vec![
+            VoiceCommand::new(&["open_jtalk", "aplay"], concat!(
+                r#"wav=$(mktemp --suffix=.wav) && echo "$1" | open_jtalk -x /var/lib/mecab/dic/open-jtalk/naist-jdic "#,
+                r#"-m /usr/share/hts-voice/nitech-jp-atr503-m001/nitech_jp_atr503_m001.htsvoice -ow "$wav" && aplay -q "$wav"; rm -f "$wav""#)),
+            VoiceCommand::new(&["espeak-ng"], r#"espeak-ng -v ja "$1""#),
+        ]);
+        SpeechConfig {
+            voices,
+            max_concurrent: 1,
+        }
+    }
 }
 
-pub fn generate_audiofuncs(audiofuncs_type: AudioFuncsType) -> Box<dyn AudioFuncs> {
+impl SpeechConfig {
+    /// the voice used for each language. languages without an installed command are silent
+    pub fn detect_voices(&self) -> BTreeMap<String, Option<VoiceCommand>> {
+        self.voices.iter()
+            .map(|(lang, candidates)| (lang.to_string(), candidates.iter().find(|voice| voice.is_installed()).cloned()))
+            .collect()
+    }
+}
+
+/// commands which play a wav file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoundConfig {
+    /// candidates run by `sh -c` with the wav file as $1. the first installed one is used
+    pub players: Vec<VoiceCommand>,
+}
+
+impl Default for SoundConfig {
+    fn default() -> Self {
+        SoundConfig {
+            players: vec![
+                VoiceCommand::new(&["aplay"], r#"aplay -q "$1""#),
+                VoiceCommand::new(&["paplay"], r#"paplay "$1""#),
+                VoiceCommand::new(&["afplay"], r#"afplay "$1""#),
+            ],
+        }
+    }
+}
+
+impl SoundConfig {
+    pub fn detect_player(&self) -> Option<VoiceCommand> {
+        self.players.iter().find(|player| player.is_installed()).cloned()
+    }
+}
+
+/// whether the program is found in $PATH
+pub fn is_installed(program: &str) -> bool {
+    match std::env::var_os("PATH") {
+        Some(paths) => std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()),
+        None => false,
+    }
+}
+
+pub fn generate_audiofuncs(audiofuncs_type: AudioFuncsType, speech_config: &SpeechConfig, sound_config: &SoundConfig, sounds: SoundLibrary) -> Box<dyn AudioFuncs> {
     match audiofuncs_type {
         AudioFuncsType::OsCommand => {
-            Box::new(OsCommandAudioFuncs::new())
-        }
+            Box::new(OsCommandAudioFuncs::new(speech_config, sound_config, sounds))
+        },
+        AudioFuncsType::Null => {
+            Box::new(NullAudioFuncs{})
+        },
+        AudioFuncsType::Recording => {
+            Box::new(RecordingAudioFuncs::new())
+        },
     }
 }
 
 pub struct OsCommandAudioFuncs {
-
+    queue: SpeechQueue,
+    player: SoundPlayer,
+    /// synthesised effects live here while the game runs
+    _synth_dir: Option<tempfile::TempDir>,
 }
 
 impl OsCommandAudioFuncs {
-    pub fn new() -> Self {
-        OsCommandAudioFuncs{}
+    /// effects which sounds.json does not name are synthesised
+    pub fn new(speech_config: &SpeechConfig, sound_config: &SoundConfig, mut sounds: SoundLibrary) -> Self {
+        let player = sound_config.detect_player();
+        let synth_dir = match player {
+            Some(_) => tempfile::tempdir().ok().filter(|dir| sounds.synthesize_missing(dir.path()).is_ok()),
+            None => None,
+        };
+        // voice of each language. not used on macos where `say` is always there
+        let voices: BTreeMap<String, VoiceCommand> = speech_config.detect_voices().into_iter()
+            .filter_map(|(lang, voice)| voice.map(|voice| (lang, voice)))
+            .collect();
+        OsCommandAudioFuncs{
+            queue: SpeechQueue::new(speech_config.max_concurrent, Box::new(move |text: &str, lang: &str| spawn_voice(&voices, text, lang))),
+            player: SoundPlayer::new(sounds, player.map(|player| player.script)),
+            _synth_dir: synth_dir,
+        }
     }
 }
 
+#[cfg(target_os = "macos")]
+fn spawn_voice(_voices: &BTreeMap<String, VoiceCommand>, text: &str, lang: &str) -> Option<Child> {
+    match lang {
+        "en" => Command::new("say").args([text]).spawn().ok(),
+        "ja" => Command::new("say").args(["-v", "Otoya", text]).spawn().ok(),
+        _ => None,
+    }
+}
+
+/// languages without an installed voice are silent
+#[cfg(target_os = "linux")]
+fn spawn_voice(voices: &BTreeMap<String, VoiceCommand>, text: &str, lang: &str) -> Option<Child> {
+    let voice = voices.get(lang)?;
+    Command::new("sh")
+        .args(["-c", &voice.script, "aquatyping", text])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .ok()
+}
+
+#[cfg(target_os = "windows")]
+fn spawn_voice(_voices: &BTreeMap<String, VoiceCommand>, _text: &str, _lang: &str) -> Option<Child> {
+    None
+}
+
 impl AudioFuncs for OsCommandAudioFuncs {
-    #[cfg(target_os = "macos")]
-    fn speech(&self, text: &str) {
-        let t = text.to_string();
-        std::thread::spawn(move || {
-            let mut c = Command::new("say")
-            .args(&[&t])
-            .spawn()
-            .expect("fail to execute say command");
-            c.wait();    
-        });
-    }
-    #[cfg(target_os = "macos")]
-    fn speech_lang(&self, text: &str, lang: &str) {
-        let text = text.to_string();
-        match lang {
-            "en" => {
-                std::thread::spawn(move || {
-                    let mut c = Command::new("say")
-                        .args(&[&text])
-                        .spawn()
-                        .expect("fail to execute say command");//.kill();
-                    c.wait();
-                });
-            },
-            "ja" => {
-                std::thread::spawn(move || {
-                    let mut c = Command::new("say")
-                        .args(&["-v","Otoya",&text])
-                        .spawn()
-                        .expect("fail to execute say -v command");//.kill();
-                    c.wait();
-                });
-            },
-            _ => {
-            }
+    fn speak(&self, text: &str, lang: &str, priority: SpeechPriority, handle: UtteranceHandle) {
+        self.queue.push(text, lang, priority, handle);
+    }
+
+    fn stop_speech(&self) {
+        self.queue.clear();
+    }
+
+    fn play(&self, name: &str) {
+        self.player.play(name);
+    }
+
+    fn play_loop(&self, name: &str) {
+        self.player.play_loop(name);
+    }
+
+    fn stop(&self, name: &str) {
+        self.player.stop(name);
+    }
+
+    fn stop_all(&self) {
+        self.player.stop_all();
+    }
+}
+
+pub struct NullAudioFuncs {
+}
+
+impl AudioFuncs for NullAudioFuncs {
+    /// nothing is spoken so nobody has to wait for it
+    fn speak(&self, _text: &str, _lang: &str, _priority: SpeechPriority, handle: UtteranceHandle) {
+        handle.set_state(UtteranceState::Done);
+    }
+    fn stop_speech(&self) {}
+    fn play(&self, _name: &str) {}
+    fn play_loop(&self, _name: &str) {}
+    fn stop(&self, _name: &str) {}
+    fn stop_all(&self) {}
+}
+
+pub struct RecordingAudioFuncs {
+    log: AudioLog,
+    start_time: Instant,
+}
+
+impl Default for RecordingAudioFuncs {
+    fn default() -> Self {
+        RecordingAudioFuncs::new()
+    }
+}
+
+impl RecordingAudioFuncs {
+    pub fn new() -> Self {
+        RecordingAudioFuncs {
+            log: Arc::new(Mutex::new(Vec::new())),
+            start_time: Instant::now(),
         }
     }
 
+    fn record(&self, call: AudioCall) {
+        let time = self.start_time.elapsed().as_millis();
+        self.log.lock().unwrap().push(AudioEvent { call, time });
+    }
+}
 
-    #[cfg(target_os = "windows")]
-    fn speech(&self, text: &str) {
+impl AudioFuncs for RecordingAudioFuncs {
+    /// utterances finish at once so that nobody waits for them
+    fn speak(&self, text: &str, lang: &str, priority: SpeechPriority, handle: UtteranceHandle) {
+        self.record(AudioCall::Speak { text: text.to_string(), lang: lang.to_string(), priority });
+        handle.set_state(UtteranceState::Done);
+    }
+    fn stop_speech(&self) {
+        self.record(AudioCall::StopSpeech);
+    }
+    fn play(&self, name: &str) {
+        self.record(AudioCall::Play(name.to_string()));
+    }
+    fn play_loop(&self, name: &str) {
+        self.record(AudioCall::PlayLoop(name.to_string()));
+    }
+    fn stop(&self, name: &str) {
+        self.record(AudioCall::Stop(name.to_string()));
+    }
+    fn stop_all(&self) {
+        self.record(AudioCall::StopAll);
+    }
+    fn audio_log(&self) -> Option<AudioLog> {
+        Some(self.log.clone())
     }
 }
 
 #[test]
 fn speech_works() {
-    let funcs = generate_audiofuncs(AudioFuncsType::OsCommand);
-    println!("call");
+    let funcs = generate_audiofuncs(AudioFuncsType::Recording, &SpeechConfig::default(), &SoundConfig::default(), SoundLibrary::new());
+    let log = funcs.audio_log().unwrap();
     funcs.speech("hello megamouse shark");
-    println!("end");
-    funcs.speech("bye");
-}
\ No newline at end of file
+    funcs.speech_lang("メガマウスザメ", "ja");
+    let handle = UtteranceHandle::new();
+    funcs.speak("I caught megamouse shark!", "en", SpeechPriority::Announcement, handle.clone());
+    assert!(handle.is_finished());
+    funcs.play(super::sound::CLICK);
+
+    let log = log.lock().unwrap();
+    let calls: Vec<&AudioCall> = log.iter().map(|event| &event.call).collect();
+    assert_eq!(calls, [
+        &AudioCall::Speak { text: String::from("hello megamouse shark"), lang: String::from("en"), priority: SpeechPriority::Syllable },
+        &AudioCall::Speak { text: String::from("メガマウスザメ"), lang: String::from("ja"), priority: SpeechPriority::Syllable },
+        &AudioCall::Speak { text: String::from("I caught megamouse shark!"), lang: String::from("en"), priority: SpeechPriority::Announcement },
+        &AudioCall::Play(String::from("click")),
+    ]);
+    assert!(log.windows(2).all(|events| events[0].time <= events[1].time));
+}
+
+#[test]
+fn detect_voices_works() {
+    let mut config = SpeechConfig::default();
+    config.voices.insert(String::from("xx"), vec![
+        VoiceCommand::new(&["aquatyping-no-such-command"], "true"),
+        VoiceCommand::new(&["sh"], r#"echo "$1""#),
+    ]);
+    config.voices.insert(String::from("yy"), vec![VoiceCommand::new(&["aquatyping-no-such-command"], "true")]);
+    let voices = config.detect_voices();
+    assert_eq!(voices["xx"].as_ref().map(|voice| voice.script.as_str()), Some(r#"echo "$1""#));
+    assert_eq!(voices["yy"], None);
+}
+
+#[test]
+fn null_audiofuncs_works() {
+    let funcs = generate_audiofuncs(AudioFuncsType::Null, &SpeechConfig::default(), &SoundConfig::default(), SoundLibrary::new());
+    let handle = UtteranceHandle::new();
+    funcs.speak("I caught megamouse shark!", "en", SpeechPriority::Announcement, handle.clone());
+    assert_eq!(handle.state(), UtteranceState::Done);
+    funcs.play_loop(super::sound::AMBIENT);
+    funcs.stop_all();
+}
@@ -1,6 +1,6 @@
 
-use rand::{thread_rng, Rng};
-use rand::rngs::ThreadRng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use std::collections::HashSet;
 use image2ascii::{Char2DArray};
 
@@ -14,22 +14,22 @@ pub enum BackgroundType {
     NONE,
 }
 
-pub fn generate_background_randomly() -> Box<dyn Background> {
-    let mut rng = thread_rng();
+pub fn generate_background_randomly(rng: &mut StdRng) -> Box<dyn Background> {
     let index: u32 = rng.gen_range(0, 2);
     let bgtype = match index {
         // 0 => BackgroundType::RANDOM,
         // 1 => BackgroundType::NONE,
         _ => BackgroundType::RANDOM,
     };
-    generate_background(bgtype)
+    generate_background(bgtype, rng)
 }
 
-pub fn generate_background(background_type: BackgroundType) -> Box<dyn Background> {
+/// a random background draws from its own rng seeded by `rng` so that the number of frames drawn does not change the other random values
+pub fn generate_background(background_type: BackgroundType, rng: &mut StdRng) -> Box<dyn Background> {
     match background_type {
         BackgroundType::RANDOM => {
             let chars = vec![' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', '`', '.'];
-            Box::new(RandomBackground::new(chars))
+            Box::new(RandomBackground::new(chars, StdRng::seed_from_u64(rng.gen())))
         },
         BackgroundType::NONE => {
             Box::new(NoneBackground{})
@@ -38,14 +38,14 @@ pub fn generate_background(background_type: BackgroundType) -> Box<dyn Backgroun
 }
 
 struct RandomBackground {
-    rng: ThreadRng,
+    rng: StdRng,
     chars: Vec<char>,
 }
 
 impl RandomBackground {
-    pub fn new(chars: Vec<char>) -> Self{
+    pub fn new(chars: Vec<char>, rng: StdRng) -> Self{
         RandomBackground {
-            rng: thread_rng(),
+            rng,
             chars: chars,
         }
     }
@@ -71,7 +71,7 @@ impl Background for NoneBackground {
 
 #[test]
 fn randombackground_works() {
-    let mut bg = generate_background(BackgroundType::RANDOM);
+    let mut bg = generate_background(BackgroundType::RANDOM, &mut StdRng::seed_from_u64(0));
     let mut stage = Char2DArray::new(20, 10);
     stage.overwrite_char_all('@');
     let mut chars = HashSet::new();
@@ -89,7 +89,7 @@ fn randombackground_works() {
 
 #[test]
 fn nonebackground_works() {
-    let mut bg = generate_background(BackgroundType::NONE);
+    let mut bg = generate_background(BackgroundType::NONE, &mut StdRng::seed_from_u64(0));
     let mut stage = Char2DArray::new(20, 10);
     stage.overwrite_char_all('@');
     bg.update(&mut stage);
@@ -1,7 +1,10 @@
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use rand::rngs::StdRng;
 
 pub trait Behavior {
     fn update(&mut self) -> (i32, i32);
+    /// the stage or the art has a new size. the movement goes on from where it is
+    fn resize(&mut self, _stage_wxh: (usize, usize), _aa_wxh: (usize, usize)) {}
 }
 
 #[derive(Debug)]
@@ -10,17 +13,18 @@ pub enum BehaviorType {
     UPANDDOWN,
 }
 
-pub fn generate_behavior_randomly(framerate: u32, stage_wxh: (usize, usize), aa_wxh: (usize, usize)) -> Box<dyn Behavior> {
-    generate_behavior(BehaviorType::UPANDDOWN, framerate, stage_wxh, aa_wxh)
+pub fn generate_behavior_randomly(framerate: u32, stage_wxh: (usize, usize), aa_wxh: (usize, usize), rng: &mut StdRng) -> Box<dyn Behavior> {
+    generate_behavior(BehaviorType::UPANDDOWN, framerate, stage_wxh, aa_wxh, rng)
 }
 
-pub fn generate_behavior(behavior_type: BehaviorType, framerate: u32, stage_wxh: (usize, usize), aa_wxh: (usize, usize)) -> Box<dyn Behavior> {
+/// `rng` picks the periods and amplitudes of the moving behaviors
+pub fn generate_behavior(behavior_type: BehaviorType, framerate: u32, stage_wxh: (usize, usize), aa_wxh: (usize, usize), rng: &mut StdRng) -> Box<dyn Behavior> {
     match behavior_type {
         BehaviorType::NO => {
             Box::new(NoBehavior::new())
         },
         BehaviorType::UPANDDOWN => {
-            Box::new(UpAndDownBehavior::new(framerate, stage_wxh, aa_wxh))
+            Box::new(UpAndDownBehavior::new(framerate, stage_wxh, aa_wxh, rng))
         }
         _ => panic!("not found effector: {:?}", behavior_type),
     }
@@ -56,8 +60,7 @@ struct UpAndDownBehavior {
 
 impl UpAndDownBehavior {
     // ランダムで振動数と振幅
-    fn new(framerate: u32, stage_wxh: (usize, usize), aa_wxh: (usize, usize)) -> UpAndDownBehavior{
-        let mut rng = thread_rng();
+    fn new(framerate: u32, stage_wxh: (usize, usize), aa_wxh: (usize, usize), rng: &mut StdRng) -> UpAndDownBehavior{
         let h_period: f32 =  rng.gen_range(2.0, 7.0); // 1.0-4.0
         let v_period: f32 =  rng.gen_range(2.0, 7.0); // 1.0-4.0
         let h_amp: f32 = rng.gen_range(0.5, 0.6);
@@ -80,25 +83,30 @@ impl Behavior for UpAndDownBehavior {
         let t = self.current_frame as f32 / self.framerate as f32;
         
         // horizontal
-        let h_amp = (self.stage_wxh.0 - self.aa_wxh.0) as f32 * self.h_amp;
+        let h_amp = self.stage_wxh.0.saturating_sub(self.aa_wxh.0) as f32 * self.h_amp;
         let h_w = 2.0 * 3.14 * (1.0 / self.h_period);
         let x = - (h_w * t).sin() * h_amp;
         
         // vertical
-        let v_amp = (self.stage_wxh.1 - self.aa_wxh.1) as f32 * self.v_amp;
+        let v_amp = self.stage_wxh.1.saturating_sub(self.aa_wxh.1) as f32 * self.v_amp;
         let v_w = 2.0 * 3.14 * (1.0 / self.v_period);
         let y = - (v_w * t).sin() * v_amp;
 
         self.current_frame += 1;
         (x as i32, y as i32)
     }
+
+    fn resize(&mut self, stage_wxh: (usize, usize), aa_wxh: (usize, usize)) {
+        self.stage_wxh = stage_wxh;
+        self.aa_wxh = aa_wxh;
+    }
 }
 
 #[test]
 fn upanddownbehavior_works() {
     let stage_wxh = (600, 120);
     let aa_wxh = (300, 80);
-    let mut behavior = generate_behavior(BehaviorType::UPANDDOWN, 10, stage_wxh, aa_wxh);
+    let mut behavior = generate_behavior(BehaviorType::UPANDDOWN, 10, stage_wxh, aa_wxh, &mut rand::SeedableRng::seed_from_u64(0));
     let pos = behavior.update();
     assert_eq!(pos, (0, 0));
     let pos = behavior.update();
@@ -0,0 +1,379 @@
+//! headless ui backend which keeps every frame in memory so that tests can check what was drawn
+
+use crossbeam_channel as channel;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use super::cellart::Cell;
+use super::renderer::SharedRenderStats;
+use super::uifuncs::{DebugUIFuncs, RenderColor, UIFuncs, UIGraphics, UIKeyEvent};
+
+/// directory of the golden files compared by `check_snapshot`
+pub const SNAPSHOT_DIR: &str = "./snapshots/";
+
+/// the whole screen at a flush
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    pub cells: Vec<Vec<Cell>>,
+}
+
+impl Frame {
+    /// characters of the rows in `rows`. trailing spaces are dropped so that golden files stay small
+    pub fn text_of_rows(&self, rows: std::ops::Range<usize>) -> String {
+        self.cells.iter().skip(rows.start).take(rows.len())
+            .map(|line| line.iter().map(|cell| cell.ch).collect::<String>().trim_end().to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn text(&self) -> String {
+        self.text_of_rows(0..self.cells.len())
+    }
+}
+
+/// what has been drawn so far and the frames flushed
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    pub screen: Frame,
+    pub frames: Vec<Frame>,
+}
+
+impl Capture {
+    /// the oldest frames are dropped beyond this
+    pub const MAX_FRAMES: usize = 10000;
+
+    pub fn last_frame(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    /// put the cells on the screen. it grows to hold them
+    pub fn draw(&mut self, area: &[Vec<Cell>], offset: Option<(usize, usize)>) {
+        let (offset_x, offset_y) = offset.unwrap_or((0, 0));
+        let cells = &mut self.screen.cells;
+        if cells.len() < offset_y + area.len() {
+            cells.resize(offset_y + area.len(), Vec::new());
+        }
+        for (y, line) in area.iter().enumerate() {
+            let row = &mut cells[offset_y + y];
+            if row.len() < offset_x + line.len() {
+                row.resize(offset_x + line.len(), Cell::BLANK);
+            }
+            row[offset_x..offset_x + line.len()].copy_from_slice(line);
+        }
+    }
+
+    /// cells beyond the new size are dropped
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let cells = &mut self.screen.cells;
+        cells.truncate(height);
+        for row in cells.iter_mut() {
+            row.truncate(width);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.frames.len() >= Capture::MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(self.screen.clone());
+    }
+}
+
+pub type SharedCapture = Arc<Mutex<Capture>>;
+
+/// poll the capture until `done` holds for it and return the last frame then. the server thread draws on its own,
+/// so this is how a test knows a command has been drawn
+pub fn wait_for_capture(capture: &SharedCapture, timeout: Duration, done: impl Fn(&Capture) -> bool) -> Result<Frame, String> {
+    let start = Instant::now();
+    loop {
+        {
+            let capture = capture.lock().unwrap();
+            if done(&capture) {
+                return Ok(capture.last_frame().cloned().unwrap_or_default());
+            }
+            if start.elapsed() > timeout {
+                return Err(format!("the frame did not come in {:?}. the last frame was:\n{}",
+                    timeout, capture.last_frame().map(|frame| frame.text()).unwrap_or_default()));
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+pub struct CaptureGraphics {
+    capture: SharedCapture,
+}
+
+impl CaptureGraphics {
+    pub fn new(capture: SharedCapture) -> Self {
+        CaptureGraphics { capture }
+    }
+}
+
+impl UIGraphics for CaptureGraphics {
+    fn draw_area(&self, color: &RenderColor, bgcolor: &RenderColor, rect: &Vec<Vec<char>>, offset: Option<(usize, usize)>) {
+        let cells: Vec<Vec<Cell>> = rect.iter()
+            .map(|line| line.iter().map(|ch| Cell { ch: *ch, fg: *color, bg: *bgcolor }).collect())
+            .collect();
+        self.capture.lock().unwrap().draw(&cells, offset);
+    }
+
+    fn draw_cells(&self, rect: &[Vec<Cell>], offset: Option<(usize, usize)>) {
+        self.capture.lock().unwrap().draw(rect, offset);
+    }
+
+    fn flush(&self) {
+        self.capture.lock().unwrap().flush();
+    }
+
+    fn resize(&self, width: usize, height: usize) {
+        self.capture.lock().unwrap().resize(width, height);
+    }
+}
+
+/// keys come from a script like DebugUIFuncs, or from a channel, and frames go to the shared capture
+pub struct CaptureUIFuncs {
+    capture: SharedCapture,
+    keys: DebugUIFuncs,
+    key_rx: Option<channel::Receiver<UIKeyEvent>>,
+    resize_rx: Option<channel::Receiver<(usize, usize)>>,
+}
+
+impl CaptureUIFuncs {
+    pub fn new() -> Self {
+        CaptureUIFuncs::scripted(Vec::new())
+    }
+
+    /// keys sent after waiting the msec of each one. end the script with ESC to leave the game
+    pub fn scripted(script: Vec<(u64, UIKeyEvent)>) -> Self {
+        CaptureUIFuncs {
+            capture: SharedCapture::default(),
+            keys: DebugUIFuncs::scripted(script),
+            key_rx: None,
+            resize_rx: None,
+        }
+    }
+
+    /// keys are the ones sent to `key_rx`, so that a test can send the next key once it has seen a frame
+    pub fn keyed(key_rx: channel::Receiver<UIKeyEvent>) -> Self {
+        CaptureUIFuncs {
+            key_rx: Some(key_rx),
+            ..CaptureUIFuncs::new()
+        }
+    }
+
+    /// the screen sizes sent to `resize_rx` come as resize events like those of a terminal
+    pub fn with_resizes(self, resize_rx: channel::Receiver<(usize, usize)>) -> Self {
+        CaptureUIFuncs {
+            resize_rx: Some(resize_rx),
+            ..self
+        }
+    }
+}
+
+impl Default for CaptureUIFuncs {
+    fn default() -> Self {
+        CaptureUIFuncs::new()
+    }
+}
+
+impl UIFuncs for CaptureUIFuncs {
+    fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent> {
+        match self.key_rx.take() {
+            Some(key_rx) => key_rx,
+            None => self.keys.start_keyevent_thread(),
+        }
+    }
+
+    fn get_graphics(&self) -> Box<dyn UIGraphics> {
+        Box::new(CaptureGraphics::new(self.capture.clone()))
+    }
+
+    fn render_stats(&self) -> Option<SharedRenderStats> {
+        None
+    }
+
+    fn capture(&self) -> Option<SharedCapture> {
+        Some(self.capture.clone())
+    }
+
+    fn resize_events(&mut self) -> Option<channel::Receiver<(usize, usize)>> {
+        self.resize_rx.take()
+    }
+}
+
+/// compare the text with SNAPSHOT_DIR/NAME.txt. with UPDATE_SNAPSHOTS=1 in the environment the file is written instead
+pub fn check_snapshot(name: &str, text: &str) -> Result<(), String> {
+    let update = std::env::var("UPDATE_SNAPSHOTS").map(|value| value == "1").unwrap_or(false);
+    compare_snapshot(&Path::new(SNAPSHOT_DIR).join(format!("{}.txt", name)), text, update)
+}
+
+/// the first line which differs is reported
+pub fn compare_snapshot(path: &Path, text: &str, update: bool) -> Result<(), String> {
+    if update {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("can not create snapshot dir {}: {}", dir.display(), e))?;
+        }
+        return fs::write(path, format!("{}\n", text)).map_err(|e| format!("can not write snapshot {}: {}", path.display(), e));
+    }
+    let golden = fs::read_to_string(path)
+        .map_err(|e| format!("can not read snapshot {}: {} (run with UPDATE_SNAPSHOTS=1 to create it)", path.display(), e))?;
+    let golden = golden.strip_suffix('\n').unwrap_or(&golden);
+    if golden == text {
+        return Ok(());
+    }
+    let line = golden.lines().zip(text.lines()).position(|(a, b)| a != b)
+        .unwrap_or_else(|| golden.lines().count().min(text.lines().count()));
+    Err(format!("snapshot {} differs from line {}:\nexpected: {}\nactual:   {}",
+        path.display(), line + 1, golden.lines().nth(line).unwrap_or(""), text.lines().nth(line).unwrap_or("")))
+}
+
+/// long enough for a loaded machine. a test which passes does not wait for it
+#[cfg(test)]
+const WAIT_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[cfg(test)]
+fn snapshot_config() -> super::config::GameConfig {
+    super::config::GameConfig { stage_wxh: (120, 36), aa_width: 60, ..super::config::GameConfig::default() }
+}
+
+#[test]
+fn capture_works() {
+    let funcs = CaptureUIFuncs::new();
+    let capture = funcs.capture().unwrap();
+    let graphics = funcs.get_graphics();
+    graphics.draw_area(&RenderColor::Black, &RenderColor::White, &vec![vec!['a', 'b'], vec!['c', 'd']], None);
+    graphics.flush();
+    graphics.draw_cells(&[vec![Cell { ch: 'X', fg: RenderColor::Red, bg: RenderColor::White }]], Some((3, 1)));
+    graphics.flush();
+
+    let capture = capture.lock().unwrap();
+    assert_eq!(capture.frames.len(), 2);
+    assert_eq!(capture.frames[0].text(), "ab\ncd");
+    let last = capture.last_frame().unwrap();
+    assert_eq!(last.text(), "ab\ncd X");
+    assert_eq!(last.cells[1][3].fg, RenderColor::Red);
+    assert_eq!(last.cells[1][2], Cell::BLANK);
+}
+
+#[test]
+fn compare_snapshot_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("frame.txt");
+    assert!(compare_snapshot(&path, "ab\ncd", false).unwrap_err().contains("UPDATE_SNAPSHOTS"));
+    compare_snapshot(&path, "ab\ncd", true).unwrap();
+    compare_snapshot(&path, "ab\ncd", false).unwrap();
+    let err = compare_snapshot(&path, "ab\nce", false).unwrap_err();
+    assert!(err.contains("line 2"));
+    assert!(compare_snapshot(&path, "ab", false).is_err());
+}
+
+#[test]
+fn title_snapshot_works() {
+    use super::audiofuncs::{AudioFuncsType, generate_audiofuncs};
+    let dir = tempfile::tempdir().unwrap();
+    let config = super::config::GameConfig {
+        history_file: dir.path().join("history.jsonl").to_string_lossy().to_string(),
+        collection_dir: dir.path().join("collection").to_string_lossy().to_string(),
+        ..snapshot_config()
+    };
+    let (key_tx, key_rx) = channel::unbounded::<UIKeyEvent>();
+    let funcs = CaptureUIFuncs::keyed(key_rx);
+    let capture = funcs.capture().unwrap();
+    let audio = generate_audiofuncs(AudioFuncsType::Null, &Default::default(), &Default::default(), Default::default());
+    let game = thread::spawn(move || super::controller::control(Box::new(funcs), audio, &config));
+
+    // the subtelop is the last thing the title shows. the title art is in the frame drawn after it
+    let rows = super::stage::Stage::new(snapshot_config().stage_config()).subtelop_offset()..snapshot_config().stage_wxh.1;
+    let frame = wait_for_capture(&capture, WAIT_TIMEOUT, |capture| {
+        capture.frames.iter().position(|frame| !frame.text_of_rows(rows.clone()).trim().is_empty())
+            .is_some_and(|i| i + 1 < capture.frames.len())
+    }).unwrap();
+    key_tx.send(UIKeyEvent::ESC).unwrap();
+    game.join().unwrap().unwrap();
+
+    let text = frame.text();
+    check_snapshot("title", &text).unwrap();
+}
+
+#[test]
+fn telop_snapshot_works() {
+    use super::audiofuncs::{AudioFuncsType, generate_audiofuncs};
+    use super::command::start_command_server;
+    use super::stage::Stage;
+    let config = snapshot_config();
+    let funcs = CaptureUIFuncs::new();
+    let capture = funcs.capture().unwrap();
+    let audio = generate_audiofuncs(AudioFuncsType::Null, &Default::default(), &Default::default(), Default::default());
+    let client = start_command_server(funcs.get_graphics(), config.stage_config(), config.color, audio);
+    let stage = Stage::new(config.stage_config());
+    let rows = stage.telop_offset()..stage.subtelop_offset();
+
+    let mut progress: Vec<String> = Vec::new();
+    for pos in [0, 4, 9, 15].iter() {
+        client.telop("MEGAMOUSE SHARK", *pos).unwrap();
+        // each position marks more characters, so the telop differs from the one before once it is drawn
+        let before = progress.last().cloned().unwrap_or_default();
+        let frame = wait_for_capture(&capture, WAIT_TIMEOUT, |capture| {
+            capture.last_frame().is_some_and(|frame| frame.text_of_rows(rows.clone()) != before)
+        }).unwrap();
+        progress.push(frame.text_of_rows(rows.clone()));
+    }
+    client.shutdown().unwrap();
+    check_snapshot("telop_progress", &progress.join("\n----\n")).unwrap();
+}
+
+#[test]
+fn appear_snapshot_works() {
+    use super::stage::Stage;
+    let config = super::stage::StageConfig { seed: Some(7), ..snapshot_config().stage_config() };
+    let capture = SharedCapture::default();
+    let graphics = CaptureGraphics::new(capture.clone());
+    let mut stage = Stage::new(config);
+    stage.appear("./drawings/001_megamouse_shark.png");
+    for _ in 0..12 {
+        graphics.draw_cells(&stage.update_pict_cells(RenderColor::Black, RenderColor::White).buffer, None);
+        graphics.flush();
+    }
+    let capture = capture.lock().unwrap();
+    let frames: Vec<String> = [0, 4, 8, 11].iter().map(|i| capture.frames[*i].text()).collect();
+    check_snapshot("appear", &frames.join("\n----\n")).unwrap();
+}
+
+#[test]
+fn resize_works() {
+    use super::audiofuncs::{AudioFuncsType, generate_audiofuncs};
+    use super::command::start_command_server;
+    let config = snapshot_config();
+    let funcs = CaptureUIFuncs::new();
+    let capture = funcs.capture().unwrap();
+    let audio = generate_audiofuncs(AudioFuncsType::Null, &Default::default(), &Default::default(), Default::default());
+    let client = start_command_server(funcs.get_graphics(), config.stage_config(), config.color, audio);
+    client.appear("./drawings/001_megamouse_shark.png", "MEGAMOUSE SHARK").unwrap();
+    client.telop("SHARK", 2).unwrap();
+    // the creature has been drawn once. commands run in order, so the telop is below it
+    wait_for_capture(&capture, WAIT_TIMEOUT, |capture| {
+        capture.last_frame().is_some_and(|frame| frame.cells.first().is_some_and(|row| !row.is_empty()))
+    }).unwrap();
+
+    client.resize(80, 24).unwrap();
+    let frame = wait_for_capture(&capture, WAIT_TIMEOUT, |capture| capture.last_frame().is_some_and(|frame| frame.cells.len() == 24)).unwrap();
+    assert!(frame.cells.iter().all(|row| row.len() == 80));
+    // the telop is drawn again at the new size
+    assert!(frame.text_of_rows(16..20).contains('@'));
+
+    client.resize(30, 8).unwrap();
+    wait_for_capture(&capture, WAIT_TIMEOUT, |capture| capture.last_frame().is_some_and(|frame| frame.cells.len() == 8)).unwrap();
+    client.telop("SHARK", 3).unwrap();
+    // nothing else is flushed while the screen is too small. the notice of the second resize comes after the telop
+    let frames = capture.lock().unwrap().frames.len();
+    client.resize(30, 8).unwrap();
+    let frame = wait_for_capture(&capture, WAIT_TIMEOUT, |capture| capture.frames.len() > frames).unwrap();
+    assert_eq!(frame.cells.len(), 8);
+    // the telop waits until the screen is big enough
+    assert!(frame.text().contains("terminal too small"));
+    assert!(frame.text().contains("30x8 < 40x12"));
+    client.shutdown().unwrap();
+}
@@ -0,0 +1,118 @@
+//! characters which carry their own colours
+
+use image::imageops::FilterType;
+use image2ascii::{Char2DArray, CharPosition};
+use std::cmp;
+use super::uifuncs::RenderColor;
+
+/// one character on the terminal
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: RenderColor,
+    pub bg: RenderColor,
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell {
+        ch: ' ',
+        fg: RenderColor::Default,
+        bg: RenderColor::Default,
+    };
+}
+
+/// Char2DArray whose cells have colours
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell2DArray {
+    pub buffer: Vec<Vec<Cell>>,
+}
+
+impl Cell2DArray {
+    pub fn new(width: usize, height: usize) -> Cell2DArray {
+        Cell2DArray {
+            buffer: vec![vec![Cell::BLANK; width]; height],
+        }
+    }
+
+    /// every character gets the same colours
+    pub fn from_chars(chars: &Char2DArray, fg: RenderColor, bg: RenderColor) -> Cell2DArray {
+        Cell2DArray {
+            buffer: chars.buffer.iter()
+                .map(|line| line.iter().map(|ch| Cell { ch: *ch, fg, bg }).collect())
+                .collect(),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn width(&self) -> usize {
+        self.buffer.first().map_or(0, |line| line.len())
+    }
+
+    /// paint the foreground of the characters of `rect` placed at `position` like `Char2DArray::overwrite_rect`.
+    /// characters equal to `transparent` keep their colour
+    pub fn overwrite_fg(&mut self, rect: &Char2DArray, colors: &[Vec<RenderColor>], position: CharPosition, transparent: Option<char>) {
+        let y_start = cmp::max(0, position.y);
+        let y_end = cmp::min(self.height() as i32, position.y + rect.height() as i32);
+        let x_start = cmp::max(0, position.x);
+        let x_end = cmp::min(self.width() as i32, position.x + rect.width() as i32);
+
+        for y in y_start..y_end {
+            for x in x_start..x_end {
+                let (rx, ry) = ((x - position.x) as usize, (y - position.y) as usize);
+                if Some(rect.buffer[ry][rx]) == transparent {
+                    continue;
+                }
+                if let Some(color) = colors.get(ry).and_then(|line| line.get(rx)) {
+                    self.buffer[y as usize][x as usize].fg = *color;
+                }
+            }
+        }
+    }
+
+    /// same as `overwrite_fg` but `position` is relative to the center like `Char2DArray::overwrite_rect_center`
+    pub fn overwrite_fg_center(&mut self, rect: &Char2DArray, colors: &[Vec<RenderColor>], position: CharPosition, transparent: Option<char>) {
+        let center_x = (self.width() / 2) as i32 - (rect.width() / 2) as i32;
+        let center_y = (self.height() / 2) as i32 - (rect.height() / 2) as i32;
+        let offset_posi = CharPosition {
+            x: position.x + center_x,
+            y: position.y + center_y,
+        };
+        self.overwrite_fg(rect, colors, offset_posi, transparent)
+    }
+}
+
+/// colour of the image sampled for each cell of a width x height ascii art
+pub fn image2colors(image_file: &str, width: usize, height: usize) -> Result<Vec<Vec<RenderColor>>, String> {
+    let img = image::open(image_file).map_err(|_| format!("can not open file {}", image_file))?;
+    let resized_img = img.resize_exact(width as u32, height as u32, FilterType::Triangle).to_rgb();
+    let mut colors = vec![vec![RenderColor::Default; width]; height];
+    for (x, y, pixel) in resized_img.enumerate_pixels() {
+        colors[y as usize][x as usize] = RenderColor::Byte(pixel[0], pixel[1], pixel[2]);
+    }
+    Ok(colors)
+}
+
+#[test]
+fn cell2darray_works() {
+    let chars = Char2DArray::from(vec![vec!['a', ' '], vec![' ', 'b']]);
+    let colors = vec![vec![RenderColor::Red, RenderColor::Green], vec![RenderColor::Blue, RenderColor::Yellow]];
+    let mut cells = Cell2DArray::from_chars(&Char2DArray::from(vec![vec!['.'; 4]; 3]), RenderColor::Black, RenderColor::White);
+    cells.overwrite_fg(&chars, &colors, CharPosition { x: 3, y: 1 }, Some(' '));
+    assert_eq!(cells.buffer[1][3].fg, RenderColor::Red);
+    assert_eq!(cells.buffer[1][2].fg, RenderColor::Black);
+    // out of the area
+    assert_eq!(cells.buffer[2][3].fg, RenderColor::Black);
+    assert_eq!(cells.buffer[2][3].ch, '.');
+    assert_eq!(cells.buffer[2][3].bg, RenderColor::White);
+}
+
+#[test]
+fn image2colors_works() {
+    let colors = image2colors("./drawings/001_megamouse_shark.png", 8, 4).unwrap();
+    assert_eq!((colors[0].len(), colors.len()), (8, 4));
+    assert!(colors.iter().flatten().all(|color| matches!(color, RenderColor::Byte(_, _, _))));
+    assert!(image2colors("./drawings/no_such_file.png", 8, 4).is_err());
+}
@@ -1,4 +1,5 @@
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use rand::rngs::StdRng;
 use image2ascii::{Char2DArray, string2ascii, CharPosition};
 
 fn generate_character(character: char, size: f32, ch: char) -> Char2DArray {
@@ -14,8 +15,7 @@ fn generate_character(character: char, size: f32, ch: char) -> Char2DArray {
     c2d
 }
 
-fn generate_character_randomly(character: char, min_size: f32, max_size: f32, chars: &Vec<char>) -> Char2DArray{
-    let mut rng = thread_rng();
+fn generate_character_randomly(character: char, min_size: f32, max_size: f32, chars: &[char], rng: &mut StdRng) -> Char2DArray{
     let size = rng.gen_range(min_size, max_size);
     let index = rng.gen_range(0, chars.len());
     generate_character(character, size, chars[index])
@@ -44,9 +44,8 @@ pub enum CharacterStatus {
 }
 
 impl Character {
-    pub fn new(ch: char, config: CharacterConfig) -> Self{
-        let mut rng = thread_rng();
-        let c2d = generate_character_randomly(ch, config.min_size, config.max_size, &config.chars); 
+    pub fn new(ch: char, config: CharacterConfig, rng: &mut StdRng) -> Self{
+        let c2d = generate_character_randomly(ch, config.min_size, config.max_size, &config.chars, rng); 
         let pos = CharPosition {
             x: rng.gen_range(0, 1 /*config.area_wxh.0 - c2d.width()*/) as i32,
             y: rng.gen_range(0, 1 /*config.area_wxh.1 - c2d.height()*/) as i32,
@@ -0,0 +1,111 @@
+//! creatures each player has caught, saved as a json file per player
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// how a creature has been caught so far
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaughtRecord {
+    /// unix time (sec) of the first catch
+    pub first_caught: u64,
+    /// unix time (sec) of the latest catch
+    pub last_caught: u64,
+    pub count: u32,
+    /// msec of the fastest catch
+    pub best_time: u128,
+    pub fewest_typos: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Collection {
+    /// keyed by the pict id
+    pub caught: BTreeMap<String, CaughtRecord>,
+}
+
+impl Collection {
+    pub fn new() -> Self {
+        Collection::default()
+    }
+
+    fn collection_file(collection_dir: &str, player: &str) -> PathBuf {
+        Path::new(collection_dir).join(format!("{}.json", player))
+    }
+
+    /// load the collection of the player. a player who has not played yet has an empty one
+    pub fn load(collection_dir: &str, player: &str) -> Result<Self, String> {
+        let path = Collection::collection_file(collection_dir, player);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Collection::new()),
+            Err(e) => return Err(format!("can not open collection file {}: {}", path.display(), e)),
+        };
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| format!("invalid collection file {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, collection_dir: &str, player: &str) -> Result<(), String> {
+        fs::create_dir_all(collection_dir).map_err(|e| format!("can not create collection dir {}: {}", collection_dir, e))?;
+        let path = Collection::collection_file(collection_dir, player);
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        // write the whole file at once so that a crash does not leave half of it
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, json).map_err(|e| format!("can not write collection file {}: {}", tmp_path.display(), e))?;
+        fs::rename(&tmp_path, &path).map_err(|e| format!("can not write collection file {}: {}", path.display(), e))
+    }
+
+    /// returns true when the creature is caught for the first time
+    pub fn record_catch(&mut self, id: &str, time: u128, typo: u32, timestamp: u64) -> bool {
+        match self.caught.get_mut(id) {
+            Some(record) => {
+                record.last_caught = timestamp;
+                record.count += 1;
+                record.best_time = record.best_time.min(time);
+                record.fewest_typos = record.fewest_typos.min(typo);
+                false
+            },
+            None => {
+                self.caught.insert(id.to_string(), CaughtRecord {
+                    first_caught: timestamp,
+                    last_caught: timestamp,
+                    count: 1,
+                    best_time: time,
+                    fewest_typos: typo,
+                });
+                true
+            },
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&CaughtRecord> {
+        self.caught.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.caught.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.caught.is_empty()
+    }
+}
+
+#[test]
+fn collection_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let collection_dir = dir.path().join("collection");
+    let collection_dir = collection_dir.to_str().unwrap();
+    let mut collection = Collection::load(collection_dir, "alice").unwrap();
+    assert!(collection.is_empty());
+
+    assert!(collection.record_catch("001.png", 5000, 2, 100));
+    assert!(!collection.record_catch("001.png", 7000, 1, 200));
+    let record = collection.get("001.png").unwrap();
+    assert_eq!((record.first_caught, record.last_caught, record.count), (100, 200, 2));
+    assert_eq!((record.best_time, record.fewest_typos), (5000, 1));
+
+    collection.save(collection_dir, "alice").unwrap();
+    assert_eq!(Collection::load(collection_dir, "alice").unwrap(), collection);
+    assert!(Collection::load(collection_dir, "bob").unwrap().is_empty());
+}
@@ -1,8 +1,8 @@
 // use super::Graphics::GraphicsType
 use crossbeam_channel as channel;
-use std::collections::HashMap;
 use std::thread;
 use std::time::Duration;
+use serde::{Deserialize, Serialize};
 // use std::time::Instant;
 
 
@@ -10,23 +10,146 @@ use std::time::Duration;
 use super::uifuncs::{UIGraphics, RenderColor};
 use super::stage::{Stage, StageConfig};
 use super::audiofuncs::{AudioFuncs};
+use super::speechqueue::{SpeechPriority, UtteranceHandle};
 
+/// show the ascii art of the image with an effect
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppearCommand {
+    pub filename: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisappearCommand {
+    pub name: String,
+}
+
+/// text of the telop or subtelop. characters before `pos` are marked as typed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelopCommand {
+    pub text: String,
+    #[serde(default)]
+    pub pos: usize,
+}
+
+/// show the image as it is. used for titles and caught creatures
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TitleCommand {
+    pub filename: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SilhouetteCommand {
+    pub filename: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpeechCommand {
+    pub text: String,
+    #[serde(default = "SpeechCommand::default_lang")]
+    pub lang: String,
+    #[serde(default)]
+    pub priority: SpeechPriority,
+}
 
+impl SpeechCommand {
+    fn default_lang() -> String {
+        String::from("en")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayCommand {
+    pub name: String,
+    /// play again and again until it is stopped
+    #[serde(default, rename = "loop")]
+    pub repeat: bool,
+}
+
+/// without a name every sound stops
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StopSoundCommand {
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// the character typed last, shown next to the creature
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CharacterCommand {
+    pub ch: char,
+}
+
+/// the screen has a new size in characters. the stage is fitted in it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResizeCommand {
+    pub width: usize,
+    pub height: usize,
+}
+
+/// what the command server can do. serialized as {"command": "telop", "text": ..., "pos": ...}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
 pub enum Command {
-    AppearCommand(HashMap<String, String>),
-    DisappearCommand(HashMap<String, String>),
-    TelopCommand(HashMap<String, String>),
-    TitleCommand(HashMap<String, String>),
-    SubTelopCommand(HashMap<String, String>),
-    SpeechCommand(HashMap<String, String>),
-    CharacterCommand(HashMap<String, String>),
+    Appear(AppearCommand),
+    Disappear(DisappearCommand),
+    Telop(TelopCommand),
+    #[serde(rename = "subtelop")]
+    SubTelop(TelopCommand),
+    Title(TitleCommand),
+    Silhouette(SilhouetteCommand),
+    Speech(SpeechCommand),
+    StopSpeech,
+    Play(PlayCommand),
+    StopSound(StopSoundCommand),
+    Character(CharacterCommand),
+    /// remove the creature and the telops
+    Clear,
+    SetColors(ColorConfig),
+    Resize(ResizeCommand),
+    /// stop every sound and end the server thread
+    Shutdown,
+}
+
+impl Command {
+    /// what can be known before the command runs: the image files exist and the typed position is in the text.
+    /// errors which only the server thread finds, such as an image which can not be decoded, are not reported
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Command::Appear(AppearCommand { filename, .. })
+            | Command::Title(TitleCommand { filename })
+            | Command::Silhouette(SilhouetteCommand { filename }) if !std::path::Path::new(filename).is_file() => {
+                Err(format!("image file {} does not exist", filename))
+            },
+            Command::Telop(telop) | Command::SubTelop(telop) if telop.pos > telop.text.chars().count() => {
+                Err(format!("pos {} is beyond the {} characters of the text", telop.pos, telop.text.chars().count()))
+            },
+            _ => Ok(()),
+        }
+    }
 }
 
+/// a command and the handle of the utterance it starts
+struct Request {
+    command: Command,
+    handle: Option<UtteranceHandle>,
+}
+
+#[derive(Clone)]
 pub struct CommandClient {
-    chan_tx: channel::Sender<Command>,
+    chan_tx: channel::Sender<Request>,
 }
 
-#[derive(Debug, Copy , Clone)]
+#[derive(Debug, Copy , Clone, PartialEq, Serialize, Deserialize)]
 pub struct ColorConfig {
     pub normal: RenderColor,
     pub normal_bg: RenderColor,
@@ -35,58 +158,73 @@ pub struct ColorConfig {
 }
 
 impl CommandClient {
-    pub fn appear(&self, filename: &str, name: &str) {
-        let mut params = HashMap::new();
-        params.insert(String::from("filename"), filename.to_string());
-        params.insert(String::from("name"), name.to_string());
-        let cmd = Command::AppearCommand(params);
-        self.chan_tx.send(cmd).unwrap();
-    }
-    pub fn disappear(&self, name: &str) {
-        let mut params = HashMap::new();
-        params.insert(String::from("name"), name.to_string());
-        let cmd = Command::DisappearCommand(params);
-        self.chan_tx.send(cmd).unwrap();
-    }
-    pub fn telop(&self, text: &str, pos: usize) {
-        let mut params = HashMap::new();
-        params.insert(String::from("text"), text.to_string());
-        params.insert(String::from("pos"), pos.to_string());
-        let cmd = Command::TelopCommand(params);
-        self.chan_tx.send(cmd).unwrap();
-    }
-    pub fn title(&self, filename: &str) {
-        let mut params = HashMap::new();
-        params.insert(String::from("filename"), filename.to_string());
-        let cmd = Command::TitleCommand(params);
-        self.chan_tx.send(cmd).unwrap();
-    }
-    pub fn subtelop(&self, text: &str, pos: usize) {
-        let mut params = HashMap::new();
-        params.insert(String::from("text"), text.to_string());
-        params.insert(String::from("pos"), pos.to_string());
-        let cmd = Command::SubTelopCommand(params);
-        self.chan_tx.send(cmd).unwrap();
-    }
-    pub fn speech(&self, text: &str, lang: &str) {
-        let mut params = HashMap::new();
-        params.insert(String::from("text"), text.to_string());
-        params.insert(String::from("lang"), lang.to_string());
-        let cmd = Command::SpeechCommand(params);
-        self.chan_tx.send(cmd).unwrap();
-    }
-    pub fn character(&self, ch: char) {
-        let mut params = HashMap::new();
-        params.insert(String::from("ch"), ch.to_string());
-        let cmd = Command::CharacterCommand(params);
-        self.chan_tx.send(cmd).unwrap();
+    /// fails when the server thread has ended
+    pub fn send(&self, command: Command) -> Result<(), String> {
+        self.send_request(Request { command, handle: None })
+    }
+    fn send_request(&self, request: Request) -> Result<(), String> {
+        self.chan_tx.send(request).map_err(|_| String::from("command server has stopped"))
+    }
+    pub fn appear(&self, filename: &str, name: &str) -> Result<(), String> {
+        self.send(Command::Appear(AppearCommand { filename: filename.to_string(), name: name.to_string() }))
+    }
+    pub fn disappear(&self, name: &str) -> Result<(), String> {
+        self.send(Command::Disappear(DisappearCommand { name: name.to_string() }))
+    }
+    pub fn telop(&self, text: &str, pos: usize) -> Result<(), String> {
+        self.send(Command::Telop(TelopCommand { text: text.to_string(), pos }))
+    }
+    pub fn title(&self, filename: &str) -> Result<(), String> {
+        self.send(Command::Title(TitleCommand { filename: filename.to_string() }))
+    }
+    pub fn silhouette(&self, filename: &str) -> Result<(), String> {
+        self.send(Command::Silhouette(SilhouetteCommand { filename: filename.to_string() }))
+    }
+    pub fn subtelop(&self, text: &str, pos: usize) -> Result<(), String> {
+        self.send(Command::SubTelop(TelopCommand { text: text.to_string(), pos }))
+    }
+    /// the handle tells when the utterance has finished
+    pub fn speech(&self, text: &str, lang: &str, priority: SpeechPriority) -> Result<UtteranceHandle, String> {
+        let handle = UtteranceHandle::new();
+        let command = Command::Speech(SpeechCommand { text: text.to_string(), lang: lang.to_string(), priority });
+        self.send_request(Request { command, handle: Some(handle.clone()) })?;
+        Ok(handle)
+    }
+    pub fn stop_speech(&self) -> Result<(), String> {
+        self.send(Command::StopSpeech)
+    }
+    pub fn play(&self, name: &str) -> Result<(), String> {
+        self.send(Command::Play(PlayCommand { name: name.to_string(), repeat: false }))
+    }
+    pub fn play_loop(&self, name: &str) -> Result<(), String> {
+        self.send(Command::Play(PlayCommand { name: name.to_string(), repeat: true }))
+    }
+    /// without a name every sound stops
+    pub fn stop_sound(&self, name: Option<&str>) -> Result<(), String> {
+        self.send(Command::StopSound(StopSoundCommand { name: name.map(|name| name.to_string()) }))
+    }
+    pub fn character(&self, ch: char) -> Result<(), String> {
+        self.send(Command::Character(CharacterCommand { ch }))
+    }
+    pub fn clear(&self) -> Result<(), String> {
+        self.send(Command::Clear)
+    }
+    pub fn set_colors(&self, colors: ColorConfig) -> Result<(), String> {
+        self.send(Command::SetColors(colors))
+    }
+    pub fn resize(&self, width: usize, height: usize) -> Result<(), String> {
+        self.send(Command::Resize(ResizeCommand { width, height }))
+    }
+    pub fn shutdown(&self) -> Result<(), String> {
+        self.send(Command::Shutdown)
     }
 }
 
 pub fn start_command_server(graphics: Box<dyn UIGraphics>, config: StageConfig, color_config: ColorConfig, audio: Box<dyn AudioFuncs>) -> CommandClient {
-    let (chan_tx, chan_rx) = channel::unbounded::<Command>();
+    let (chan_tx, chan_rx) = channel::unbounded::<Request>();
     thread::spawn(move || {
         let mut stage = Stage::new(config);
+        let mut color_config = color_config;
         let mut current_color = color_config.normal;
         let mut current_bg = color_config.normal_bg;
         let interval = 1000 / config.framerate as u64;
@@ -95,71 +233,95 @@ pub fn start_command_server(graphics: Box<dyn UIGraphics>, config: StageConfig,
             channel::select! {
                 recv(chan_rx) -> received => {
                     match received {
-                        Ok(cmd) => {
-                            match cmd {
-                                Command::AppearCommand(data) => {
-                                    if let Some(filename) = data.get("filename") {
-                                        stage.appear(filename);
+                        Ok(Request { command, handle }) => {
+                            match command {
+                                Command::Appear(appear) => {
+                                    stage.appear(&appear.filename);
+                                },
+                                Command::Disappear(_) => {
+                                    stage.disappear();
+                                },
+                                Command::Telop(telop) => {
+                                    let offset = stage.telop_offset();
+                                    let too_small = stage.is_too_small();
+                                    let area = stage.update_telop(&telop.text, telop.pos);
+                                    if !too_small {
+                                        graphics.draw_area(&current_color, &current_bg, &area.buffer, Some((0, offset)));
+                                        graphics.flush();
                                     }
-                                    if let Some(name) = data.get("name") {
-                                        //audio.speech(name);
+                                },
+                                Command::Title(title) => {
+                                    stage.title(&title.filename);
+                                },
+                                Command::Silhouette(silhouette) => {
+                                    stage.silhouette(&silhouette.filename);
+                                },
+                                Command::SubTelop(subtelop) => {
+                                    let offset = stage.subtelop_offset();
+                                    let too_small = stage.is_too_small();
+                                    let area = stage.update_subtelop(&subtelop.text, subtelop.pos);
+                                    if !too_small {
+                                        graphics.draw_area(&current_color, &current_bg, &area.buffer, Some((0, offset)));
+                                        graphics.flush();
                                     }
                                 },
-                                Command::DisappearCommand(data) => {
-                                    if let Some(name) = data.get("name") {
-                                        //audio.speech_lang(&format!("{},Get", name), "ja");
+                                Command::Speech(speech) => {
+                                    audio.speak(&speech.text, &speech.lang, speech.priority, handle.unwrap_or_default());
+                                },
+                                Command::StopSpeech => {
+                                    audio.stop_speech();
+                                },
+                                Command::Play(play) => {
+                                    if play.repeat {
+                                        audio.play_loop(&play.name);
+                                    } else {
+                                        audio.play(&play.name);
                                     }
-                                    stage.disappear();
                                 },
-                                Command::TelopCommand(data) => {
-                                    let mut text = "";
-                                    if let Some(text_data) = data.get("text") {
-                                        text = text_data;
+                                Command::StopSound(stop) => {
+                                    match stop.name {
+                                        Some(name) => audio.stop(&name),
+                                        None => audio.stop_all(),
                                     }
-
-                                    let pos: usize = data.get("pos").unwrap_or(&String::from("0")).parse().unwrap();
+                                },
+                                Command::Character(character) => {
+                                    stage.update_character(character.ch);
+                                },
+                                Command::Clear => {
+                                    stage.clear();
+                                    let too_small = stage.is_too_small();
                                     let offset = stage.telop_offset();
-                                    /*
-                                    if pos > 0  && pos < text.len() {
-                                        let ch = text.chars().nth(pos-1).unwrap().to_ascii_lowercase();
-                                        audio.speech(&ch.to_string());
+                                    let area = stage.update_telop("", 0);
+                                    if !too_small {
+                                        graphics.draw_area(&current_color, &current_bg, &area.buffer, Some((0, offset)));
                                     }
-                                    */
-                                    graphics.draw_area(&current_color, &current_bg, &stage.update_telop(text, pos).buffer, Some((0, offset)));
-                                    graphics.flush();
-                                },
-                                Command::TitleCommand(data) => {
-                                    if let Some(filename) = data.get("filename") {
-                                        stage.title(filename);
+                                    let offset = stage.subtelop_offset();
+                                    let area = stage.update_subtelop("", 0);
+                                    if !too_small {
+                                        graphics.draw_area(&current_color, &current_bg, &area.buffer, Some((0, offset)));
+                                        graphics.flush();
                                     }
                                 },
-                                Command::SubTelopCommand(data) => {
-                                    let mut text = "";
-                                    if let Some(text_data) = data.get("text") {
-                                        text = text_data;
+                                Command::SetColors(colors) => {
+                                    color_config = colors;
+                                },
+                                Command::Resize(resize) => {
+                                    graphics.resize(resize.width, resize.height);
+                                    if stage.resize((resize.width, resize.height)) {
+                                        let offset = stage.telop_offset();
+                                        graphics.draw_area(&current_color, &current_bg, &stage.refresh_telop().buffer, Some((0, offset)));
+                                        let offset = stage.subtelop_offset();
+                                        graphics.draw_area(&current_color, &current_bg, &stage.refresh_subtelop().buffer, Some((0, offset)));
+                                    } else {
+                                        graphics.draw_area(&current_color, &current_bg, &stage.too_small_notice().buffer, None);
                                     }
-
-                                    let pos: usize = data.get("pos").unwrap_or(&String::from("0")).parse().unwrap();
-                                    let offset = stage.subtelop_offset();
-                                    graphics.draw_area(&current_color, &current_bg, &stage.update_subtelop(text, pos).buffer, Some((0, offset)));
                                     graphics.flush();
                                 },
-                                Command::SpeechCommand(data) => {
-                                    if let Some(text) = data.get("text") {
-                                        if let Some(lang) = data.get("lang") {
-                                            audio.speech_lang(text, lang);
-                                        } else {
-                                            audio.speech(text);
-                                        }
-                                    }
+                                Command::Shutdown => {
+                                    audio.stop_speech();
+                                    audio.stop_all();
+                                    break;
                                 },
-                                Command::CharacterCommand(data) => {
-                                    if let Some(ch) = data.get("ch") {
-                                        if let Some(character) = ch.chars().nth(0) {
-                                            stage.update_character(character);
-                                        }
-                                    }
-                                }
                             }
                         },
                         Err(_) => {
@@ -175,8 +337,11 @@ pub fn start_command_server(graphics: Box<dyn UIGraphics>, config: StageConfig,
                         current_color = color_config.normal;
                         current_bg = color_config.normal_bg;
                     }
-                    graphics.draw_area(&current_color, &current_bg, &stage.update_pict().buffer, Option::None);
-                    graphics.flush();
+                    // the notice stays until the screen is big enough again
+                    if !stage.is_too_small() {
+                        graphics.draw_cells(&stage.update_pict_cells(current_color, current_bg).buffer, Option::None);
+                        graphics.flush();
+                    }
                 }
             }
         }
@@ -188,6 +353,62 @@ pub fn start_command_server(graphics: Box<dyn UIGraphics>, config: StageConfig,
 
 }
 
+/// send a resize command for each size the ui backend reports
+pub fn forward_resize_events(resize_rx: Option<channel::Receiver<(usize, usize)>>, client: &CommandClient) {
+    if let Some(resize_rx) = resize_rx {
+        let client = client.clone();
+        thread::spawn(move || {
+            for (width, height) in resize_rx.iter() {
+                if client.resize(width, height).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[test]
+fn command_serde_works() {
+    let command: Command = serde_json::from_str(r#"{"command": "telop", "text": "MEGAMOUSE"}"#).unwrap();
+    assert_eq!(command, Command::Telop(TelopCommand { text: String::from("MEGAMOUSE"), pos: 0 }));
+    let command: Command = serde_json::from_str(r#"{"command": "play", "name": "ambient", "loop": true}"#).unwrap();
+    assert_eq!(command, Command::Play(PlayCommand { name: String::from("ambient"), repeat: true }));
+    let command: Command = serde_json::from_str(r#"{"command": "speech", "text": "メガマウスザメ", "lang": "ja", "priority": "name"}"#).unwrap();
+    assert_eq!(command, Command::Speech(SpeechCommand { text: String::from("メガマウスザメ"), lang: String::from("ja"), priority: SpeechPriority::Name }));
+    assert_eq!(serde_json::to_string(&Command::Shutdown).unwrap(), r#"{"command":"shutdown"}"#);
+    let command: Command = serde_json::from_str(r#"{"command": "resize", "width": 80, "height": 24}"#).unwrap();
+    assert_eq!(command, Command::Resize(ResizeCommand { width: 80, height: 24 }));
+    let command = Command::SubTelop(TelopCommand { text: String::from("ME"), pos: 1 });
+    assert_eq!(serde_json::from_str::<Command>(&serde_json::to_string(&command).unwrap()).unwrap(), command);
+
+    // a typo in a key or a bad value is an error instead of lost data
+    assert!(serde_json::from_str::<Command>(r#"{"command": "telop", "txt": "MEGAMOUSE"}"#).is_err());
+    assert!(serde_json::from_str::<Command>(r#"{"command": "telop", "text": "MEGAMOUSE", "poss": 3}"#).is_err());
+    assert!(serde_json::from_str::<Command>(r#"{"command": "telop", "text": "MEGAMOUSE", "pos": "x"}"#).is_err());
+    assert!(serde_json::from_str::<Command>(r#"{"command": "dance"}"#).is_err());
+}
+
+#[test]
+fn command_client_works() {
+    let funcs = super::uifuncs::generate_uifuncs(super::uifuncs::UIFuncsType::DEBUG, super::renderer::ColorDepth::Auto);
+    let audio = super::audiofuncs::generate_audiofuncs(super::audiofuncs::AudioFuncsType::Recording, &Default::default(), &Default::default(), Default::default());
+    let log = audio.audio_log().unwrap();
+    let config = StageConfig { stage_wxh: (64, 18), aa_width: 32, framerate: 10, color_art: false, seed: Some(0) };
+    let colors = ColorConfig { normal: RenderColor::Black, normal_bg: RenderColor::White, info: RenderColor::Blue, info_bg: RenderColor::White };
+    let client = start_command_server(funcs.get_graphics(), config, colors, audio);
+    client.telop("HELLO", 1).unwrap();
+    client.set_colors(ColorConfig { normal: RenderColor::Red, ..colors }).unwrap();
+    client.clear().unwrap();
+    let handle = client.speech("hello", "en", SpeechPriority::Name).unwrap();
+    assert!(handle.wait(Duration::from_secs(5)));
+    client.shutdown().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    // the server has gone
+    assert!(client.telop("BYE", 0).is_err());
+    assert_eq!(log.lock().unwrap().len(), 3);
+}
+
 /*
 #[test]
 fn command_works() {
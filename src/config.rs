@@ -0,0 +1,469 @@
+//! launch configuration loaded from a json file and command line arguments
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use super::uifuncs::{UIFuncsType, RenderColor};
+use super::audiofuncs::{AudioFuncsType, SpeechConfig, SoundConfig};
+use super::sound::SoundLibrary;
+use super::remote::ListenAddress;
+use super::script::Script;
+use super::session::Session;
+use super::stage::StageConfig;
+use super::command::ColorConfig;
+use super::renderer::ColorDepth;
+use super::raster::RasterConfig;
+use super::pict::{PictManager, RoundConfig};
+use super::wordinput::TypingMode;
+
+pub const USAGE: &str = "usage: aquatyping [COMMAND] [OPTIONS]
+
+commands:
+    play                 play the typing game (default)
+    serve                show the stage and take commands as json lines from other programs
+    script FILE          play a json script of stage commands with waits, loops and variables
+    replay FILE          play a session recorded with --record again. the keys and telops come at the
+                         recorded times; the creatures move with the clock and can be a frame or so off
+    sounds               write the synthesised sound effects to wav files
+    export IMAGE         write the ascii art of an image as html, svg or text by the extension of --out
+    check                report picts whose romaji disagrees with the kana name, the voice used for each language, and missing sounds
+
+options:
+    --config FILE        load settings from a json config file
+    --stage-size WxH     largest stage size in characters (e.g. 640x180). the stage fits in the terminal
+    --aa-width N         width of the ascii art in characters
+    --framerate N        frames per second
+    --pict-dir DIR       directory which contains picts_info.json
+    --targets N          number of creatures in a round
+    --include-tags TAGS  use only picts with one of these comma separated tags
+    --exclude-tags TAGS  do not use picts with any of these comma separated tags
+    --order NAME         sequential, shuffled, shuffled-no-repeat or weighted
+    --seed N             seed of the random order and the animations
+    --player NAME        name the results and key statistics are saved under
+    --collection-dir DIR directory where the creatures each player caught are saved
+    --history FILE       json lines file where the result of each round is saved
+    --mode LANG          name to type: ja (romaji of the kana name) or en (english name)
+    --ui NAME            ui backend (tui, debug, capture)
+    --audio NAME         audio backend (oscommand, null, recording)
+    --color-art          colour the ascii art with the colours of the image
+    --color-depth NAME   colours of the terminal: auto, truecolor, 256 or 16
+    --render-stats       print the bytes written to the terminal per frame on exit
+    --record FILE        record the keys, seed and config of the session to replay it later
+    --cast FILE          also write what is drawn to an asciicast v2 file (works with replay)
+    --frames PATH        also rasterise what is drawn into an animated gif when PATH ends with .gif,
+                         otherwise into png files in the directory PATH (works with replay).
+                         encoding gif frames of a big stage can take a while after the game ends
+    --cell-size WxH      pixels of a character in the rasterised frames (default 8x16)
+    --listen ADDR        where serve listens: unix:PATH or tcp:127.0.0.1:PORT
+    --out PATH           directory the sounds command writes to (default ./sounds/)
+                         or file the export command writes to (default IMAGE.html)
+    --set NAME=VALUE     set a variable of the script (repeatable)
+    -h, --help           show this message
+
+options given on the command line override values in the config file.
+tts commands used on linux are set by \"speech\" in the config file and wav players by \"sound\".
+sound effects (click, typo, catch, ambient) are wav files named in sounds.json in the pict dir.
+effects sounds.json does not name are synthesised.
+scripts can use ${script_dir} and ${pict_dir} to name images.";
+
+/// settings of the whole game
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    pub stage_wxh: (usize, usize),
+    pub aa_width: usize,
+    pub framerate: u32,
+    pub pict_dir: String,
+    pub num_of_targets: usize,
+    pub round: RoundConfig,
+    pub typing_mode: TypingMode,
+    pub player: String,
+    pub history_file: String,
+    pub collection_dir: String,
+    /// session file the keys are recorded to
+    pub record_file: Option<String>,
+    /// asciicast file the frames are written to
+    pub cast_file: Option<String>,
+    /// gif file or directory of png files the frames are rasterised to
+    pub frames_path: Option<String>,
+    pub raster: RasterConfig,
+    /// address the serve command listens on
+    pub listen: String,
+    pub ui: UIFuncsType,
+    pub audio: AudioFuncsType,
+    pub speech: SpeechConfig,
+    pub sound: SoundConfig,
+    pub render_stats: bool,
+    pub color_art: bool,
+    pub color_depth: ColorDepth,
+    pub color: ColorConfig,
+}
+
+/// what the command line asked for
+#[derive(Debug)]
+pub enum CliAction {
+    Play(GameConfig),
+    Check(GameConfig),
+    Serve(GameConfig),
+    Script(GameConfig, Script),
+    /// the config holds the backends to replay the session with
+    Replay(GameConfig, Box<Session>),
+    /// dump the sound effects into the directory
+    Sounds(String),
+    /// write the ascii art of the image to the file
+    Export(GameConfig, String, String),
+    Help,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            stage_wxh: (640, 180),
+            aa_width: 270,
+            framerate: 10,
+            pict_dir: String::from("./drawings/"),
+            num_of_targets: 120,
+            round: RoundConfig::default(),
+            typing_mode: TypingMode::Japanese,
+            player: String::from("player"),
+            history_file: String::from("./aquatyping_history.jsonl"),
+            collection_dir: String::from("./aquatyping_collection/"),
+            record_file: None,
+            cast_file: None,
+            frames_path: None,
+            raster: RasterConfig::default(),
+            listen: String::from("tcp:127.0.0.1:7878"),
+            ui: UIFuncsType::TUI,
+            audio: AudioFuncsType::OsCommand,
+            speech: SpeechConfig::default(),
+            sound: SoundConfig::default(),
+            render_stats: false,
+            color_art: false,
+            color_depth: ColorDepth::Auto,
+            color: ColorConfig {
+                normal: RenderColor::Black,
+                normal_bg: RenderColor::White,
+                info: RenderColor::Blue,
+                info_bg: RenderColor::White,
+            },
+        }
+    }
+}
+
+impl GameConfig {
+    /// load config from a json file. missing keys keep their default values
+    pub fn load(config_file: &str) -> Result<GameConfig, String> {
+        let file = File::open(config_file).map_err(|e| format!("can not open config file {}: {}", config_file, e))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| format!("invalid config file {}: {}", config_file, e))
+    }
+
+    pub fn stage_config(&self) -> StageConfig {
+        StageConfig {
+            stage_wxh: self.stage_wxh,
+            aa_width: self.aa_width,
+            framerate: self.framerate,
+            color_art: self.color_art,
+            seed: self.round.seed,
+        }
+    }
+
+    /// check every value and report all problems at once
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors: Vec<String> = Vec::new();
+        let (width, height) = self.stage_wxh;
+        if width == 0 || height < 6 {
+            errors.push(format!("stage size {}x{} is too small (minimum height is 6)", width, height));
+        }
+        if self.aa_width == 0 || self.aa_width > width {
+            errors.push(format!("aa width {} must be between 1 and the stage width {}", self.aa_width, width));
+        }
+        let (cell_w, cell_h) = self.raster.cell_wxh;
+        if cell_w == 0 || cell_h == 0 {
+            errors.push(format!("cell size {}x{} must not be 0", cell_w, cell_h));
+        }
+        if self.framerate == 0 || self.framerate > 60 {
+            errors.push(format!("framerate {} must be between 1 and 60", self.framerate));
+        }
+        // the player name is a part of the collection file name
+        if self.player.is_empty() || !self.player.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_') {
+            errors.push(format!("player name '{}' must be letters, digits, '-' or '_'", self.player));
+        }
+        if self.num_of_targets == 0 {
+            errors.push(String::from("number of targets must be at least 1"));
+        }
+        match PictManager::load(&self.pict_dir) {
+            Ok(pict_manager) => {
+                if pict_manager.get_pict_len() == 0 {
+                    errors.push(format!("no picts found in {}", self.pict_dir));
+                } else if pict_manager.filter(&self.round).is_empty() {
+                    errors.push(format!("no picts in {} match the tag filters", self.pict_dir));
+                }
+            },
+            Err(e) => errors.push(e),
+        }
+        if let Err(e) = SoundLibrary::load(&self.pict_dir) {
+            errors.push(e);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+/// parse command line arguments (without the program name)
+pub fn parse_args(args: &[String]) -> Result<CliAction, String> {
+    let (command, args) = match args.first() {
+        Some(first) if !first.starts_with('-') => (first.as_str(), &args[1..]),
+        _ => ("play", args),
+    };
+
+    // the config file is loaded first so that the other flags can override it
+    let mut config = GameConfig::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            config = GameConfig::load(next_value(arg, &mut iter)?)?;
+        }
+    }
+
+    let mut out_path: Option<String> = None;
+    let mut script_file: Option<String> = None;
+    let mut session_file: Option<String> = None;
+    let mut image_file: Option<String> = None;
+    let mut script_vars: Vec<(String, String)> = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(CliAction::Help),
+            "--out" => out_path = Some(next_value(arg, &mut iter)?.to_string()),
+            "--set" => script_vars.push(parse_var(next_value(arg, &mut iter)?)?),
+            "--config" => { next_value(arg, &mut iter)?; },
+            "--stage-size" => config.stage_wxh = parse_wxh(arg, next_value(arg, &mut iter)?)?,
+            "--aa-width" => config.aa_width = parse_number(arg, next_value(arg, &mut iter)?)?,
+            "--framerate" => config.framerate = parse_number(arg, next_value(arg, &mut iter)?)?,
+            "--pict-dir" => config.pict_dir = next_value(arg, &mut iter)?.to_string(),
+            "--targets" => config.num_of_targets = parse_number(arg, next_value(arg, &mut iter)?)?,
+            "--include-tags" => config.round.include_tags = parse_list(next_value(arg, &mut iter)?),
+            "--exclude-tags" => config.round.exclude_tags = parse_list(next_value(arg, &mut iter)?),
+            "--order" => config.round.order = parse_name(arg, next_value(arg, &mut iter)?)?,
+            "--seed" => config.round.seed = Some(parse_number(arg, next_value(arg, &mut iter)?)?),
+            "--player" => config.player = next_value(arg, &mut iter)?.to_string(),
+            "--collection-dir" => config.collection_dir = next_value(arg, &mut iter)?.to_string(),
+            "--listen" => config.listen = next_value(arg, &mut iter)?.to_string(),
+            "--record" => config.record_file = Some(next_value(arg, &mut iter)?.to_string()),
+            "--cast" => config.cast_file = Some(next_value(arg, &mut iter)?.to_string()),
+            "--frames" => config.frames_path = Some(next_value(arg, &mut iter)?.to_string()),
+            "--cell-size" => config.raster.cell_wxh = parse_wxh(arg, next_value(arg, &mut iter)?)?,
+            "--history" => config.history_file = next_value(arg, &mut iter)?.to_string(),
+            "--mode" => config.typing_mode = parse_name(arg, next_value(arg, &mut iter)?)?,
+            "--ui" => config.ui = parse_name(arg, next_value(arg, &mut iter)?)?,
+            "--audio" => config.audio = parse_name(arg, next_value(arg, &mut iter)?)?,
+            "--render-stats" => config.render_stats = true,
+            "--color-art" => config.color_art = true,
+            "--color-depth" => config.color_depth = parse_name(arg, next_value(arg, &mut iter)?)?,
+            _ if command == "script" && script_file.is_none() && !arg.starts_with('-') => script_file = Some(arg.to_string()),
+            _ if command == "replay" && session_file.is_none() && !arg.starts_with('-') => session_file = Some(arg.to_string()),
+            _ if command == "export" && image_file.is_none() && !arg.starts_with('-') => image_file = Some(arg.to_string()),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+    match command {
+        "play" => {
+            config.validate()?;
+            Ok(CliAction::Play(config))
+        },
+        "check" => Ok(CliAction::Check(config)),
+        "serve" => {
+            config.validate()?;
+            ListenAddress::parse(&config.listen)?;
+            Ok(CliAction::Serve(config))
+        },
+        "script" => {
+            let script_file = script_file.ok_or_else(|| String::from("script requires a script file"))?;
+            config.validate()?;
+            let mut script = Script::load(&script_file)?;
+            script.vars.extend(script_vars);
+            Ok(CliAction::Script(config, script))
+        },
+        "replay" => {
+            let session_file = session_file.ok_or_else(|| String::from("replay requires a session file"))?;
+            let session = Session::load(&session_file)?;
+            session.header.config.validate()?;
+            Ok(CliAction::Replay(config, Box::new(session)))
+        },
+        "sounds" => Ok(CliAction::Sounds(out_path.unwrap_or_else(|| String::from("./sounds/")))),
+        "export" => {
+            let image_file = image_file.ok_or_else(|| String::from("export requires an image file"))?;
+            if config.aa_width == 0 {
+                return Err(String::from("aa width must be at least 1"));
+            }
+            let out_file = out_path.unwrap_or_else(|| Path::new(&image_file).with_extension("html").to_string_lossy().to_string());
+            Ok(CliAction::Export(config, image_file, out_file))
+        },
+        _ => Err(format!("unknown command: {}", command)),
+    }
+}
+
+fn next_value<'a>(flag: &str, iter: &mut std::slice::Iter<'a, String>) -> Result<&'a str, String> {
+    iter.next().map(|s| s.as_str()).ok_or_else(|| format!("{} requires a value", flag))
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} expects a number but got '{}'", flag, value))
+}
+
+fn parse_wxh<T: std::str::FromStr>(flag: &str, value: &str) -> Result<(T, T), String> {
+    let mut parts = value.splitn(2, 'x');
+    let width = parts.next().and_then(|w| w.parse().ok());
+    let height = parts.next().and_then(|h| h.parse().ok());
+    match (width, height) {
+        (Some(w), Some(h)) => Ok((w, h)),
+        _ => Err(format!("{} expects WxH but got '{}'", flag, value)),
+    }
+}
+
+fn parse_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("--set expects NAME=VALUE but got '{}'", value)),
+    }
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+}
+
+/// backend names are parsed with the same spelling as the config file
+fn parse_name<T: DeserializeOwned>(flag: &str, value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("unknown value for {}: '{}'", flag, value))
+}
+
+#[test]
+fn parse_args_works() {
+    let args: Vec<String> = vec!["--stage-size", "320x90", "--aa-width", "120", "--ui", "debug", "--targets", "5", "--mode", "en"]
+        .iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Play(config)) => {
+            assert_eq!(config.stage_wxh, (320, 90));
+            assert_eq!(config.aa_width, 120);
+            assert_eq!(config.num_of_targets, 5);
+            assert_eq!(config.framerate, 10);
+            assert_eq!(config.typing_mode, TypingMode::English);
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let args: Vec<String> = vec!["--include-tags", "aquatic, shark", "--order", "shuffled-no-repeat", "--seed", "42"]
+        .iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Play(config)) => {
+            assert_eq!(config.round.include_tags, ["aquatic", "shark"]);
+            assert_eq!(config.round.order, super::pict::PictOrder::ShuffledNoRepeat);
+            assert_eq!(config.round.seed, Some(42));
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn parse_args_reports_errors() {
+    let args: Vec<String> = vec!["--stage-size", "10x3", "--framerate", "0", "--pict-dir", "./no_such_dir/"]
+        .iter().map(|s| s.to_string()).collect();
+    let err = parse_args(&args).unwrap_err();
+    assert!(err.contains("stage size"));
+    assert!(err.contains("framerate"));
+    assert!(err.contains("no_such_dir"));
+
+    let args: Vec<String> = vec!["--ui", "gui"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).is_err());
+
+    let args: Vec<String> = vec!["--player", "../alice"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).unwrap_err().contains("player name"));
+
+    let args: Vec<String> = vec!["--include-tags", "no_such_tag"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).unwrap_err().contains("tag filters"));
+}
+
+#[test]
+fn config_file_is_overridden_by_args() {
+    let mut tmp = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(&mut tmp, br#"{"framerate": 20, "aa_width": 200, "ui": "debug"}"#).unwrap();
+    let path = tmp.path().to_str().unwrap().to_string();
+    let args: Vec<String> = vec!["--aa-width".to_string(), "100".to_string(), "--config".to_string(), path];
+    match parse_args(&args) {
+        Ok(CliAction::Play(config)) => {
+            assert_eq!(config.framerate, 20);
+            assert_eq!(config.aa_width, 100);
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn parse_args_subcommand_works() {
+    let args: Vec<String> = vec!["check", "--pict-dir", "./somewhere/"].iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Check(config)) => assert_eq!(config.pict_dir, "./somewhere/"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let args: Vec<String> = vec!["sounds", "--out", "/tmp/sounds"].iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Sounds(out_dir)) => assert_eq!(out_dir, "/tmp/sounds"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let args: Vec<String> = vec!["export", "./drawings/001_megamouse_shark.png", "--aa-width", "80"].iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Export(config, image_file, out_file)) => {
+            assert_eq!(config.aa_width, 80);
+            assert_eq!(image_file, "./drawings/001_megamouse_shark.png");
+            assert_eq!(out_file, "./drawings/001_megamouse_shark.html");
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let args: Vec<String> = vec!["export", "--out", "/tmp/shark.svg"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).unwrap_err().contains("image file"));
+    let args: Vec<String> = vec!["serve", "--listen", "unix:/tmp/aquatyping.sock"].iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Serve(config)) => assert_eq!(config.listen, "unix:/tmp/aquatyping.sock"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let args: Vec<String> = vec!["serve", "--listen", "tcp:0.0.0.0:7878"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).is_err());
+    let mut tmp = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(&mut tmp, br#"{"vars": {"name": "SHARK"}, "steps": [{"command": "telop", "text": "${name}"}]}"#).unwrap();
+    let path = tmp.path().to_str().unwrap().to_string();
+    let args: Vec<String> = vec!["script", &path, "--set", "name=FISH", "--ui", "debug"].iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Script(config, script)) => {
+            assert!(matches!(config.ui, UIFuncsType::DEBUG));
+            assert_eq!(script.vars["name"], "FISH");
+            assert_eq!(script.with_config_vars(&config).vars["pict_dir"], config.pict_dir);
+            assert!(script.vars.contains_key("script_dir"));
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let args: Vec<String> = vec!["script"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).unwrap_err().contains("script file"));
+    let args: Vec<String> = vec!["script", &path, "--set", "=FISH"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).is_err());
+    let args: Vec<String> = vec!["--record", "/tmp/session.jsonl", "--cast", "/tmp/session.cast", "--frames", "/tmp/session.gif", "--cell-size", "6x12"]
+        .iter().map(|s| s.to_string()).collect();
+    match parse_args(&args) {
+        Ok(CliAction::Play(config)) => {
+            assert_eq!(config.record_file.as_deref(), Some("/tmp/session.jsonl"));
+            assert_eq!(config.cast_file.as_deref(), Some("/tmp/session.cast"));
+            assert_eq!(config.frames_path.as_deref(), Some("/tmp/session.gif"));
+            assert_eq!(config.raster.cell_wxh, (6, 12));
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let args: Vec<String> = vec!["replay", "/no/such/session.jsonl"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).unwrap_err().contains("session file"));
+    let args: Vec<String> = vec!["dance"].iter().map(|s| s.to_string()).collect();
+    assert!(parse_args(&args).is_err());
+}
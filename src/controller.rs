@@ -1,14 +1,24 @@
 use crossbeam_channel as channel;
-use std::time::{Duration, SystemTime, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::time::{Duration, SystemTime, UNIX_EPOCH, Instant};
 use super::uifuncs::{UIFuncs, UIGraphics, UIKeyEvent};
 use super::audiofuncs::{AudioFuncs};
-use super::stage::{Stage, StageConfig};
-use super::command::{Command, CommandClient, start_command_server, ColorConfig};
-use super::pict::{PictManager, Pict};
-
+use super::speechqueue::{SpeechPriority, UtteranceHandle};
+use super::command::{CommandClient, forward_resize_events, start_command_server};
+use super::pict::{PictManager, RoundConfig};
+use super::config::GameConfig;
+use super::romaji::InputResult;
+use super::wordinput::{WordInput, TypingMode, generate_word_input};
+use super::history::{TypingResult, RoundRecord, append_record, load_player_stats};
+use super::keystats::TypingStats;
+use super::collection::Collection;
+use super::sound;
+
+/// errors are returned when the command server has stopped
 trait Controller{
-    fn handle_key_event(&mut self, context: &mut ControlContext, keyevent: &UIKeyEvent) -> ControlState;
-    fn handle_timer_event(&mut self, context: &mut ControlContext) -> ControlState;
+    fn handle_key_event(&mut self, context: &mut ControlContext, keyevent: &UIKeyEvent) -> Result<ControlState, String>;
+    fn handle_timer_event(&mut self, context: &mut ControlContext) -> Result<ControlState, String>;
 }
 
 pub enum ControlState {
@@ -20,7 +30,9 @@ pub enum ControlState {
 pub enum ControlMode {
     TITLE,
     TYPING,
-//    RESULT,
+    PRACTICE,
+    RESULT,
+    GALLERY,
 }
 
 struct ControlContext {
@@ -28,41 +40,78 @@ struct ControlContext {
     pict_dir: String,
     command_client: CommandClient,
     num_of_targets: usize,
+    round: RoundConfig,
+    typing_mode: TypingMode,
+    player: String,
+    history_file: String,
+    collection_dir: String,
+    collection: Collection,
     results: Vec<TypingResult>,
+    stats: TypingStats,
+    /// draws the seed of a round when the seed is not fixed. it is seeded by the config so that a replay draws the same
+    seed_rng: StdRng,
+    /// seed the last round was played with. S on the title screen fixes it to play the round again
+    last_seed: Option<u64>,
     //results: &'a Vec<Result>,
 }
 
-fn generate_controller(mode: ControlMode, context: &mut ControlContext) -> Box<dyn Controller> {
-    match mode {
+fn generate_controller(mode: ControlMode, context: &mut ControlContext) -> Result<Box<dyn Controller>, String> {
+    let controller: Box<dyn Controller> = match mode {
         ControlMode::TITLE => {
-            Box::new(TitleController::new(context))
+            Box::new(TitleController::new(context)?)
         },
         ControlMode::TYPING => {
-            Box::new(TypingController::new(context))
+            Box::new(TypingController::new(context, false)?)
         },
-    }
-} 
+        ControlMode::PRACTICE => {
+            Box::new(TypingController::new(context, true)?)
+        },
+        ControlMode::RESULT => {
+            Box::new(ResultController::new(context)?)
+        },
+        ControlMode::GALLERY => {
+            Box::new(GalleryController::new(context)?)
+        },
+    };
+    Ok(controller)
+}
 
-pub fn control(mut uifuncs: Box<dyn UIFuncs>, color_config: ColorConfig, audiofuncs: Box<dyn AudioFuncs>, pict_dir: &str, stage_config: StageConfig, num_of_targets: usize) {
+/// play until ESC is pressed. fails when the command server stops on the way
+pub fn control(mut uifuncs: Box<dyn UIFuncs>, audiofuncs: Box<dyn AudioFuncs>, config: &GameConfig) -> Result<(), String> {
+    // a collection file which can not be read would be overwritten at the first catch
+    let collection = Collection::load(&config.collection_dir, &config.player)?;
 
     let graphics = uifuncs.get_graphics();
     let keyevent_rx = uifuncs.start_keyevent_thread();
 
-    let command_client = start_command_server(graphics, stage_config, color_config, audiofuncs);
+    let command_client = start_command_server(graphics, config.stage_config(), config.color, audiofuncs);
+    forward_resize_events(uifuncs.resize_events(), &command_client);
 
     //let pict_manager = PictManager::new(pict_dir);
     // commandserverとkeyeventスレッドを終わらせる
 
     let mut context = ControlContext {
         //pict_manager: pict_manager,
-        pict_dir: pict_dir.to_string(),
+        pict_dir: config.pict_dir.to_string(),
         command_client: command_client,
-        num_of_targets: num_of_targets,
+        num_of_targets: config.num_of_targets,
+        round: config.round.clone(),
+        typing_mode: config.typing_mode,
+        player: config.player.to_string(),
+        history_file: config.history_file.to_string(),
+        collection_dir: config.collection_dir.to_string(),
+        collection,
         results: Vec::new(),
+        stats: TypingStats::new(),
+        seed_rng: match config.round.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        },
+        last_seed: None,
     };
 
     let mut mode = ControlMode::TITLE;
-    let mut controller = generate_controller(mode, &mut context);
+    let mut controller = generate_controller(mode, &mut context)?;
 
     loop {
         channel::select! {
@@ -71,69 +120,148 @@ pub fn control(mut uifuncs: Box<dyn UIFuncs>, color_config: ColorConfig, audiofu
                     Ok(event) => {
                         match event {
                             UIKeyEvent::ESC => {
-                                break;
+                                return context.command_client.shutdown();
                             },
                             _ => {
-                                if let ControlState::FINISHED(next_mode) = controller.handle_key_event(&mut context, &event ) {
+                                if let ControlState::FINISHED(next_mode) = controller.handle_key_event(&mut context, &event )? {
                                     mode = next_mode;
-                                    controller = generate_controller(mode, &mut context);
+                                    controller = generate_controller(mode, &mut context)?;
                                 }
                             }
                         }
                     },
                     Err(_) => {
-                        panic!("error");
+                        let _ = context.command_client.shutdown();
+                        return Err(String::from("key events have stopped"));
                     }
                 }
             },
             default(Duration::from_millis(100)) => {
-                if let ControlState::FINISHED(next_mode) = controller.handle_timer_event(&mut context) {
+                if let ControlState::FINISHED(next_mode) = controller.handle_timer_event(&mut context)? {
                     mode = next_mode;
-                    controller = generate_controller(mode, &mut context); 
+                    controller = generate_controller(mode, &mut context)?;
                 }
             }
         }
     }
-    
-
 }
 
 
 struct TitleController {
+    tags: Vec<String>,
+}
 
+fn tag_name(round: &RoundConfig) -> String {
+    if round.include_tags.is_empty() {
+        String::from("ALL")
+    } else {
+        round.include_tags.join(",")
+    }
+}
+
+fn seed_name(round: &RoundConfig) -> String {
+    match round.seed {
+        Some(seed) => seed.to_string(),
+        None => String::from("RANDOM"),
+    }
+}
+
+/// short enough to be read on the title screen and typed after --seed
+fn draw_seed(rng: &mut StdRng) -> u64 {
+    rng.gen_range(0, 10000)
+}
+
+/// a random seed is fixed to the seed of the last round, or a new one before the first round.
+/// a fixed seed becomes random again
+fn next_seed(seed: Option<u64>, last_seed: Option<u64>, rng: &mut StdRng) -> Option<u64> {
+    match seed {
+        Some(_) => None,
+        None => Some(last_seed.unwrap_or_else(|| draw_seed(rng))),
+    }
 }
 
 impl TitleController {
-    fn new(context: &mut ControlContext) -> Self {
+    fn new(context: &mut ControlContext) -> Result<Self, String> {
         let pict_manager = PictManager::new(&context.pict_dir);
         let pict = pict_manager.get_title_by_id("T01.png");
         let filename = pict_manager.get_pict_path(pict.unwrap());
-        context.command_client.title(&filename);
-        context.command_client.telop("PRESS SPACE KEY", 0);
-        TitleController {}
+        context.command_client.title(&filename)?;
+        context.command_client.telop("PRESS SPACE KEY", 0)?;
+        let controller = TitleController {
+            tags: pict_manager.get_tags(),
+        };
+        controller.show_round(context)?;
+        Ok(controller)
+    }
+
+    /// O changes the order, T changes the tag and S fixes the seed of the next round
+    fn show_round(&self, context: &ControlContext) -> Result<(), String> {
+        let round = &context.round;
+        let text = format!("P:PRACTICE G:GALLERY O:{} T:{} S:{}", round.order.name(), tag_name(round), seed_name(round));
+        context.command_client.subtelop(&text.to_ascii_uppercase(), 0)
+    }
+
+    /// only the setting which has changed is shown so that it fits in the subtelop
+    fn show_setting(&self, context: &ControlContext, name: &str, value: &str) -> Result<(), String> {
+        context.command_client.subtelop(&format!("{}:{}", name, value).to_ascii_uppercase(), 0)
+    }
+
+
+    fn next_tag(&self, round: &mut RoundConfig) {
+        let next = match round.include_tags.first() {
+            Some(tag) => self.tags.iter().position(|t| t == tag).map_or(0, |i| i + 1),
+            None => 0,
+        };
+        round.include_tags = self.tags.get(next).cloned().into_iter().collect();
     }
 }
 
 impl Controller for TitleController {
-    fn handle_key_event(&mut self, context: &mut ControlContext, keyevent: &UIKeyEvent) -> ControlState{
-        match *keyevent {
+    fn handle_key_event(&mut self, context: &mut ControlContext, keyevent: &UIKeyEvent) -> Result<ControlState, String>{
+        let state = match *keyevent {
             UIKeyEvent::Char(' ') => {
                 ControlState::FINISHED(ControlMode::TYPING)
             },
+            UIKeyEvent::Char('p') => {
+                ControlState::FINISHED(ControlMode::PRACTICE)
+            },
+            UIKeyEvent::Char('g') => {
+                ControlState::FINISHED(ControlMode::GALLERY)
+            },
+            UIKeyEvent::Char('o') => {
+                context.round.order = context.round.order.next();
+                self.show_setting(context, "ORDER", context.round.order.name())?;
+                ControlState::EXECUTING
+            },
+            UIKeyEvent::Char('t') => {
+                self.next_tag(&mut context.round);
+                self.show_setting(context, "TAG", &tag_name(&context.round))?;
+                ControlState::EXECUTING
+            },
+            UIKeyEvent::Char('s') => {
+                context.round.seed = next_seed(context.round.seed, context.last_seed, &mut context.seed_rng);
+                self.show_setting(context, "SEED", &seed_name(&context.round))?;
+                ControlState::EXECUTING
+            },
             _ => {
                 ControlState::EXECUTING
             }
-        }
+        };
+        Ok(state)
     }
-    fn handle_timer_event(&mut self, context: &mut ControlContext) -> ControlState {
-        ControlState::EXECUTING
+    fn handle_timer_event(&mut self, _context: &mut ControlContext) -> Result<ControlState, String> {
+        Ok(ControlState::EXECUTING)
     }
 }
 
 struct TypingController {
     pict_manager: PictManager,
     index_series: Vec<usize>,
+    typing_mode: TypingMode,
     typing_info: Option<TypingInfo>,
+    /// the collection is saved at each catch so that a round left with ESC keeps it
+    collection_dir: String,
+    player: String,
 }
 
 enum TypingStatus {
@@ -142,197 +270,169 @@ enum TypingStatus {
 }
 
 struct TypingInfo {
+    id: String,
     filepath: String,
     words: String,
     words_ja: String,
-    words_romaji: String,
-    pos: usize,
-    subpos: usize,
+    input: Box<dyn WordInput>,
     start_time: Instant,
+    last_key_time: Instant,
     typo: u32,
+    keystrokes: u32,
     status: TypingStatus,
-}
-
-struct TypingResult {
-    filepath: String,
-    words: String,
-    time: u128,
-    typo: u32,
+    /// "I caught ...!" which has to finish before the next pict comes
+    announcement: Option<UtteranceHandle>,
 }
 
 
 impl TypingController {
     
-    fn new(context: &mut ControlContext) -> TypingController {
+    const NUM_OF_WEAK_SYLLABLES: usize = 5;
+    const IDLING_MSEC: u128 = 3000;
+    /// the next pict comes even if the announcement has not finished by then
+    const MAX_IDLING_MSEC: u128 = 10000;
+
+    /// a practice round picks picts which contain the player's weakest syllables
+    fn new(context: &mut ControlContext, practice: bool) -> Result<TypingController, String> {
         context.results = Vec::new();
+        context.stats = TypingStats::new();
         let pict_manager = PictManager::new(&context.pict_dir);
-        let index_series = pict_manager.index_series(context.num_of_targets);
-        TypingController {
+        let seed = context.round.seed.unwrap_or_else(|| draw_seed(&mut context.seed_rng));
+        context.last_seed = Some(seed);
+        let round = RoundConfig { seed: Some(seed), ..context.round.clone() };
+        let index_series = if practice {
+            let stats = load_player_stats(&context.history_file, &context.player, context.typing_mode).unwrap_or_default();
+            let weak_syllables = stats.weakest_syllables(Self::NUM_OF_WEAK_SYLLABLES);
+            pict_manager.practice_series(&round, context.typing_mode, &weak_syllables, context.num_of_targets)
+        } else {
+            pict_manager.index_series(&round, context.typing_mode, context.num_of_targets)
+        };
+        context.command_client.play_loop(sound::AMBIENT)?;
+        Ok(TypingController {
             pict_manager: pict_manager,
             index_series: index_series,
+            typing_mode: context.typing_mode,
             typing_info: None,
-        }
+            collection_dir: context.collection_dir.to_string(),
+            player: context.player.to_string(),
+        })
     }
 
   
 
-    fn load_pict(&mut self, command_client: &CommandClient) -> bool {
+    fn load_pict(&mut self, command_client: &CommandClient) -> Result<bool, String> {
         let index = self.index_series.pop();
         match index {
             Some(i) => {
                 if let Some(pict) = self.pict_manager.get_pict(i) {
                     let typing_info = TypingInfo {
+                        id: pict.id.to_string(),
                         filepath: self.pict_manager.get_pict_path(pict),
                         words: pict.en.to_string(),
                         words_ja: pict.ja.to_string(),
-                        words_romaji: pict.romaji.to_string().to_ascii_uppercase(),
-                        pos: 0,
-                        subpos: 0,
+                        input: generate_word_input(self.typing_mode, pict),
                         start_time: Instant::now(),
+                        last_key_time: Instant::now(),
                         typo: 0,
+                        keystrokes: 0,
                         status: TypingStatus::TYPING,
+                        announcement: None,
                     };
-                    command_client.speech(&typing_info.words_ja, "ja");
-                    command_client.appear(&self.pict_manager.get_pict_path(pict), &pict.en);
-                    command_client.telop(&typing_info.words_romaji.replace("_", " ").replace("~"," "), 0);
-                    command_client.subtelop(&typing_info.words_ja, 0);
+                    command_client.speech(typing_info.input.name(), typing_info.input.lang(), SpeechPriority::Name)?;
+                    command_client.appear(&self.pict_manager.get_pict_path(pict), &pict.en)?;
+                    let (text, pos) = typing_info.input.telop();
+                    command_client.telop(&text, pos)?;
+                    let (text, pos) = typing_info.input.subtelop();
+                    command_client.subtelop(&text, pos)?;
                     self.typing_info = Some(typing_info);
-                    true
+                    Ok(true)
                 } else {
-                    false
+                    Ok(false)
                 }
             },
             None => {
-                false
+                Ok(false)
             }
         }
     }
 
-    fn unload_pict(&mut self, command_client: &CommandClient) -> Option<TypingResult> {
+    fn unload_pict(&mut self, command_client: &CommandClient, collection: &mut Collection) -> Result<Option<TypingResult>, String> {
     
         if let Some(info) = &mut self.typing_info {
-            command_client.speech(&format!("I caught {}!", &info.words), "en");
-            command_client.disappear(&info.words_ja);
+            command_client.play(sound::CATCH)?;
+            info.announcement = Some(command_client.speech(&format!("I caught {}!", &info.words), "en", SpeechPriority::Announcement)?);
+            command_client.disappear(&info.words_ja)?;
         
             let result = TypingResult {
                 filepath: (&info.filepath).to_string(),
                 words: (&info.words).to_string(),
                 time: info.start_time.elapsed().as_millis(),
                 typo: info.typo,
+                keystrokes: info.keystrokes,
             };
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            collection.record_catch(&info.id, result.time, result.typo, timestamp);
+            if collection.save(&self.collection_dir, &self.player).is_err() {
+                command_client.subtelop("COLLECTION NOT SAVED", 0)?;
+            }
             info.status = TypingStatus::IDLING;
             info.start_time = Instant::now(); 
-            Some(result)
+            Ok(Some(result))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    fn handle_input_char(&mut self, input_ch: char, command_client: &CommandClient) -> Option<TypingResult>{
+    fn handle_input_char(&mut self, input_ch: char, command_client: &CommandClient, stats: &mut TypingStats, collection: &mut Collection) -> Result<Option<TypingResult>, String>{
         if let Some(info) = &mut self.typing_info {
             match info.status {
                 TypingStatus::TYPING => {
-                    /*
-                    let target = info.words.chars().nth(info.pos).unwrap();
-                    //panic!("chchchchc  {} {}", input_ch, target);
-                    
-                    if target.to_ascii_lowercase() == input_ch {
-                        info.pos += 1;
-                        command_client.telop(&info.words, info.pos);
-
-                        if info.pos >= info.words.len() {
-                            return self.unload_pict(command_client);
-                        }
-                    } else {
-                        info.typo += 1;
-                    }
-                    */
-                    let target = info.words_romaji.chars().nth(info.pos).unwrap();
-                    
-                    //panic!("chchchchc  {} {}", input_ch, target);
-                    
-                    if target.to_ascii_lowercase() == input_ch.to_ascii_lowercase() {
-                        command_client.character(input_ch.to_ascii_uppercase());
-                        info.pos += 1;
-                        
-                        //command_client.telop(&info.words_romaji, info.pos);
-
-                        
-                        if let Some(next_target) = info.words_romaji.chars().nth(info.pos) {
-                            match next_target {
-                                '_' => {
-                                    info.pos += 1;
-                                    info.subpos += 1;
-                                    if let Some(speech_ch) = info.words_ja.chars().nth(info.subpos-1) {
-                                        command_client.speech(&speech_ch.to_string(), "ja");
-                                    }
-                                    command_client.subtelop(&info.words_ja, info.subpos);
-                                    command_client.telop(&info.words_romaji.replace("_", " ").replace("~"," "), info.pos);
-                                    
-                                },
-                                '~' => {
-                                    info.pos += 1;
-                                    info.subpos += 2;
-                                    if let Some(speech_ch) = info.words_ja.chars().nth(info.subpos-2) {
-                                        if let Some(speech_ch2) = info.words_ja.chars().nth(info.subpos-1) {
-                                            let speech_ch = speech_ch.to_string();
-                                            let speech_ch2 = speech_ch2.to_string();
-                                            let speech_char = format!("{}{}", speech_ch, speech_ch2);
-                                            command_client.speech(&speech_char, "ja");
-                                        }
-                                    }
-                                    command_client.subtelop(&info.words_ja, info.subpos);
-                                    command_client.telop(&info.words_romaji.replace("_", " ").replace("~"," "), info.pos);
-                                },
-                                _ => {
-                                    command_client.telop(&info.words_romaji.replace("_", " ").replace("~"," "), info.pos);
-                                },
+                    let syllable = info.input.current_syllable();
+                    let expected_key = info.input.expected_keys().first().copied();
+                    match info.input.input(input_ch) {
+                        InputResult::Accepted(committed) => {
+                            info.keystrokes += 1;
+                            stats.record_hit(input_ch, &syllable, info.last_key_time.elapsed().as_millis() as u64);
+                            info.last_key_time = Instant::now();
+                            command_client.play(sound::CLICK)?;
+                            command_client.character(input_ch.to_ascii_uppercase())?;
+                            if !committed.is_empty() {
+                                let speech_text: Vec<&str> = committed.iter().map(|unit| unit.text.as_str()).collect();
+                                command_client.speech(&speech_text.join(" "), info.input.lang(), SpeechPriority::Syllable)?;
+                                let (text, pos) = info.input.subtelop();
+                                command_client.subtelop(&text, pos)?;
                             }
-                        }
-                        if info.pos >= info.words_romaji.len() {
-                            return self.unload_pict(command_client);
-                        }
-
+                            let (text, pos) = info.input.telop();
+                            command_client.telop(&text, pos)?;
 
-                        /*
-                        if info.pos >= info.words_romaji.len() {
-                            info.subpos += 1;
-                            command_client.subtelop(&info.words_ja, info.subpos);
-                            command_client.telop(&info.words_romaji, info.pos);
-                            command_client.speech(&info.words_ja.chars().nth(info.subpos-1).unwrap().to_string(), "ja");
-                            return self.unload_pict(command_client);
-                        } else {
-                            let next_target = info.words_romaji.chars().nth(info.pos).unwrap();
-                            if next_target == '_' {
-                                info.pos += 1;
-                                info.subpos += 1;
-                                command_client.speech(&info.words_ja.chars().nth(info.subpos-1).unwrap().to_string(), "ja");
-                                command_client.subtelop(&info.words_ja, info.subpos);
-                                command_client.telop(&info.words_romaji, info.pos-1);
-                            
-                                
-                            }else{
-                                command_client.telop(&info.words_romaji, info.pos);
+                            if info.input.is_finished() {
+                                return self.unload_pict(command_client, collection);
                             }
-                        }
-                        */
-                    } else {
-                        info.typo += 1;
+                        },
+                        InputResult::Miss => {
+                            info.typo += 1;
+                            command_client.play(sound::TYPO)?;
+                            if let Some(expected_key) = expected_key {
+                                stats.record_miss(expected_key, &syllable, input_ch);
+                            }
+                        },
+                        InputResult::Finished => {},
                     }
-                    
                 },
                 TypingStatus::IDLING => {}
             }
         }
-        None
+        Ok(None)
     }
 
-    fn handle_periodical_event(&mut self, command_client: &CommandClient) -> bool{
+    fn handle_periodical_event(&mut self, command_client: &CommandClient) -> Result<bool, String>{
         if let Some(info) = &mut self.typing_info {
             match info.status {
                 TypingStatus::TYPING => {},
                 TypingStatus::IDLING => {
-                    if info.start_time.elapsed().as_millis() > 3000 {
+                    let elapsed = info.start_time.elapsed().as_millis();
+                    let announced = info.announcement.as_ref().is_none_or(|handle| handle.is_finished());
+                    if (elapsed > Self::IDLING_MSEC && announced) || elapsed > Self::MAX_IDLING_MSEC {
                         return self.load_pict(command_client);
                     }
                 }
@@ -340,55 +440,286 @@ impl TypingController {
         } else {
             return self.load_pict(command_client);
         }
-        true
+        Ok(true)
     }
 
 }
 
 impl Controller for TypingController {
-    fn handle_key_event(&mut self, context: &mut ControlContext, keyevent: &UIKeyEvent) -> ControlState {
+    fn handle_key_event(&mut self, context: &mut ControlContext, keyevent: &UIKeyEvent) -> Result<ControlState, String> {
         match *keyevent {
             UIKeyEvent::Char(ch) => {
-                if let Some(result) = self.handle_input_char(ch, &context.command_client) {
+                if let Some(result) = self.handle_input_char(ch, &context.command_client, &mut context.stats, &mut context.collection)? {
                     context.results.push(result);
                 }
             },
             _ => {}
         }
         
-        ControlState::EXECUTING
+        Ok(ControlState::EXECUTING)
     }
 
-    fn handle_timer_event(&mut self, context: &mut ControlContext) -> ControlState{
-        if self.handle_periodical_event(&context.command_client) {
-            ControlState::EXECUTING
+    fn handle_timer_event(&mut self, context: &mut ControlContext) -> Result<ControlState, String>{
+        if self.handle_periodical_event(&context.command_client)? {
+            Ok(ControlState::EXECUTING)
         } else {
-            ControlState::FINISHED(ControlMode::TITLE)
+            Ok(ControlState::FINISHED(ControlMode::RESULT))
+        }
+    }
+}
+
+/// shows the summary of the round and the slowest creatures one page at a time
+struct ResultController {
+    pages: Vec<ResultPage>,
+    page: usize,
+    page_time: Instant,
+}
+
+struct ResultPage {
+    filename: String,
+    telop: String,
+    subtelop: String,
+}
+
+impl ResultController {
+    const PAGE_MSEC: u128 = 3000;
+    const NUM_OF_SLOWEST: usize = 3;
+
+    fn new(context: &mut ControlContext) -> Result<Self, String> {
+        context.command_client.stop_sound(Some(sound::AMBIENT))?;
+        let pict_manager = PictManager::new(&context.pict_dir);
+        let title = pict_manager.get_title_by_id("T01.png").map(|pict| pict_manager.get_pict_path(pict)).unwrap_or_default();
+
+        let record = RoundRecord::new(&context.player, context.typing_mode, context.results.split_off(0), std::mem::take(&mut context.stats));
+        let summary = record.summary();
+        let saved = record.results.is_empty()
+            || append_record(&context.history_file, &record).is_ok();
+
+        let mut pages = vec![ResultPage {
+            filename: title,
+            telop: format!("TIME {}S", summary.total_time / 1000),
+            subtelop: format!("{:.0}KPM {:.0}%{}", summary.kpm, summary.accuracy * 100.0, if saved { "" } else { " NOT SAVED" }),
+        }];
+        for (i, result) in record.slowest(Self::NUM_OF_SLOWEST).iter().enumerate() {
+            pages.push(ResultPage {
+                filename: result.filepath.to_string(),
+                telop: format!("SLOW{} {:.1}S", i + 1, result.time as f32 / 1000.0),
+                subtelop: result.words.to_string(),
+            });
+        }
+
+        let controller = ResultController {
+            pages,
+            page: 0,
+            page_time: Instant::now(),
+        };
+        controller.show_page(&context.command_client)?;
+        Ok(controller)
+    }
+
+    fn show_page(&self, command_client: &CommandClient) -> Result<(), String> {
+        let page = &self.pages[self.page];
+        command_client.title(&page.filename)?;
+        command_client.telop(&page.telop, 0)?;
+        command_client.subtelop(&page.subtelop, 0)
+    }
+}
+
+impl Controller for ResultController {
+    fn handle_key_event(&mut self, _context: &mut ControlContext, keyevent: &UIKeyEvent) -> Result<ControlState, String> {
+        match *keyevent {
+            UIKeyEvent::Char(' ') => Ok(ControlState::FINISHED(ControlMode::TITLE)),
+            _ => Ok(ControlState::EXECUTING),
+        }
+    }
+
+    fn handle_timer_event(&mut self, context: &mut ControlContext) -> Result<ControlState, String> {
+        if self.page_time.elapsed().as_millis() > Self::PAGE_MSEC {
+            self.page = (self.page + 1) % self.pages.len();
+            self.page_time = Instant::now();
+            self.show_page(&context.command_client)?;
+        }
+        Ok(ControlState::EXECUTING)
+    }
+}
+
+/// pages through every creature. the ones not caught yet are shown as silhouettes
+struct GalleryController {
+    pict_manager: PictManager,
+    index: usize,
+}
+
+impl GalleryController {
+    fn new(context: &mut ControlContext) -> Result<Self, String> {
+        let controller = GalleryController {
+            pict_manager: PictManager::new(&context.pict_dir),
+            index: 0,
+        };
+        controller.show_pict(context)?;
+        Ok(controller)
+    }
+
+    fn show_pict(&self, context: &ControlContext) -> Result<(), String> {
+        let command_client = &context.command_client;
+        if let Some(pict) = self.pict_manager.get_pict(self.index) {
+            let filename = self.pict_manager.get_pict_path(pict);
+            let number = format!("NO.{}", self.index + 1);
+            match context.collection.get(&pict.id) {
+                Some(record) => {
+                    command_client.title(&filename)?;
+                    command_client.telop(&format!("{} {}", number, pict.en), 0)?;
+                    command_client.subtelop(&format!("{} {:.1}S {}MISS", pict.ja, record.best_time as f32 / 1000.0, record.fewest_typos), 0)?;
+                },
+                None => {
+                    command_client.silhouette(&filename)?;
+                    command_client.telop(&format!("{} ?????", number), 0)?;
+                    command_client.subtelop(&format!("{}/{} CAUGHT", context.collection.len(), self.pict_manager.get_pict_len()), 0)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Controller for GalleryController {
+    fn handle_key_event(&mut self, context: &mut ControlContext, keyevent: &UIKeyEvent) -> Result<ControlState, String> {
+        let len = self.pict_manager.get_pict_len();
+        match *keyevent {
+            UIKeyEvent::Char(' ') => return Ok(ControlState::FINISHED(ControlMode::TITLE)),
+            UIKeyEvent::Right => self.index = (self.index + 1) % len,
+            UIKeyEvent::Left => self.index = (self.index + len - 1) % len,
+            _ => return Ok(ControlState::EXECUTING),
         }
+        self.show_pict(context)?;
+        Ok(ControlState::EXECUTING)
+    }
+
+    fn handle_timer_event(&mut self, _context: &mut ControlContext) -> Result<ControlState, String> {
+        Ok(ControlState::EXECUTING)
     }
 }
 
+/// the romaji of the first creature of the round. the series is reversed so that it comes last
+#[cfg(test)]
+pub fn first_name_keys(config: &GameConfig) -> Vec<char> {
+    let pict_manager = PictManager::new(&config.pict_dir);
+    let series = pict_manager.index_series(&config.round, config.typing_mode, config.num_of_targets);
+    let pict = pict_manager.get_pict(*series.last().unwrap()).unwrap();
+    let mut input = generate_word_input(config.typing_mode, pict);
+    let mut keys = Vec::new();
+    while !input.is_finished() {
+        let key = input.expected_keys()[0];
+        input.input(key);
+        keys.push(key);
+    }
+    keys
+}
+
 #[test]
 fn controller_works() {
-    let funcs = super::uifuncs::generate_uifuncs(super::uifuncs::UIFuncsType::DEBUG);
-    //let funcs = generate_uifuncs(UIFuncsType::DEBUG);
-    //let graphics = funcs.get_graphics();
-    let config = super::stage::StageConfig {
-        stage_wxh: (400, 120),
-        aa_width: 250, //250
+    use super::audiofuncs::{AudioCall, AudioFuncsType, generate_audiofuncs};
+    use super::uifuncs::DebugUIFuncs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = GameConfig {
         framerate: 10,
-    };
-    let audio = super::audiofuncs::generate_audiofuncs(super::audiofuncs::AudioFuncsType::OsCommand);
-    let color_config = super::command::ColorConfig {
-        normal: super::uifuncs::RenderColor::Black,
-        normal_bg: super::uifuncs::RenderColor::White,
-        info: super::uifuncs::RenderColor::Blue,
-        info_bg: super::uifuncs::RenderColor::White,
+        pict_dir: String::from("./drawings/"),
+        num_of_targets: 1,
+        history_file: dir.path().join("history.jsonl").to_str().unwrap().to_string(),
+        collection_dir: dir.path().join("collection").to_str().unwrap().to_string(),
+        ..GameConfig::default()
     };
 
-    super::controller::control(funcs, color_config, audio, "/Users/shizuku/drawings/", config, 2);
+    let keys = first_name_keys(&config);
+
+    // start a round, type the name after the creature appeared and leave after the result
+    let mut script = vec![(300, UIKeyEvent::Char(' '))];
+    script.extend(keys.iter().enumerate().map(|(i, key)| (if i == 0 { 500 } else { 30 }, UIKeyEvent::Char(*key))));
+    script.push((4500, UIKeyEvent::ESC));
+    let funcs = Box::new(DebugUIFuncs::scripted(script));
+    let audio = generate_audiofuncs(AudioFuncsType::Recording, &config.speech, &config.sound, super::sound::SoundLibrary::new());
+    let log = audio.audio_log().unwrap();
+
+    super::controller::control(funcs, audio, &config).unwrap();
+
+    let calls: Vec<AudioCall> = log.lock().unwrap().iter().map(|event| event.call.clone()).collect();
+    let speeches: Vec<(String, String)> = calls.iter().filter_map(|call| match call {
+        AudioCall::Speak { text, lang, .. } => Some((text.to_string(), lang.to_string())),
+        _ => None,
+    }).collect();
+    let (caught, syllables) = speeches.split_last().unwrap();
+    assert_eq!(caught, &(String::from("I caught MEGAMOUSE SHARK!"), String::from("en")));
+    // the name and then its kana one by one
+    assert_eq!(syllables[0], (String::from("メガマウスザメ"), String::from("ja")));
+    assert!(syllables.iter().all(|(_, lang)| lang == "ja"));
+    let spoken: String = syllables[1..].iter().map(|(text, _)| text.as_str()).collect();
+    assert_eq!(spoken, "メガマウスザメ");
+
+    let count = |expected: AudioCall| calls.iter().filter(|call| **call == expected).count();
+    assert_eq!(count(AudioCall::Play(String::from(super::sound::CLICK))), keys.len());
+    assert_eq!(count(AudioCall::Play(String::from(super::sound::CATCH))), 1);
+    assert_eq!(count(AudioCall::Stop(String::from(super::sound::AMBIENT))), 1);
+
+    let records = super::history::load_records(&config.history_file).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].results[0].words, "MEGAMOUSE SHARK");
+    assert!(Collection::load(&config.collection_dir, &config.player).unwrap().get("001_megamouse_shark.png").is_some());
+}
 
+#[test]
+fn next_seed_works() {
+    let mut rng = StdRng::seed_from_u64(0);
+    // the last round is played again
+    assert_eq!(next_seed(None, Some(42), &mut rng), Some(42));
+    assert_eq!(next_seed(Some(42), Some(42), &mut rng), None);
+    let seed = next_seed(None, None, &mut rng).unwrap();
+    assert!(seed < 10000);
+    assert_eq!(next_seed(None, None, &mut StdRng::seed_from_u64(0)), next_seed(None, None, &mut StdRng::seed_from_u64(0)));
+}
 
+#[test]
+fn catch_is_saved_before_the_result_works() {
+    use super::audiofuncs::{AudioFuncsType, generate_audiofuncs};
+    use super::uifuncs::DebugUIFuncs;
 
+    let dir = tempfile::tempdir().unwrap();
+    let config = GameConfig {
+        framerate: 10,
+        pict_dir: String::from("./drawings/"),
+        num_of_targets: 2,
+        history_file: dir.path().join("history.jsonl").to_str().unwrap().to_string(),
+        collection_dir: dir.path().join("collection").to_str().unwrap().to_string(),
+        ..GameConfig::default()
+    };
+    let keys = first_name_keys(&config);
+
+    // catch the first creature and leave in the middle of the round
+    let mut script = vec![(300, UIKeyEvent::Char(' '))];
+    script.extend(keys.iter().enumerate().map(|(i, key)| (if i == 0 { 500 } else { 30 }, UIKeyEvent::Char(*key))));
+    script.push((300, UIKeyEvent::ESC));
+    let funcs = Box::new(DebugUIFuncs::scripted(script));
+    let audio = generate_audiofuncs(AudioFuncsType::Null, &config.speech, &config.sound, super::sound::SoundLibrary::new());
+    super::controller::control(funcs, audio, &config).unwrap();
+
+    assert!(super::history::load_records(&config.history_file).unwrap_or_default().is_empty());
+    assert!(Collection::load(&config.collection_dir, &config.player).unwrap().get("001_megamouse_shark.png").is_some());
 }
 
+#[test]
+fn broken_collection_is_kept_works() {
+    use super::audiofuncs::{AudioFuncsType, generate_audiofuncs};
+    use super::uifuncs::DebugUIFuncs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = GameConfig {
+        collection_dir: dir.path().to_str().unwrap().to_string(),
+        ..GameConfig::default()
+    };
+    let collection_file = dir.path().join(format!("{}.json", config.player));
+    std::fs::write(&collection_file, "{\"caught\": ").unwrap();
+
+    let funcs = Box::new(DebugUIFuncs::scripted(vec![(0, UIKeyEvent::ESC)]));
+    let audio = generate_audiofuncs(AudioFuncsType::Null, &config.speech, &config.sound, super::sound::SoundLibrary::new());
+    assert!(super::controller::control(funcs, audio, &config).is_err());
+    assert_eq!(std::fs::read_to_string(&collection_file).unwrap(), "{\"caught\": ");
+}
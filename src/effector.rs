@@ -1,4 +1,5 @@
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use rand::rngs::StdRng;
 use image2ascii::{Char2DArray, CharPosition};
 
 #[derive(Debug, PartialEq)]
@@ -20,8 +21,7 @@ pub trait Effector {
     fn update(&mut self, data: &mut Char2DArray, original_data: &Char2DArray) -> EffectorStatus;
 }
 
-pub fn generate_appear_effector_randomly(duration: u32, framerate: u32) -> Box<dyn Effector> {
-    let mut rng = thread_rng();
+pub fn generate_appear_effector_randomly(duration: u32, framerate: u32, rng: &mut StdRng) -> Box<dyn Effector> {
     let direction:usize = rng.gen_range(0,4);
     let direction = match direction {
         0 => FadeDirection::UP,
@@ -33,8 +33,7 @@ pub fn generate_appear_effector_randomly(duration: u32, framerate: u32) -> Box<d
     generate_effector(EffectorType::FADEIN(direction), duration, framerate)
 }
 
-pub fn generate_disappear_effector_randomly(duration: u32, framerate: u32) -> Box<dyn Effector> {
-    let mut rng = thread_rng();
+pub fn generate_disappear_effector_randomly(duration: u32, framerate: u32, rng: &mut StdRng) -> Box<dyn Effector> {
     let direction:usize = rng.gen_range(0,4);
     let direction = match direction {
         0 => FadeDirection::UP,
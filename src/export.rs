@@ -0,0 +1,239 @@
+//! export ascii art and stage frames as a self-contained html page or an svg image
+
+use image2ascii::{Char2DArray, CharPosition, image2ascii};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use super::cellart::{Cell, Cell2DArray, image2colors};
+use super::config::GameConfig;
+use super::raster::RasterConfig;
+use super::uifuncs::RenderColor;
+
+/// picked by the extension of the file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExportFormat {
+    Text,
+    Html,
+    Svg,
+}
+
+impl ExportFormat {
+    /// .html, .htm and .svg. anything else is plain text
+    pub fn from_path(path: &str) -> ExportFormat {
+        let ext = Path::new(path).extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        match ext.as_str() {
+            "html" | "htm" => ExportFormat::Html,
+            "svg" => ExportFormat::Svg,
+            _ => ExportFormat::Text,
+        }
+    }
+}
+
+type Rgb = (u8, u8, u8);
+
+/// consecutive cells of a row with the same colours
+struct Run {
+    start: usize,
+    text: String,
+    fg: Rgb,
+    bg: Rgb,
+}
+
+fn runs(row: &[Cell], colors: &RasterConfig) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for (x, cell) in row.iter().enumerate() {
+        let fg = colors.rgb(cell.fg, colors.foreground);
+        let bg = colors.rgb(cell.bg, colors.background);
+        match runs.last_mut() {
+            Some(run) if run.fg == fg && run.bg == bg => run.text.push(cell.ch),
+            _ => runs.push(Run { start: x, text: cell.ch.to_string(), fg, bg }),
+        }
+    }
+    runs
+}
+
+/// the colours most of the cells have. they become the colours of the page so that only the others need markup
+fn page_colors(cells: &[Vec<Cell>], colors: &RasterConfig) -> (Rgb, Rgb) {
+    let mut counts: HashMap<(Rgb, Rgb), usize> = HashMap::new();
+    for cell in cells.iter().flatten() {
+        *counts.entry((colors.rgb(cell.fg, colors.foreground), colors.rgb(cell.bg, colors.background))).or_insert(0) += 1;
+    }
+    counts.into_iter().max_by_key(|(pair, count)| (*count, *pair)).map(|(pair, _)| pair)
+        .unwrap_or((colors.foreground, colors.background))
+}
+
+fn hex((r, g, b): Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// characters only. trailing spaces are dropped like `Frame::text`
+pub fn to_text(cells: &[Vec<Cell>]) -> String {
+    cells.iter()
+        .map(|row| row.iter().map(|cell| cell.ch).collect::<String>().trim_end().to_string())
+        .collect::<Vec<String>>()
+        .join("\n") + "\n"
+}
+
+/// a page with the cells in a `<pre>` block. colours are kept when it is printed
+pub fn to_html(cells: &[Vec<Cell>], title: &str, colors: &RasterConfig) -> String {
+    let (page_fg, page_bg) = page_colors(cells, colors);
+    let mut body = String::new();
+    for row in cells.iter() {
+        for run in runs(row, colors) {
+            let mut style = Vec::new();
+            if run.fg != page_fg {
+                style.push(format!("color:{}", hex(run.fg)));
+            }
+            if run.bg != page_bg {
+                style.push(format!("background:{}", hex(run.bg)));
+            }
+            if style.is_empty() {
+                body.push_str(&escape(&run.text));
+            } else {
+                body.push_str(&format!("<span style=\"{}\">{}</span>", style.join(";"), escape(&run.text)));
+            }
+        }
+        body.push('\n');
+    }
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ margin: 0; background: {bg}; }}
+pre {{ margin: 0; padding: 1em; font-family: "DejaVu Sans Mono", Menlo, Consolas, monospace; line-height: 1; color: {fg}; background: {bg}; }}
+* {{ -webkit-print-color-adjust: exact; print-color-adjust: exact; }}
+</style>
+</head>
+<body>
+<pre>
+{body}</pre>
+</body>
+</html>
+"#, title = escape(title), fg = hex(page_fg), bg = hex(page_bg), body = body)
+}
+
+/// an image with a text element per row. each character is placed on the grid of `colors.cell_wxh`
+pub fn to_svg(cells: &[Vec<Cell>], colors: &RasterConfig) -> String {
+    let (cell_w, cell_h) = colors.cell_wxh;
+    let columns = cells.iter().map(|row| row.len()).max().unwrap_or(0);
+    let (width, height) = (columns as u32 * cell_w, cells.len() as u32 * cell_h);
+    let (page_fg, page_bg) = page_colors(cells, colors);
+    let mut rects = String::new();
+    let mut texts = String::new();
+    for (y, row) in cells.iter().enumerate() {
+        let top = y as u32 * cell_h;
+        let mut spans = String::new();
+        for run in runs(row, colors) {
+            let left = run.start as u32 * cell_w;
+            let len = run.text.chars().count() as u32;
+            if run.bg != page_bg {
+                rects.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n", left, top, len * cell_w, cell_h, hex(run.bg)));
+            }
+            if run.text.trim().is_empty() {
+                continue;
+            }
+            let xs: Vec<String> = (0..len).map(|i| (left + i * cell_w).to_string()).collect();
+            let fill = if run.fg == page_fg { String::new() } else { format!(" fill=\"{}\"", hex(run.fg)) };
+            spans.push_str(&format!("<tspan x=\"{}\"{}>{}</tspan>", xs.join(" "), fill, escape(&run.text)));
+        }
+        if !spans.is_empty() {
+            // the baseline is near the bottom of the cell so that descenders stay in it
+            texts.push_str(&format!("<text y=\"{}\">{}</text>\n", top + cell_h * 4 / 5, spans));
+        }
+    }
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">
+<rect width="{w}" height="{h}" fill="{bg}"/>
+{rects}<g font-family="DejaVu Sans Mono, Menlo, Consolas, monospace" font-size="{size}" fill="{fg}" xml:space="preserve">
+{texts}</g>
+</svg>
+"#, w = width, h = height, bg = hex(page_bg), fg = hex(page_fg), size = cell_h, rects = rects, texts = texts)
+}
+
+/// write the cells in the format of the extension of `out_file`
+pub fn export_cells(out_file: &str, cells: &[Vec<Cell>], title: &str, colors: &RasterConfig) -> Result<(), String> {
+    let data = match ExportFormat::from_path(out_file) {
+        ExportFormat::Text => to_text(cells),
+        ExportFormat::Html => to_html(cells, title, colors),
+        ExportFormat::Svg => to_svg(cells, colors),
+    };
+    fs::write(out_file, data).map_err(|e| format!("can not write {}: {}", out_file, e))
+}
+
+/// every character gets the same colours
+pub fn export_chars(out_file: &str, chars: &Char2DArray, fg: RenderColor, bg: RenderColor, title: &str, colors: &RasterConfig) -> Result<(), String> {
+    export_cells(out_file, &Cell2DArray::from_chars(chars, fg, bg).buffer, title, colors)
+}
+
+/// convert the image to ascii art aa_width characters wide in the normal colours of the config,
+/// or in the colours of the image with color_art
+pub fn export_image(image_file: &str, out_file: &str, config: &GameConfig) -> Result<(), String> {
+    let chars = image2ascii(image_file, config.aa_width as u32, None, None)?;
+    let mut cells = Cell2DArray::from_chars(&chars, config.color.normal, config.color.normal_bg);
+    if config.color_art {
+        let colors = image2colors(image_file, chars.width(), chars.height())?;
+        cells.overwrite_fg(&chars, &colors, CharPosition { x: 0, y: 0 }, None);
+    }
+    let title = Path::new(image_file).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    export_cells(out_file, &cells.buffer, &title, &config.raster)
+}
+
+#[cfg(test)]
+fn export_test_cells() -> Vec<Vec<Cell>> {
+    let cell = |ch: char, fg: RenderColor| Cell { ch, fg, bg: RenderColor::White };
+    vec![
+        vec![cell('<', RenderColor::Black), cell('&', RenderColor::Black), cell('R', RenderColor::Red)],
+        vec![cell(' ', RenderColor::Black), cell('a', RenderColor::Black), Cell { ch: 'b', fg: RenderColor::Black, bg: RenderColor::Byte(1, 2, 3) }],
+    ]
+}
+
+#[test]
+fn export_format_works() {
+    assert_eq!(ExportFormat::from_path("shark.HTML"), ExportFormat::Html);
+    assert_eq!(ExportFormat::from_path("shark.htm"), ExportFormat::Html);
+    assert_eq!(ExportFormat::from_path("./out/shark.svg"), ExportFormat::Svg);
+    assert_eq!(ExportFormat::from_path("shark.txt"), ExportFormat::Text);
+    assert_eq!(ExportFormat::from_path("shark"), ExportFormat::Text);
+}
+
+#[test]
+fn to_html_works() {
+    let html = to_html(&export_test_cells(), "a <shark>", &RasterConfig::default());
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>a &lt;shark&gt;</title>"));
+    // black on white is the most common so it is the colour of the page
+    assert!(html.contains("color: #000000; background: #ffffff;"));
+    assert!(html.contains("<pre>\n&lt;&amp;<span style=\"color:#cd0000\">R</span>\n a<span style=\"background:#010203\">b</span>\n</pre>"));
+}
+
+#[test]
+fn to_svg_works() {
+    let config = RasterConfig { cell_wxh: (10, 20), ..RasterConfig::default() };
+    let svg = to_svg(&export_test_cells(), &config);
+    assert!(svg.contains(r#"width="30" height="40" viewBox="0 0 30 40""#));
+    assert!(svg.contains(r##"<rect width="30" height="40" fill="#ffffff"/>"##));
+    assert!(svg.contains(r##"<rect x="20" y="20" width="10" height="20" fill="#010203"/>"##));
+    assert!(svg.contains(r##"<text y="16"><tspan x="0 10">&lt;&amp;</tspan><tspan x="20" fill="#cd0000">R</tspan></text>"##));
+    assert!(svg.contains(r##"<text y="36"><tspan x="0 10"> a</tspan><tspan x="20">b</tspan></text>"##));
+}
+
+#[test]
+fn export_image_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = GameConfig { aa_width: 40, color_art: true, ..GameConfig::default() };
+    for name in ["shark.html", "shark.svg", "shark.txt"].iter() {
+        let out_file = dir.path().join(name).to_string_lossy().to_string();
+        export_image("./drawings/001_megamouse_shark.png", &out_file, &config).unwrap();
+        assert!(!fs::read_to_string(&out_file).unwrap().is_empty());
+    }
+    let text = fs::read_to_string(dir.path().join("shark.txt")).unwrap();
+    assert!(text.lines().all(|line| line.chars().count() <= 40));
+    assert!(fs::read_to_string(dir.path().join("shark.html")).unwrap().contains("<title>001_megamouse_shark</title>"));
+    assert!(export_image("./drawings/no_such_file.png", &dir.path().join("x.html").to_string_lossy(), &config).is_err());
+}
@@ -0,0 +1,169 @@
+//! typing results of each round persisted as json lines
+
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::wordinput::TypingMode;
+use super::keystats::TypingStats;
+
+/// result of typing one pict
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypingResult {
+    pub filepath: String,
+    pub words: String,
+    /// msec from the appearance to the catch
+    pub time: u128,
+    pub typo: u32,
+    /// keys which were accepted
+    pub keystrokes: u32,
+}
+
+/// one line of the history file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundRecord {
+    /// unix time (sec) when the round finished
+    pub timestamp: u64,
+    #[serde(default)]
+    pub player: String,
+    pub typing_mode: TypingMode,
+    pub results: Vec<TypingResult>,
+    #[serde(default)]
+    pub stats: TypingStats,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RoundSummary {
+    pub total_time: u128,
+    pub keystrokes: u32,
+    pub typo: u32,
+    /// keystrokes per minute
+    pub kpm: f32,
+    /// ratio of correct keys (0.0 - 1.0)
+    pub accuracy: f32,
+}
+
+impl RoundRecord {
+    pub fn new(player: &str, typing_mode: TypingMode, results: Vec<TypingResult>, stats: TypingStats) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        RoundRecord {
+            timestamp,
+            player: player.to_string(),
+            typing_mode,
+            results,
+            stats,
+        }
+    }
+
+    pub fn summary(&self) -> RoundSummary {
+        let total_time: u128 = self.results.iter().map(|r| r.time).sum();
+        let keystrokes: u32 = self.results.iter().map(|r| r.keystrokes).sum();
+        let typo: u32 = self.results.iter().map(|r| r.typo).sum();
+        let kpm = if total_time > 0 {
+            keystrokes as f32 * 60000.0 / total_time as f32
+        } else {
+            0.0
+        };
+        let accuracy = if keystrokes + typo > 0 {
+            keystrokes as f32 / (keystrokes + typo) as f32
+        } else {
+            0.0
+        };
+        RoundSummary {
+            total_time,
+            keystrokes,
+            typo,
+            kpm,
+            accuracy,
+        }
+    }
+
+    /// results which took the longest time first
+    pub fn slowest(&self, count: usize) -> Vec<&TypingResult> {
+        let mut results: Vec<&TypingResult> = self.results.iter().collect();
+        results.sort_by_key(|r| std::cmp::Reverse(r.time));
+        results.truncate(count);
+        results
+    }
+}
+
+/// append a round to the history file
+pub fn append_record(history_file: &str, record: &RoundRecord) -> Result<(), String> {
+    let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(history_file)
+        .map_err(|e| format!("can not open history file {}: {}", history_file, e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("can not write history file {}: {}", history_file, e))
+}
+
+/// load every round in the history file. broken lines are skipped
+pub fn load_records(history_file: &str) -> Result<Vec<RoundRecord>, String> {
+    let file = match File::open(history_file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("can not open history file {}: {}", history_file, e)),
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("can not read history file {}: {}", history_file, e))?;
+        if let Ok(record) = serde_json::from_str(&line) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// key statistics of every round the player played in the typing mode
+pub fn load_player_stats(history_file: &str, player: &str, typing_mode: TypingMode) -> Result<TypingStats, String> {
+    let mut stats = TypingStats::new();
+    for record in load_records(history_file)? {
+        if record.player == player && record.typing_mode == typing_mode {
+            stats.merge(&record.stats);
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+fn typing_result(words: &str, time: u128, typo: u32, keystrokes: u32) -> TypingResult {
+    TypingResult {
+        filepath: format!("{}.png", words),
+        words: words.to_string(),
+        time,
+        typo,
+        keystrokes,
+    }
+}
+
+#[test]
+fn round_summary_works() {
+    let record = RoundRecord::new("player", TypingMode::Japanese, vec![
+        typing_result("A", 20000, 1, 10),
+        typing_result("B", 40000, 3, 30),
+    ], TypingStats::new());
+    let summary = record.summary();
+    assert_eq!(summary.total_time, 60000);
+    assert_eq!(summary.keystrokes, 40);
+    assert_eq!(summary.kpm, 40.0);
+    assert_eq!(summary.accuracy, 40.0 / 44.0);
+    let slowest: Vec<&str> = record.slowest(1).iter().map(|r| r.words.as_str()).collect();
+    assert_eq!(slowest, ["B"]);
+}
+
+#[test]
+fn history_file_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let history_file = dir.path().join("history.jsonl");
+    let history_file = history_file.to_str().unwrap();
+    assert_eq!(load_records(history_file).unwrap().len(), 0);
+
+    let mut stats = TypingStats::new();
+    stats.record_miss('k', "カ", 'l');
+    append_record(history_file, &RoundRecord::new("alice", TypingMode::Japanese, vec![typing_result("A", 1000, 0, 5)], stats.clone())).unwrap();
+    append_record(history_file, &RoundRecord::new("alice", TypingMode::English, vec![typing_result("B", 2000, 1, 5)], stats.clone())).unwrap();
+    append_record(history_file, &RoundRecord::new("bob", TypingMode::Japanese, vec![typing_result("C", 2000, 1, 5)], stats.clone())).unwrap();
+    let records = load_records(history_file).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[1].typing_mode, TypingMode::English);
+    assert_eq!(records[1].results[0].words, "B");
+    assert_eq!(load_player_stats(history_file, "alice", TypingMode::Japanese).unwrap(), stats);
+}
@@ -0,0 +1,137 @@
+//! hits, misses and latency of each key and each syllable
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// statistics of one key or one syllable
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyStat {
+    pub hits: u32,
+    pub misses: u32,
+    /// keys typed instead of the expected one and how many times
+    pub typed_instead: BTreeMap<String, u32>,
+    /// msec from the previous key summed over every hit
+    pub total_latency: u64,
+}
+
+impl KeyStat {
+    pub fn attempts(&self) -> u32 {
+        self.hits + self.misses
+    }
+
+    /// ratio of misses (0.0 - 1.0)
+    pub fn miss_rate(&self) -> f32 {
+        if self.attempts() > 0 {
+            self.misses as f32 / self.attempts() as f32
+        } else {
+            0.0
+        }
+    }
+
+    /// average msec from the previous key
+    pub fn average_latency(&self) -> f32 {
+        if self.hits > 0 {
+            self.total_latency as f32 / self.hits as f32
+        } else {
+            0.0
+        }
+    }
+
+    fn hit(&mut self, latency: u64) {
+        self.hits += 1;
+        self.total_latency += latency;
+    }
+
+    fn miss(&mut self, typed: char) {
+        self.misses += 1;
+        *self.typed_instead.entry(typed.to_string()).or_insert(0) += 1;
+    }
+
+    fn merge(&mut self, other: &KeyStat) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.total_latency += other.total_latency;
+        for (key, count) in &other.typed_instead {
+            *self.typed_instead.entry(key.to_string()).or_insert(0) += count;
+        }
+    }
+}
+
+/// statistics of a round or of every round of a player.
+/// a syllable is a kana unit in japanese mode and a letter in english mode
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TypingStats {
+    pub keys: BTreeMap<String, KeyStat>,
+    pub syllables: BTreeMap<String, KeyStat>,
+}
+
+impl TypingStats {
+    /// syllables need this many attempts before they are judged
+    pub const MIN_ATTEMPTS: u32 = 3;
+
+    pub fn new() -> Self {
+        TypingStats::default()
+    }
+
+    /// `key` was accepted while typing `syllable`
+    pub fn record_hit(&mut self, key: char, syllable: &str, latency: u64) {
+        self.keys.entry(key.to_ascii_lowercase().to_string()).or_default().hit(latency);
+        if !syllable.is_empty() {
+            self.syllables.entry(syllable.to_string()).or_default().hit(latency);
+        }
+    }
+
+    /// `typed` was rejected while `expected` was waited for
+    pub fn record_miss(&mut self, expected: char, syllable: &str, typed: char) {
+        let typed = typed.to_ascii_lowercase();
+        self.keys.entry(expected.to_ascii_lowercase().to_string()).or_default().miss(typed);
+        if !syllable.is_empty() {
+            self.syllables.entry(syllable.to_string()).or_default().miss(typed);
+        }
+    }
+
+    pub fn merge(&mut self, other: &TypingStats) {
+        for (key, stat) in &other.keys {
+            self.keys.entry(key.to_string()).or_default().merge(stat);
+        }
+        for (syllable, stat) in &other.syllables {
+            self.syllables.entry(syllable.to_string()).or_default().merge(stat);
+        }
+    }
+
+    /// syllables with the highest miss rate first. slower ones win a tie
+    pub fn weakest_syllables(&self, count: usize) -> Vec<String> {
+        let mut syllables: Vec<(&String, &KeyStat)> = self.syllables.iter()
+            .filter(|(_, stat)| stat.attempts() >= Self::MIN_ATTEMPTS)
+            .collect();
+        syllables.sort_by(|(_, a), (_, b)| {
+            b.miss_rate().partial_cmp(&a.miss_rate()).unwrap()
+                .then(b.average_latency().partial_cmp(&a.average_latency()).unwrap())
+        });
+        syllables.iter().take(count).map(|(syllable, _)| syllable.to_string()).collect()
+    }
+}
+
+#[test]
+fn typingstats_works() {
+    let mut stats = TypingStats::new();
+    for _ in 0..3 {
+        stats.record_hit('k', "カ", 200);
+        stats.record_hit('a', "カ", 100);
+        stats.record_hit('s', "シ", 400);
+    }
+    stats.record_miss('h', "シ", 'i');
+    stats.record_miss('t', "ツ", 'u');
+    assert_eq!(stats.keys["k"].hits, 3);
+    assert_eq!(stats.keys["h"].typed_instead["i"], 1);
+    assert_eq!(stats.syllables["カ"].average_latency(), 150.0);
+    assert_eq!(stats.syllables["シ"].miss_rate(), 0.25);
+    // "ツ" has too few attempts to be judged
+    assert_eq!(stats.weakest_syllables(5), ["シ", "カ"]);
+
+    let mut total = TypingStats::new();
+    total.merge(&stats);
+    total.merge(&stats);
+    assert_eq!(total.syllables["シ"].misses, 2);
+    assert_eq!(total.keys["h"].typed_instead["i"], 2);
+}
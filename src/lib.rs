@@ -6,9 +6,27 @@ pub mod background;
 pub mod stage;
 pub mod controller;
 pub mod command;
+pub mod remote;
+pub mod script;
+pub mod session;
 pub mod uifuncs;
+pub mod capture;
+pub mod asciicast;
+pub mod raster;
+pub mod export;
 pub mod pict;
 pub mod character;
 pub mod audiofuncs;
+pub mod speechqueue;
+pub mod sound;
+pub mod synth;
+pub mod config;
+pub mod romaji;
+pub mod wordinput;
+pub mod history;
+pub mod keystats;
+pub mod collection;
+pub mod renderer;
+pub mod cellart;
 
 
@@ -1,60 +1,199 @@
 
-/*
-mod uifuncs;
-use uifuncs::{generate_uifuncs, UIFuncs, UIFuncsType, RenderColor};
-mod stage;
-use stage::{StageConfig};
-mod command;
-use command::{start_command_server};
-
-use std::thread;
-use std::time::{Duration};
-*/
-
-use std::thread;
-use std::time::Duration;
-
-use aquatyping;
+use std::env;
+use std::path::Path;
+use std::process;
+
 use aquatyping::uifuncs;
-use aquatyping::stage;
-use aquatyping::command;
 use aquatyping::audiofuncs;
 use aquatyping::controller;
+use aquatyping::remote;
+use aquatyping::asciicast::AsciicastUIFuncs;
+use aquatyping::raster::RasterUIFuncs;
+use aquatyping::export;
+use aquatyping::script;
+use aquatyping::session::{self, ReplayUIFuncs};
+use aquatyping::pict::PictManager;
+use aquatyping::sound::SoundLibrary;
+use aquatyping::synth;
+use aquatyping::config::{self, CliAction, GameConfig};
 
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match config::parse_args(&args) {
+        Ok(CliAction::Play(config)) => config,
+        Ok(CliAction::Check(config)) => {
+            process::exit(check(&config));
+        },
+        Ok(CliAction::Serve(config)) => {
+            let funcs = uifuncs::generate_uifuncs(config.ui, config.color_depth);
+            let sounds = SoundLibrary::load(&config.pict_dir).unwrap_or_default();
+            let audio = audiofuncs::generate_audiofuncs(config.audio, &config.speech, &config.sound, sounds);
+            if let Err(e) = remote::serve(funcs, audio, &config) {
+                eprintln!("aquatyping: {}", e);
+                process::exit(1);
+            }
+            return;
+        },
+        Ok(CliAction::Script(config, script)) => {
+            let funcs = uifuncs::generate_uifuncs(config.ui, config.color_depth);
+            let sounds = SoundLibrary::load(&config.pict_dir).unwrap_or_default();
+            let audio = audiofuncs::generate_audiofuncs(config.audio, &config.speech, &config.sound, sounds);
+            if let Err(e) = script::play_script(funcs, audio, &config, &script) {
+                eprintln!("aquatyping: {}", e);
+                process::exit(1);
+            }
+            return;
+        },
+        Ok(CliAction::Replay(backends, session)) => {
+            let dir = match tempfile::tempdir() {
+                Ok(dir) => dir,
+                Err(e) => {
+                    eprintln!("aquatyping: {}", e);
+                    process::exit(1);
+                }
+            };
+            let mut config = match session.replay_config(&dir) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("aquatyping: {}", e);
+                    process::exit(1);
+                }
+            };
+            config.ui = backends.ui;
+            config.audio = backends.audio;
+            config.color_depth = backends.color_depth;
+            config.render_stats = backends.render_stats;
+            config.cast_file = backends.cast_file.clone();
+            config.frames_path = backends.frames_path.clone();
+            config.raster = backends.raster.clone();
+            let funcs = uifuncs::generate_uifuncs(config.ui, config.color_depth);
+            let funcs = Box::new(ReplayUIFuncs::new(funcs, &session));
+            play(funcs, &config);
+            return;
+        },
+        Ok(CliAction::Sounds(out_dir)) => {
+            match synth::dump_effects(Path::new(&out_dir)) {
+                Ok(paths) => paths.iter().for_each(|path| println!("{}", path.display())),
+                Err(e) => {
+                    eprintln!("aquatyping: {}", e);
+                    process::exit(2);
+                }
+            }
+            return;
+        },
+        Ok(CliAction::Export(config, image_file, out_file)) => {
+            match export::export_image(&image_file, &out_file, &config) {
+                Ok(()) => println!("{}", out_file),
+                Err(e) => {
+                    eprintln!("aquatyping: {}", e);
+                    process::exit(1);
+                }
+            }
+            return;
+        },
+        Ok(CliAction::Help) => {
+            println!("{}", config::USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("aquatyping: {}", e);
+            eprintln!("try 'aquatyping --help' for more information.");
+            process::exit(2);
+        }
+    };
 
+    let funcs = uifuncs::generate_uifuncs(config.ui, config.color_depth);
+    match &config.record_file {
+        Some(record_file) => match session::start_recording(funcs, &config, record_file) {
+            Ok((funcs, config)) => play(funcs, &config),
+            Err(e) => {
+                eprintln!("aquatyping: {}", e);
+                process::exit(1);
+            }
+        },
+        None => play(funcs, &config),
+    }
+}
 
-    let funcs = uifuncs::generate_uifuncs(uifuncs::UIFuncsType::TUI);
-    //let funcs = generate_uifuncs(UIFuncsType::DEBUG);
-    //let graphics = funcs.get_graphics();
-    let config = stage::StageConfig {
-        //stage_wxh: (400, 120),
-        stage_wxh: (640, 180), //140
-        aa_width: 270, //250
-        framerate: 10,
+fn play(funcs: Box<dyn uifuncs::UIFuncs>, config: &GameConfig) {
+    let funcs: Box<dyn uifuncs::UIFuncs> = match &config.cast_file {
+        Some(cast_file) => match AsciicastUIFuncs::create(funcs, cast_file, config.stage_wxh, config.color_depth) {
+            Ok(funcs) => Box::new(funcs),
+            Err(e) => {
+                eprintln!("aquatyping: {}", e);
+                process::exit(1);
+            }
+        },
+        None => funcs,
     };
-    let audio = audiofuncs::generate_audiofuncs(audiofuncs::AudioFuncsType::OsCommand);
-    let color_config = command::ColorConfig {
-        normal: uifuncs::RenderColor::Black,
-        normal_bg: uifuncs::RenderColor::White,
-        info: uifuncs::RenderColor::Blue,
-        info_bg: uifuncs::RenderColor::White,
+    let (funcs, frames): (Box<dyn uifuncs::UIFuncs>, _) = match &config.frames_path {
+        Some(frames_path) => match RasterUIFuncs::create(funcs, frames_path, &config.raster) {
+            Ok(funcs) => {
+                let recorder = funcs.recorder();
+                (Box::new(funcs), Some((frames_path, recorder)))
+            },
+            Err(e) => {
+                eprintln!("aquatyping: {}", e);
+                process::exit(1);
+            }
+        },
+        None => (funcs, None),
     };
+    let sounds = SoundLibrary::load(&config.pict_dir).unwrap_or_default();
+    let audio = audiofuncs::generate_audiofuncs(config.audio, &config.speech, &config.sound, sounds);
+    let render_stats = funcs.render_stats();
+
+    let result = controller::control(funcs, audio, config);
 
-    controller::control(funcs, color_config, audio, "./drawings/", config, 120);
-    /*
-    let command_client = command::start_command_server(graphics, config, color_config, audio);
-    command_client.appear("/Users/shizuku/drawings/001_megamouse_shark.png", "MEGAMOUSE SHARK");
-    command_client.telop("MEGAMOUSE SHARK", 0);
-    thread::sleep(Duration::from_secs(10));
-    command_client.telop("MEGAMOUSE SHARK", 1);
-    thread::sleep(Duration::from_secs(10));
-    command_client.telop("MEGAMOUSE SHARK", 2);
-    thread::sleep(Duration::from_secs(10));
-    command_client.disappear();
-    thread::sleep(Duration::from_secs(20));
-    */
-    
- 
-}
\ No newline at end of file
+    if config.render_stats {
+        if let Some(render_stats) = render_stats {
+            let stats = render_stats.lock().unwrap();
+            eprintln!("frames: {} total: {} bytes average: {:.0} bytes/frame max: {} bytes/frame",
+                stats.frames, stats.total_bytes, stats.average_frame_bytes(), stats.max_frame_bytes);
+        }
+    }
+    if let Some((frames_path, recorder)) = frames {
+        match recorder.lock().unwrap().finish() {
+            Ok(count) => eprintln!("{} frames written to {}", count, frames_path),
+            Err(e) => eprintln!("aquatyping: {}", e),
+        }
+    }
+    if let Err(e) = result {
+        eprintln!("aquatyping: {}", e);
+        process::exit(1);
+    }
+}
+
+fn check(config: &GameConfig) -> i32 {
+    for (lang, voice) in config.speech.detect_voices() {
+        match voice {
+            Some(voice) => println!("speech {}: {}", lang, voice.requires.join(", ")),
+            None => println!("speech {}: no tts command installed (silent)", lang),
+        }
+    }
+    match config.sound.detect_player() {
+        Some(player) => println!("sound: {}", player.requires.join(", ")),
+        None => println!("sound: no wav player installed (silent)"),
+    }
+    let sound_problems: Vec<String> = match SoundLibrary::load(&config.pict_dir) {
+        Ok(sounds) => sounds.missing().iter().map(|name| format!("sound {} is missing", name)).collect(),
+        Err(e) => vec![e],
+    };
+    for problem in sound_problems.iter() {
+        println!("{}", problem);
+    }
+    let pict_manager = match PictManager::load(&config.pict_dir) {
+        Ok(pict_manager) => pict_manager,
+        Err(e) => {
+            eprintln!("aquatyping: {}", e);
+            return 2;
+        }
+    };
+    let reports = pict_manager.check_romaji();
+    for report in reports.iter() {
+        println!("{}", report);
+    }
+    println!("{} of {} picts have problems", reports.len(), pict_manager.get_pict_len());
+    if reports.is_empty() && sound_problems.is_empty() { 0 } else { 1 }
+}
@@ -2,18 +2,107 @@ use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::path::Path;
 use std::io::BufReader;
-use rand::{thread_rng, Rng};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::distributions::WeightedIndex;
+use super::romaji::{kana_to_romaji, check_romaji, to_katakana};
+use super::wordinput::TypingMode;
 
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Pict {
     pub id: String,
     pub ja: String,
-    pub romaji: String,
+    /// hand-written romaji with syllable markers. generated from `ja` when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub romaji: Option<String>,
     pub en: String,
     pub tags: Vec<String>,
 }
 
+impl Pict {
+    /// romaji with syllable markers
+    pub fn romaji(&self) -> String {
+        match &self.romaji {
+            Some(romaji) => romaji.to_string(),
+            None => kana_to_romaji(&self.ja),
+        }
+    }
+
+    /// number of keys needed to type the name
+    pub fn difficulty(&self, typing_mode: TypingMode) -> usize {
+        match typing_mode {
+            TypingMode::Japanese => self.romaji().chars().filter(|ch| ch.is_ascii_alphabetic()).count(),
+            TypingMode::English => self.en.chars().filter(|ch| *ch != ' ').count(),
+        }
+    }
+
+    fn matches_tags(&self, round: &RoundConfig) -> bool {
+        (round.include_tags.is_empty() || self.tags.iter().any(|tag| round.include_tags.contains(tag)))
+            && !self.tags.iter().any(|tag| round.exclude_tags.contains(tag))
+    }
+}
+
+/// order of the picts in a round
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PictOrder {
+    /// in the order of picts_info.json
+    Sequential,
+    /// picked at random. the same pict can come again soon
+    Shuffled,
+    /// every pict comes once before any pict comes again
+    ShuffledNoRepeat,
+    /// picked at random. picts with longer names come more often
+    Weighted,
+}
+
+impl PictOrder {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PictOrder::Sequential => "sequential",
+            PictOrder::Shuffled => "shuffled",
+            PictOrder::ShuffledNoRepeat => "shuffled-no-repeat",
+            PictOrder::Weighted => "weighted",
+        }
+    }
+
+    /// used to cycle the order on the title screen
+    pub fn next(&self) -> PictOrder {
+        match self {
+            PictOrder::Sequential => PictOrder::Shuffled,
+            PictOrder::Shuffled => PictOrder::ShuffledNoRepeat,
+            PictOrder::ShuffledNoRepeat => PictOrder::Weighted,
+            PictOrder::Weighted => PictOrder::Sequential,
+        }
+    }
+}
+
+/// which picts a round uses and in which order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoundConfig {
+    /// picts need at least one of these tags. every pict is used when empty
+    pub include_tags: Vec<String>,
+    /// picts with any of these tags are not used
+    pub exclude_tags: Vec<String>,
+    pub order: PictOrder,
+    /// the same seed gives the same series. random when omitted
+    pub seed: Option<u64>,
+}
+
+impl Default for RoundConfig {
+    fn default() -> Self {
+        RoundConfig {
+            include_tags: Vec::new(),
+            exclude_tags: Vec::new(),
+            order: PictOrder::Sequential,
+            seed: None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct PictInfo {
     picts: Vec<Pict>,
@@ -66,17 +155,67 @@ impl PictManager {
         }
     }
     */
-    pub fn index_series(&self, size: usize) -> Vec<usize> {
-        //let mut index_series = generate_random_index_series(self.pict_info.picts.len(), size);
-        let mut index_series = generate_index_series(self.pict_info.picts.len(), size);
-        
-        //let mut n = index_series.split_off(67);
-        //n.reverse();
-        //n
+    /// indexes of the picts used in a round, in reverse order so that `pop` gives the first one
+    pub fn index_series(&self, round: &RoundConfig, typing_mode: TypingMode, size: usize) -> Vec<usize> {
+        let indexes = self.filter(round);
+        if indexes.is_empty() {
+            return Vec::new();
+        }
+        let mut rng = match round.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut index_series = match round.order {
+            PictOrder::Sequential => generate_index_series(&indexes, size),
+            PictOrder::Shuffled => generate_random_index_series(&indexes, size, &mut rng),
+            PictOrder::ShuffledNoRepeat => generate_shuffled_index_series(&indexes, size, &mut rng),
+            PictOrder::Weighted => {
+                let weights: Vec<usize> = indexes.iter().map(|i| self.pict_info.picts[*i].difficulty(typing_mode).max(1)).collect();
+                generate_weighted_index_series(&indexes, &weights, size, &mut rng)
+            },
+        };
+        index_series.reverse();
+        index_series
+    }
+
+    /// indexes of the picts which pass the tag filters of the round
+    pub fn filter(&self, round: &RoundConfig) -> Vec<usize> {
+        self.pict_info.picts.iter().enumerate()
+            .filter(|(_, pict)| pict.matches_tags(round))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// every tag used by the picts in alphabetical order
+    pub fn get_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.pict_info.picts.iter().flat_map(|pict| pict.tags.iter().cloned()).collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
 
+    /// series for a practice round. picts which contain more of the weak syllables come first.
+    /// falls back to `index_series` when no pict contains them
+    pub fn practice_series(&self, round: &RoundConfig, typing_mode: TypingMode, weak_syllables: &[String], size: usize) -> Vec<usize> {
+        let mut scores: Vec<(usize, usize)> = Vec::new();
+        for i in self.filter(round) {
+            let pict = &self.pict_info.picts[i];
+            let words = match typing_mode {
+                TypingMode::Japanese => to_katakana(&pict.ja),
+                TypingMode::English => pict.en.to_string(),
+            };
+            let score: usize = weak_syllables.iter().map(|syllable| words.matches(syllable.as_str()).count()).sum();
+            if score > 0 {
+                scores.push((score, i));
+            }
+        }
+        if scores.is_empty() {
+            return self.index_series(round, typing_mode, size);
+        }
+        scores.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        let mut index_series: Vec<usize> = scores.iter().map(|(_, i)| *i).cycle().take(size).collect();
         index_series.reverse();
         index_series
-        
     }
 
     pub fn get_pict(&self, index: usize) -> Option<&Pict> {
@@ -98,54 +237,72 @@ impl PictManager {
         String::from(path_buf.to_str().unwrap())
     }
 
+    /// report picts whose hand-written romaji disagrees with the kana name
+    pub fn check_romaji(&self) -> Vec<String> {
+        let mut reports = Vec::new();
+        for pict in &self.pict_info.picts {
+            if let Some(romaji) = &pict.romaji {
+                let problems = check_romaji(&pict.ja, romaji);
+                if !problems.is_empty() {
+                    reports.push(format!("{} {} '{}' (generated: '{}')\n    {}",
+                        pict.id, pict.ja, romaji, kana_to_romaji(&pict.ja), problems.join("\n    ")));
+                }
+            }
+        }
+        reports
+    }
+
     pub fn get_pict_len(&self) -> usize {
         self.pict_info.picts.len()
     }
 
     pub fn new(pict_dir: &str) -> Self{
-        
+        PictManager::load(pict_dir).unwrap()
+    }
+
+    pub fn load(pict_dir: &str) -> Result<Self, String> {
         let pict_dir = String::from(pict_dir);
         let filepath = Path::new(&pict_dir);
         let pict_json = filepath.join("picts_info.json");
 
-        let reader = BufReader::new(File::open(pict_json).unwrap());
-        let pict_info: PictInfo = serde_json::from_reader(reader).unwrap();
+        let file = File::open(&pict_json).map_err(|e| format!("can not open {}: {}", pict_json.display(), e))?;
+        let reader = BufReader::new(file);
+        let pict_info: PictInfo = serde_json::from_reader(reader).map_err(|e| format!("invalid {}: {}", pict_json.display(), e))?;
 
-        PictManager{
+        Ok(PictManager{
             pict_dir: pict_dir,
             pict_info: pict_info,
-        }
+        })
     }
 }
 
-fn generate_index_series(array_size: usize, series_size: usize) -> Vec<usize> {
-    let mut index_series: Vec<usize> = Vec::new();
-    loop {
-        for i in 0..array_size {
-            index_series.push(i);
-            if index_series.len() >= series_size {
-                return index_series;
-            }
-        }
-    }
+fn generate_index_series(indexes: &[usize], series_size: usize) -> Vec<usize> {
+    indexes.iter().copied().cycle().take(series_size).collect()
 }
 
-fn generate_random_index_series(array_size: usize, series_size: usize) -> Vec<usize> {
+fn generate_random_index_series(indexes: &[usize], series_size: usize, rng: &mut StdRng) -> Vec<usize> {
+    (0..series_size).map(|_| indexes[rng.gen_range(0, indexes.len())]).collect()
+}
+
+fn generate_shuffled_index_series(indexes: &[usize], series_size: usize, rng: &mut StdRng) -> Vec<usize> {
     let mut index_series: Vec<usize> = Vec::new();
-    loop {
-        let mut tmp: Vec<(u32, usize)> = Vec::new();
-        for i in 0..array_size {
-            let rnd: u32 = thread_rng().gen();
-            tmp.push((rnd, i));
-        }
-        tmp.sort();
-        for (_, index) in tmp.iter(){
-            index_series.push(*index);
-            if index_series.len() >= series_size {
-                return index_series;
-            }
+    while index_series.len() < series_size {
+        let mut shuffled = indexes.to_vec();
+        shuffled.shuffle(rng);
+        // the same pict should not come twice in a row at the border of two shuffles
+        if shuffled.len() > 1 && index_series.last() == shuffled.first() {
+            let last = shuffled.len() - 1;
+            shuffled.swap(0, last);
         }
+        index_series.extend(shuffled);
     }
+    index_series.truncate(series_size);
+    index_series
+}
+
+fn generate_weighted_index_series(indexes: &[usize], weights: &[usize], series_size: usize, rng: &mut StdRng) -> Vec<usize> {
+    let distribution = WeightedIndex::new(weights).unwrap();
+    (0..series_size).map(|_| indexes[rng.sample(&distribution)]).collect()
 }
 
 /*
@@ -162,3 +319,40 @@ fn pictmanager_works() {
 */
 
 
+
+#[test]
+fn practice_series_works() {
+    let manager = PictManager::new("./drawings/");
+    let weak = vec![String::from("ザ")];
+    let round = RoundConfig::default();
+    let mut series = manager.practice_series(&round, TypingMode::Japanese, &weak, 3);
+    assert_eq!(series.len(), 3);
+    while let Some(i) = series.pop() {
+        assert!(manager.get_pict(i).unwrap().ja.contains('ザ'));
+    }
+    let weak = vec![String::from("no such syllable")];
+    assert_eq!(manager.practice_series(&round, TypingMode::English, &weak, 3), manager.index_series(&round, TypingMode::English, 3));
+}
+
+#[test]
+fn index_series_works() {
+    let manager = PictManager::new("./drawings/");
+    let mut round = RoundConfig::default();
+    let series = manager.index_series(&round, TypingMode::Japanese, 3);
+    assert_eq!(series, [2, 1, 0]);
+
+    round.include_tags = vec![String::from("ancient")];
+    round.exclude_tags = vec![String::from("aquatic")];
+    let ancient = manager.filter(&round);
+    assert_eq!(ancient.len(), 3);
+
+    round.order = PictOrder::ShuffledNoRepeat;
+    round.seed = Some(7);
+    let series = manager.index_series(&round, TypingMode::Japanese, 4);
+    assert_eq!(series, manager.index_series(&round, TypingMode::Japanese, 4));
+    assert!(series.iter().all(|i| ancient.contains(i)));
+    assert!(series.windows(2).all(|w| w[0] != w[1]));
+
+    round.include_tags = vec![String::from("no such tag")];
+    assert!(manager.index_series(&round, TypingMode::Japanese, 4).is_empty());
+}
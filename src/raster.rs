@@ -0,0 +1,420 @@
+//! rasterise the frames of the stage with the bundled font into png files or an animated gif
+
+use crossbeam_channel as channel;
+use image::gif::GifEncoder;
+use image::{Delay, Rgba, RgbaImage};
+use image2ascii::Char2DArray;
+use rusttype::{Font, Scale, point};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use super::capture::{Capture, SharedCapture};
+use super::cellart::{Cell, Cell2DArray};
+use super::renderer::SharedRenderStats;
+use super::uifuncs::{RenderColor, UIFuncs, UIGraphics, UIKeyEvent};
+
+const BUNDLED_FONT: &[u8] = include_bytes!("../font/OpenSans-Regular.ttf");
+
+/// how characters and colours look in the images
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RasterConfig {
+    /// pixels of a character
+    pub cell_wxh: (u32, u32),
+    /// colours of RenderColor::Default
+    pub foreground: (u8, u8, u8),
+    pub background: (u8, u8, u8),
+    /// black, red, green, yellow, blue, magenta, cyan and white
+    pub palette: [(u8, u8, u8); 8],
+    /// ttf file used instead of the bundled font
+    pub font: Option<String>,
+}
+
+impl Default for RasterConfig {
+    fn default() -> Self {
+        RasterConfig {
+            cell_wxh: (8, 16),
+            foreground: (229, 229, 229),
+            background: (0, 0, 0),
+            palette: [(0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (255, 255, 255)],
+            font: None,
+        }
+    }
+}
+
+impl RasterConfig {
+    /// `default` is the colour of RenderColor::Default
+    pub fn rgb(&self, color: RenderColor, default: (u8, u8, u8)) -> (u8, u8, u8) {
+        let palette = &self.palette;
+        match color {
+            RenderColor::Black => palette[0],
+            RenderColor::Red => palette[1],
+            RenderColor::Green => palette[2],
+            RenderColor::Yellow => palette[3],
+            RenderColor::Blue => palette[4],
+            RenderColor::Magenta => palette[5],
+            RenderColor::Cyan => palette[6],
+            RenderColor::White => palette[7],
+            RenderColor::Byte(r, g, b) => (r, g, b),
+            RenderColor::Default => default,
+        }
+    }
+}
+
+/// draws cells into images. the coverage of each glyph is kept once it is drawn
+pub struct Rasterizer {
+    font: Font<'static>,
+    config: RasterConfig,
+    scale: Scale,
+    baseline: f32,
+    glyphs: HashMap<char, Vec<f32>>,
+}
+
+impl Rasterizer {
+    pub fn new(config: &RasterConfig) -> Result<Self, String> {
+        let (cell_w, cell_h) = config.cell_wxh;
+        if cell_w == 0 || cell_h == 0 {
+            return Err(format!("cell size {}x{} must not be 0", cell_w, cell_h));
+        }
+        let font = match &config.font {
+            Some(font_file) => {
+                let data = fs::read(font_file).map_err(|e| format!("can not read font {}: {}", font_file, e))?;
+                Font::try_from_vec(data).ok_or_else(|| format!("invalid font {}", font_file))?
+            },
+            None => Font::try_from_bytes(BUNDLED_FONT).ok_or_else(|| String::from("invalid bundled font"))?,
+        };
+        // the font is not monospaced. it is made small enough for a wide letter to fit in the cell
+        let wide = font.glyph('M').scaled(Scale::uniform(cell_h as f32)).h_metrics().advance_width;
+        let size = if wide > cell_w as f32 { cell_h as f32 * cell_w as f32 / wide } else { cell_h as f32 };
+        let scale = Scale::uniform(size);
+        let v_metrics = font.v_metrics(scale);
+        let baseline = (cell_h as f32 - (v_metrics.ascent - v_metrics.descent)) / 2.0 + v_metrics.ascent;
+        Ok(Rasterizer { font, config: config.clone(), scale, baseline, glyphs: HashMap::new() })
+    }
+
+    /// size of the image of `columns` x `rows` characters
+    pub fn image_size(&self, columns: usize, rows: usize) -> (u32, u32) {
+        (columns as u32 * self.config.cell_wxh.0, rows as u32 * self.config.cell_wxh.1)
+    }
+
+    pub fn render_chars(&mut self, chars: &Char2DArray, fg: RenderColor, bg: RenderColor) -> RgbaImage {
+        self.render_cells(&Cell2DArray::from_chars(chars, fg, bg).buffer)
+    }
+
+    /// rows may have different lengths. the image is as wide as the longest one
+    pub fn render_cells(&mut self, cells: &[Vec<Cell>]) -> RgbaImage {
+        let columns = cells.iter().map(|row| row.len()).max().unwrap_or(0);
+        let (width, height) = self.image_size(columns, cells.len());
+        let (cell_w, cell_h) = self.config.cell_wxh;
+        let (r, g, b) = self.config.background;
+        let mut image = RgbaImage::from_pixel(width.max(1), height.max(1), Rgba([r, g, b, 255]));
+        for (y, row) in cells.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                let fg = self.config.rgb(cell.fg, self.config.foreground);
+                let bg = self.config.rgb(cell.bg, self.config.background);
+                let coverage = self.glyph(cell.ch);
+                for py in 0..cell_h {
+                    for px in 0..cell_w {
+                        let c = coverage[(py * cell_w + px) as usize];
+                        let mix = |f: u8, b: u8| (b as f32 + (f as f32 - b as f32) * c).round() as u8;
+                        image.put_pixel(x as u32 * cell_w + px, y as u32 * cell_h + py,
+                            Rgba([mix(fg.0, bg.0), mix(fg.1, bg.1), mix(fg.2, bg.2), 255]));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    fn glyph(&mut self, ch: char) -> &[f32] {
+        let (cell_w, cell_h) = self.config.cell_wxh;
+        let (font, scale, baseline) = (&self.font, self.scale, self.baseline);
+        self.glyphs.entry(ch).or_insert_with(|| {
+            let mut coverage = vec![0.0; (cell_w * cell_h) as usize];
+            let scaled = font.glyph(ch).scaled(scale);
+            let left = (cell_w as f32 - scaled.h_metrics().advance_width) / 2.0;
+            let glyph = scaled.positioned(point(left, baseline));
+            if let Some(bounds) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, v| {
+                    let (x, y) = (bounds.min.x + gx as i32, bounds.min.y + gy as i32);
+                    // a glyph wider than the cell is cut off at its edge
+                    if x >= 0 && y >= 0 && (x as u32) < cell_w && (y as u32) < cell_h {
+                        coverage[(y as u32 * cell_w + x as u32) as usize] = v;
+                    }
+                });
+            }
+            coverage
+        })
+    }
+}
+
+enum FrameOutput {
+    Png(PathBuf),
+    Gif(GifEncoder<BufWriter<File>>),
+}
+
+/// writes a png file per frame into a directory, or an animated gif when the path ends with .gif
+pub struct FrameWriter {
+    rasterizer: Rasterizer,
+    output: FrameOutput,
+    path: String,
+    frames: usize,
+    /// a gif frame is written when the next one comes because its delay is not known before
+    pending: Option<(Vec<Vec<Cell>>, Duration)>,
+    gif_size: Option<(u32, u32)>,
+}
+
+impl FrameWriter {
+    pub fn create(path: &str, config: &RasterConfig) -> Result<Self, String> {
+        let rasterizer = Rasterizer::new(config)?;
+        let output = if is_gif(path) {
+            let file = File::create(path).map_err(|e| format!("can not create gif {}: {}", path, e))?;
+            FrameOutput::Gif(GifEncoder::new(BufWriter::new(file)))
+        } else {
+            fs::create_dir_all(path).map_err(|e| format!("can not create frame dir {}: {}", path, e))?;
+            FrameOutput::Png(PathBuf::from(path))
+        };
+        Ok(FrameWriter { rasterizer, output, path: path.to_string(), frames: 0, pending: None, gif_size: None })
+    }
+
+    /// `time` is when the frame was drawn since the recording started. gif frames which do not
+    /// change are merged into one
+    pub fn push(&mut self, cells: Vec<Vec<Cell>>, time: Duration) -> Result<(), String> {
+        match &self.output {
+            FrameOutput::Png(dir) => {
+                let file = dir.join(format!("frame_{:05}.png", self.frames));
+                self.rasterizer.render_cells(&cells).save(&file)
+                    .map_err(|e| format!("can not write frame {}: {}", file.display(), e))?;
+                self.frames += 1;
+            },
+            FrameOutput::Gif(_) => {
+                match self.pending.take() {
+                    Some((pending, start)) if pending == cells => self.pending = Some((pending, start)),
+                    Some((pending, start)) => {
+                        self.write_gif_frame(&pending, time.saturating_sub(start))?;
+                        self.pending = Some((cells, time));
+                    },
+                    None => self.pending = Some((cells, time)),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// write the last frame and close the file. returns the number of frames written
+    pub fn finish(mut self, time: Duration) -> Result<usize, String> {
+        if let Some((pending, start)) = self.pending.take() {
+            self.write_gif_frame(&pending, time.saturating_sub(start))?;
+        }
+        Ok(self.frames)
+    }
+
+    fn write_gif_frame(&mut self, cells: &[Vec<Cell>], delay: Duration) -> Result<(), String> {
+        let mut image = self.rasterizer.render_cells(cells);
+        // a gif is as big as its first frame. frames after a resize are cut or padded to it
+        let (width, height) = *self.gif_size.get_or_insert(image.dimensions());
+        if image.dimensions() != (width, height) {
+            let (r, g, b) = self.rasterizer.config.background;
+            let mut canvas = RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]));
+            image::imageops::replace(&mut canvas, &image, 0, 0);
+            image = canvas;
+        }
+        if let FrameOutput::Gif(encoder) = &mut self.output {
+            let delay = Delay::from_numer_denom_ms(delay.as_millis().min(u32::MAX as u128) as u32, 1);
+            encoder.encode_frame(image::Frame::from_parts(image, 0, 0, delay))
+                .map_err(|e| format!("can not write gif {}: {}", self.path, e))?;
+            self.frames += 1;
+        }
+        Ok(())
+    }
+}
+
+/// rasterises the frames on its own thread so that the game does not wait for the encoder
+pub struct FrameRecorder {
+    frame_tx: Option<channel::Sender<(Vec<Vec<Cell>>, Duration)>>,
+    writer: Option<thread::JoinHandle<Result<usize, String>>>,
+    start: Instant,
+}
+
+impl FrameRecorder {
+    pub fn start(path: &str, config: &RasterConfig) -> Result<Self, String> {
+        let mut writer = FrameWriter::create(path, config)?;
+        let start = Instant::now();
+        let (frame_tx, frame_rx) = channel::unbounded::<(Vec<Vec<Cell>>, Duration)>();
+        let handle = thread::spawn(move || {
+            for (cells, time) in frame_rx.iter() {
+                writer.push(cells, time)?;
+            }
+            writer.finish(start.elapsed())
+        });
+        Ok(FrameRecorder { frame_tx: Some(frame_tx), writer: Some(handle), start })
+    }
+
+    pub fn send(&self, cells: Vec<Vec<Cell>>) {
+        if let Some(frame_tx) = &self.frame_tx {
+            // the writer has stopped on an error which finish reports
+            let _ = frame_tx.send((cells, self.start.elapsed()));
+        }
+    }
+
+    /// stop taking frames and wait for the ones sent to be written
+    pub fn finish(&mut self) -> Result<usize, String> {
+        self.frame_tx = None;
+        match self.writer.take() {
+            Some(handle) => handle.join().map_err(|_| String::from("frame writer panicked"))?,
+            None => Err(String::from("frames are already finished")),
+        }
+    }
+}
+
+pub type SharedFrameRecorder = Arc<Mutex<FrameRecorder>>;
+
+/// draws into the inner graphics and keeps the whole screen to rasterise it at each flush
+pub struct RasterGraphics {
+    inner: Box<dyn UIGraphics>,
+    screen: RefCell<Capture>,
+    recorder: SharedFrameRecorder,
+}
+
+impl UIGraphics for RasterGraphics {
+    fn draw_area(&self, color: &RenderColor, bgcolor: &RenderColor, rect: &Vec<Vec<char>>, offset: Option<(usize, usize)>) {
+        self.inner.draw_area(color, bgcolor, rect, offset);
+        let cells: Vec<Vec<Cell>> = rect.iter()
+            .map(|line| line.iter().map(|ch| Cell { ch: *ch, fg: *color, bg: *bgcolor }).collect())
+            .collect();
+        self.screen.borrow_mut().draw(&cells, offset);
+    }
+
+    fn draw_cells(&self, rect: &[Vec<Cell>], offset: Option<(usize, usize)>) {
+        self.inner.draw_cells(rect, offset);
+        self.screen.borrow_mut().draw(rect, offset);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+        self.recorder.lock().unwrap().send(self.screen.borrow().screen.cells.clone());
+    }
+
+    fn resize(&self, width: usize, height: usize) {
+        self.inner.resize(width, height);
+        self.screen.borrow_mut().resize(width, height);
+    }
+}
+
+/// wraps a backend so that whatever it draws is also rasterised into images
+pub struct RasterUIFuncs {
+    inner: Box<dyn UIFuncs>,
+    recorder: SharedFrameRecorder,
+}
+
+impl RasterUIFuncs {
+    pub fn create(inner: Box<dyn UIFuncs>, path: &str, config: &RasterConfig) -> Result<Self, String> {
+        let recorder = FrameRecorder::start(path, config)?;
+        Ok(RasterUIFuncs { inner, recorder: Arc::new(Mutex::new(recorder)) })
+    }
+
+    /// finish it after the game to write the last frame
+    pub fn recorder(&self) -> SharedFrameRecorder {
+        self.recorder.clone()
+    }
+}
+
+impl UIFuncs for RasterUIFuncs {
+    fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent> {
+        self.inner.start_keyevent_thread()
+    }
+
+    fn get_graphics(&self) -> Box<dyn UIGraphics> {
+        Box::new(RasterGraphics {
+            inner: self.inner.get_graphics(),
+            screen: RefCell::new(Capture::default()),
+            recorder: self.recorder.clone(),
+        })
+    }
+
+    fn render_stats(&self) -> Option<SharedRenderStats> {
+        self.inner.render_stats()
+    }
+
+    fn capture(&self) -> Option<SharedCapture> {
+        self.inner.capture()
+    }
+
+    fn resize_events(&mut self) -> Option<channel::Receiver<(usize, usize)>> {
+        self.inner.resize_events()
+    }
+}
+
+/// the frames are written to png files or a gif when the path ends with .gif
+pub fn is_gif(path: &str) -> bool {
+    Path::new(path).extension().map(|ext| ext.eq_ignore_ascii_case("gif")).unwrap_or(false)
+}
+
+#[test]
+fn rasterizer_works() {
+    let config = RasterConfig { cell_wxh: (8, 16), ..RasterConfig::default() };
+    let mut rasterizer = Rasterizer::new(&config).unwrap();
+    let chars = Char2DArray::from(vec![vec!['#', ' '], vec![' ', ' ']]);
+    let image = rasterizer.render_chars(&chars, RenderColor::Black, RenderColor::White);
+    assert_eq!(image.dimensions(), (16, 32));
+    // the blank cells are the background colour and the glyph is drawn dark
+    assert!((8..16).all(|x| (0..32).all(|y| image.get_pixel(x, y) == &Rgba([255, 255, 255, 255]))));
+    assert!((0..8).any(|x| (0..16).any(|y| image.get_pixel(x, y)[0] < 64)));
+
+    let cells = vec![vec![Cell { ch: ' ', fg: RenderColor::Default, bg: RenderColor::Byte(1, 2, 3) }]];
+    assert_eq!(rasterizer.render_cells(&cells).get_pixel(3, 3), &Rgba([1, 2, 3, 255]));
+    assert!(Rasterizer::new(&RasterConfig { cell_wxh: (0, 16), ..RasterConfig::default() }).is_err());
+}
+
+#[test]
+fn frame_writer_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = RasterConfig { cell_wxh: (4, 8), ..RasterConfig::default() };
+    let frame = |ch: char| vec![vec![Cell { ch, fg: RenderColor::Red, bg: RenderColor::Black }; 3]];
+
+    let png_dir = dir.path().join("frames").to_string_lossy().to_string();
+    let mut writer = FrameWriter::create(&png_dir, &config).unwrap();
+    writer.push(frame('a'), Duration::from_millis(0)).unwrap();
+    writer.push(frame('a'), Duration::from_millis(100)).unwrap();
+    assert_eq!(writer.finish(Duration::from_millis(200)).unwrap(), 2);
+    let png = image::open(Path::new(&png_dir).join("frame_00001.png")).unwrap();
+    assert_eq!((png.to_rgba().width(), png.to_rgba().height()), (12, 8));
+
+    // frames which do not change are one gif frame
+    let gif_file = dir.path().join("frames.gif").to_string_lossy().to_string();
+    assert!(is_gif(&gif_file));
+    let mut writer = FrameWriter::create(&gif_file, &config).unwrap();
+    writer.push(frame('a'), Duration::from_millis(0)).unwrap();
+    writer.push(frame('a'), Duration::from_millis(100)).unwrap();
+    writer.push(frame('b'), Duration::from_millis(200)).unwrap();
+    assert_eq!(writer.finish(Duration::from_millis(500)).unwrap(), 2);
+
+    use image::AnimationDecoder;
+    let decoder = image::gif::GifDecoder::new(File::open(&gif_file).unwrap()).unwrap();
+    let frames = decoder.into_frames().collect_frames().unwrap();
+    let delays: Vec<(u32, u32)> = frames.iter().map(|frame| frame.delay().numer_denom_ms()).collect();
+    assert_eq!(delays, [(200, 1), (300, 1)]);
+}
+
+#[test]
+fn raster_uifuncs_works() {
+    use super::capture::CaptureUIFuncs;
+    let dir = tempfile::tempdir().unwrap();
+    let png_dir = dir.path().join("frames").to_string_lossy().to_string();
+    let funcs = RasterUIFuncs::create(Box::new(CaptureUIFuncs::new()), &png_dir, &RasterConfig::default()).unwrap();
+    let graphics = funcs.get_graphics();
+    graphics.draw_area(&RenderColor::Black, &RenderColor::White, &vec![vec!['a', 'b']], None);
+    graphics.flush();
+    graphics.draw_cells(&[vec![Cell { ch: 'X', fg: RenderColor::Red, bg: RenderColor::White }]], Some((3, 1)));
+    graphics.flush();
+    assert_eq!(funcs.recorder().lock().unwrap().finish().unwrap(), 2);
+    assert_eq!(funcs.capture().unwrap().lock().unwrap().frames.len(), 2);
+    let last = image::open(Path::new(&png_dir).join("frame_00001.png")).unwrap().to_rgba();
+    assert_eq!(last.dimensions(), (32, 32));
+}
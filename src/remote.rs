@@ -0,0 +1,303 @@
+//! the command protocol as newline delimited json over a unix socket or localhost tcp
+
+use crossbeam_channel as channel;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use super::audiofuncs::AudioFuncs;
+use super::command::{Command, CommandClient, forward_resize_events, start_command_server};
+use super::config::GameConfig;
+use super::uifuncs::{UIFuncs, UIKeyEvent};
+
+/// where the display server listens. "unix:PATH" or "tcp:HOST:PORT" where the host is a loopback address
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+}
+
+impl ListenAddress {
+    pub fn parse(address: &str) -> Result<Self, String> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(String::from("unix socket path is empty"));
+            }
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+        }
+        let host_port = address.strip_prefix("tcp:").unwrap_or(address);
+        let socket_addr: SocketAddr = host_port.parse().map_err(|_| format!("invalid listen address '{}'", address))?;
+        // anybody on the network could drive the stage otherwise
+        if !socket_addr.ip().is_loopback() {
+            return Err(format!("listen address {} is not a loopback address", socket_addr));
+        }
+        Ok(ListenAddress::Tcp(socket_addr))
+    }
+}
+
+/// sent back for every line. ok means the command passed `Command::check` and was queued for the stage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply { ok: true, error: None }
+    }
+
+    fn error(error: String) -> Self {
+        Reply { ok: false, error: Some(error) }
+    }
+}
+
+/// run one json line. the command is returned to tell shutdown from the others
+pub fn handle_line(client: &CommandClient, line: &str) -> (Reply, Option<Command>) {
+    let command = match serde_json::from_str::<Command>(line) {
+        Ok(command) => command,
+        Err(e) => return (Reply::error(format!("invalid command: {}", e)), None),
+    };
+    if let Err(e) = command.check() {
+        return (Reply::error(e), None);
+    }
+    match client.send(command.clone()) {
+        Ok(()) => (Reply::ok(), Some(command)),
+        Err(e) => (Reply::error(e), None),
+    }
+}
+
+/// read commands until the peer closes the connection. returns true when a shutdown came
+fn handle_connection<R: BufRead, W: Write>(client: &CommandClient, reader: R, mut writer: W) -> bool {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (reply, command) = handle_line(client, &line);
+        let reply = serde_json::to_string(&reply).unwrap_or_default();
+        if writeln!(writer, "{}", reply).and_then(|_| writer.flush()).is_err() {
+            break;
+        }
+        if command == Some(Command::Shutdown) {
+            return true;
+        }
+    }
+    false
+}
+
+pub enum Listener {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener, PathBuf),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    const POLL_MSEC: u64 = 50;
+
+    pub fn bind(address: &ListenAddress) -> Result<Self, String> {
+        match address {
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                use std::os::unix::net::{UnixListener, UnixStream};
+                // a socket file left by a server which has died is removed. a live one is kept
+                if path.exists() && UnixStream::connect(path).is_err() {
+                    let _ = std::fs::remove_file(path);
+                }
+                let listener = UnixListener::bind(path).map_err(|e| format!("can not listen on {}: {}", path.display(), e))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                Ok(Listener::Unix(listener, path.to_path_buf()))
+            },
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => Err(String::from("unix sockets are not supported on this platform")),
+            ListenAddress::Tcp(socket_addr) => {
+                let listener = TcpListener::bind(socket_addr).map_err(|e| format!("can not listen on {}: {}", socket_addr, e))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                Ok(Listener::Tcp(listener))
+            },
+        }
+    }
+
+    /// the port is known here when 0 was given
+    pub fn local_address(&self) -> Result<ListenAddress, String> {
+        match self {
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(ListenAddress::Unix(path.to_path_buf())),
+            Listener::Tcp(listener) => listener.local_addr().map(ListenAddress::Tcp).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// accept connections until one of them sends shutdown or `stop_rx` receives something.
+    /// each connection runs in its own thread
+    pub fn run(self, client: CommandClient, stop_rx: channel::Receiver<()>) {
+        let (shutdown_tx, shutdown_rx) = channel::unbounded::<()>();
+        loop {
+            if shutdown_rx.try_recv().is_ok() || stop_rx.try_recv().is_ok() {
+                break;
+            }
+            let client = client.clone();
+            let shutdown_tx = shutdown_tx.clone();
+            let accepted = match &self {
+                #[cfg(unix)]
+                Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| {
+                    let _ = stream.set_nonblocking(false);
+                    stream.try_clone().map(|reader| thread::spawn(move || {
+                        if handle_connection(&client, BufReader::new(reader), stream) {
+                            let _ = shutdown_tx.send(());
+                        }
+                    }))
+                }),
+                Listener::Tcp(listener) => listener.accept().map(|(stream, _)| {
+                    let _ = stream.set_nonblocking(false);
+                    stream.try_clone().map(|reader| thread::spawn(move || {
+                        if handle_connection(&client, BufReader::new(reader), stream) {
+                            let _ = shutdown_tx.send(());
+                        }
+                    }))
+                }),
+            };
+            match accepted {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(Self::POLL_MSEC)),
+                _ => {},
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        match self {
+            #[cfg(unix)]
+            Listener::Unix(_, path) => {
+                let _ = std::fs::remove_file(path);
+            },
+            Listener::Tcp(_) => {},
+        }
+    }
+}
+
+/// use the stage as a display server driven by other programs until ESC is pressed or a shutdown command comes
+pub fn serve(mut uifuncs: Box<dyn UIFuncs>, audiofuncs: Box<dyn AudioFuncs>, config: &GameConfig) -> Result<(), String> {
+    let listener = Listener::bind(&ListenAddress::parse(&config.listen)?)?;
+    let graphics = uifuncs.get_graphics();
+    let keyevent_rx = uifuncs.start_keyevent_thread();
+    let client = start_command_server(graphics, config.stage_config(), config.color, audiofuncs);
+    forward_resize_events(uifuncs.resize_events(), &client);
+
+    let (stop_tx, stop_rx) = channel::unbounded::<()>();
+    let listener_client = client.clone();
+    let listener_thread = thread::spawn(move || listener.run(listener_client, stop_rx));
+    loop {
+        channel::select! {
+            recv(keyevent_rx) -> received => {
+                if let Ok(UIKeyEvent::ESC) | Err(_) = received {
+                    let _ = stop_tx.send(());
+                    break;
+                }
+            },
+            default(Duration::from_millis(100)) => {
+                if listener_thread.is_finished() {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = listener_thread.join();
+    // the server thread may have gone with a shutdown command already
+    let _ = client.shutdown();
+    Ok(())
+}
+
+#[test]
+fn listen_address_works() {
+    assert_eq!(ListenAddress::parse("unix:/tmp/aquatyping.sock"), Ok(ListenAddress::Unix(PathBuf::from("/tmp/aquatyping.sock"))));
+    assert_eq!(ListenAddress::parse("tcp:127.0.0.1:7878"), Ok(ListenAddress::Tcp("127.0.0.1:7878".parse().unwrap())));
+    assert_eq!(ListenAddress::parse("[::1]:7878"), Ok(ListenAddress::Tcp("[::1]:7878".parse().unwrap())));
+    assert!(ListenAddress::parse("tcp:0.0.0.0:7878").unwrap_err().contains("loopback"));
+    assert!(ListenAddress::parse("unix:").is_err());
+    assert!(ListenAddress::parse("localhost").is_err());
+}
+
+#[cfg(test)]
+fn recording_command_server() -> (CommandClient, super::audiofuncs::AudioLog) {
+    use super::audiofuncs::{AudioFuncsType, generate_audiofuncs};
+    let funcs = super::uifuncs::generate_uifuncs(super::uifuncs::UIFuncsType::DEBUG, super::renderer::ColorDepth::Auto);
+    let audio = generate_audiofuncs(AudioFuncsType::Recording, &Default::default(), &Default::default(), Default::default());
+    let log = audio.audio_log().unwrap();
+    let config = GameConfig { stage_wxh: (64, 18), aa_width: 32, ..GameConfig::default() };
+    (start_command_server(funcs.get_graphics(), config.stage_config(), config.color, audio), log)
+}
+
+#[test]
+fn remote_tcp_works() {
+    use std::net::TcpStream;
+
+    let (client, log) = recording_command_server();
+    let listener = Listener::bind(&ListenAddress::parse("tcp:127.0.0.1:0").unwrap()).unwrap();
+    let socket_addr = match listener.local_address().unwrap() {
+        ListenAddress::Tcp(socket_addr) => socket_addr,
+        other => panic!("unexpected address: {:?}", other),
+    };
+    let (_stop_tx, stop_rx) = channel::unbounded::<()>();
+    let listener_thread = thread::spawn(move || listener.run(client, stop_rx));
+
+    let stream = TcpStream::connect(socket_addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut request = |line: &str| -> Reply {
+        writeln!(writer, "{}", line).unwrap();
+        let mut reply = String::new();
+        reader.read_line(&mut reply).unwrap();
+        serde_json::from_str(&reply).unwrap()
+    };
+    assert_eq!(request(r#"{"command": "telop", "text": "HELLO", "pos": 2}"#), Reply::ok());
+    assert_eq!(request(r#"{"command": "speech", "text": "hello"}"#), Reply::ok());
+    let reply = request(r#"{"command": "telop", "txt": "HELLO"}"#);
+    assert!(!reply.ok);
+    assert!(reply.error.unwrap().contains("invalid command"));
+    assert!(!request("not json").ok);
+    let reply = request(r#"{"command": "appear", "filename": "./drawings/no_such_file.png", "name": "NOTHING"}"#);
+    assert!(reply.error.unwrap().contains("does not exist"));
+    assert_eq!(request(r#"{"command": "appear", "filename": "./drawings/001_megamouse_shark.png", "name": "SHARK"}"#), Reply::ok());
+    assert!(request(r#"{"command": "subtelop", "text": "SHARK", "pos": 6}"#).error.unwrap().contains("beyond"));
+    assert_eq!(request(r#"{"command": "shutdown"}"#), Reply::ok());
+
+    // the listener ends with the shutdown
+    listener_thread.join().unwrap();
+    let log = log.lock().unwrap();
+    assert!(matches!(&log[0].call, super::audiofuncs::AudioCall::Speak { text, .. } if text == "hello"));
+}
+
+#[cfg(unix)]
+#[test]
+fn remote_unix_works() {
+    use std::os::unix::net::UnixStream;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("aquatyping.sock");
+    // a socket file left behind does not stop the server
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let (client, _log) = recording_command_server();
+    let listener = Listener::bind(&ListenAddress::Unix(path.clone())).unwrap();
+    let (stop_tx, stop_rx) = channel::unbounded::<()>();
+    let listener_thread = thread::spawn(move || listener.run(client, stop_rx));
+
+    let stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    writeln!(writer, r#"{{"command": "clear"}}"#).unwrap();
+    let mut reply = String::new();
+    reader.read_line(&mut reply).unwrap();
+    assert_eq!(reply.trim(), r#"{"ok":true}"#);
+
+    stop_tx.send(()).unwrap();
+    listener_thread.join().unwrap();
+    assert!(!path.exists());
+}
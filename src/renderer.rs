@@ -0,0 +1,375 @@
+//! double buffered terminal rendering. only the cells changed since the last frame are written
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::{Arc, Mutex};
+use super::uifuncs::RenderColor;
+use super::cellart::Cell;
+
+/// how many colours the terminal can show. `RenderColor::Byte` is reduced to the nearest one
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ColorDepth {
+    /// decided from $COLORTERM and $TERM
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "truecolor")]
+    TrueColor,
+    #[serde(rename = "256")]
+    Ansi256,
+    #[serde(rename = "16")]
+    Ansi16,
+}
+
+impl ColorDepth {
+    pub fn detect() -> ColorDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    fn resolve(self) -> ColorDepth {
+        match self {
+            ColorDepth::Auto => ColorDepth::detect(),
+            depth => depth,
+        }
+    }
+}
+
+/// bytes written to the terminal
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderStats {
+    pub frames: u64,
+    pub total_bytes: u64,
+    pub last_frame_bytes: usize,
+    pub max_frame_bytes: usize,
+}
+
+impl RenderStats {
+    pub fn average_frame_bytes(&self) -> f64 {
+        if self.frames > 0 {
+            self.total_bytes as f64 / self.frames as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+pub type SharedRenderStats = Arc<Mutex<RenderStats>>;
+
+pub struct Renderer {
+    /// cells drawn since the last frame
+    back: Vec<Vec<Cell>>,
+    /// cells the terminal shows now. None when unknown
+    front: Vec<Vec<Option<Cell>>>,
+    /// columns and rows of the terminal. cells beyond them are dropped instead of wrapping
+    screen: Option<(usize, usize)>,
+    color_depth: ColorDepth,
+    stats: SharedRenderStats,
+}
+
+impl Renderer {
+    /// an unchanged gap shorter than this is rewritten instead of moving the cursor over it
+    const MAX_GAP: usize = 6;
+
+    pub fn new(color_depth: ColorDepth, stats: SharedRenderStats) -> Self {
+        Renderer {
+            back: Vec::new(),
+            front: Vec::new(),
+            screen: None,
+            color_depth: color_depth.resolve(),
+            stats,
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        let mut width = width.max(self.back.first().map_or(0, |line| line.len()));
+        let mut height = height.max(self.back.len());
+        if let Some((screen_width, screen_height)) = self.screen {
+            width = width.min(screen_width);
+            height = height.min(screen_height);
+        }
+        self.back.resize(height, Vec::new());
+        self.front.resize(height, Vec::new());
+        for line in self.back.iter_mut() {
+            line.resize(width, Cell::BLANK);
+        }
+        for line in self.front.iter_mut() {
+            line.resize(width, None);
+        }
+    }
+
+    /// draw characters into the back buffer. nothing is written until `render`
+    pub fn draw_area(&mut self, color: &RenderColor, bgcolor: &RenderColor, area: &[Vec<char>], offset: Option<(usize, usize)>) {
+        let (offset_x, offset_y) = offset.unwrap_or((0, 0));
+        let width = area.iter().map(|line| line.len()).max().unwrap_or(0);
+        self.resize(offset_x + width, offset_y + area.len());
+        for (y, line) in area.iter().enumerate().take(self.back.len().saturating_sub(offset_y)) {
+            let row = &mut self.back[offset_y + y];
+            for (x, ch) in line.iter().enumerate().take(row.len().saturating_sub(offset_x)) {
+                row[offset_x + x] = Cell {
+                    ch: *ch,
+                    fg: *color,
+                    bg: *bgcolor,
+                };
+            }
+        }
+    }
+
+    /// draw cells with their own colours into the back buffer
+    pub fn draw_cells(&mut self, area: &[Vec<Cell>], offset: Option<(usize, usize)>) {
+        let (offset_x, offset_y) = offset.unwrap_or((0, 0));
+        let width = area.iter().map(|line| line.len()).max().unwrap_or(0);
+        self.resize(offset_x + width, offset_y + area.len());
+        for (y, line) in area.iter().enumerate().take(self.back.len().saturating_sub(offset_y)) {
+            let row = &mut self.back[offset_y + y];
+            let len = line.len().min(row.len().saturating_sub(offset_x));
+            row[offset_x..offset_x + len].copy_from_slice(&line[..len]);
+        }
+    }
+
+    /// the terminal has a new size. the cells beyond it are dropped and the next frame repaints everything
+    pub fn set_screen_size(&mut self, width: usize, height: usize) {
+        self.screen = Some((width, height));
+        self.back.truncate(height);
+        self.front.truncate(height);
+        for line in self.back.iter_mut() {
+            line.truncate(width);
+        }
+        for line in self.front.iter_mut() {
+            line.truncate(width);
+        }
+        self.invalidate();
+    }
+
+    /// forget what the terminal shows so that the next frame repaints everything
+    pub fn invalidate(&mut self) {
+        for line in self.front.iter_mut() {
+            for cell in line.iter_mut() {
+                *cell = None;
+            }
+        }
+    }
+
+    /// escape sequences which turn the front buffer into the back buffer
+    pub fn render(&mut self) -> Vec<u8> {
+        let mut out = String::new();
+        let mut pen: Option<(RenderColor, RenderColor)> = None;
+        for (y, (back, front)) in self.back.iter().zip(self.front.iter_mut()).enumerate() {
+            let mut x = 0;
+            while x < back.len() {
+                if front[x] == Some(back[x]) {
+                    x += 1;
+                    continue;
+                }
+                // the run ends at a gap of unchanged cells which is long enough to jump over
+                let start = x;
+                let mut end = x + 1;
+                let mut gap = 0;
+                while end + gap < back.len() && gap < Self::MAX_GAP {
+                    if front[end + gap] == Some(back[end + gap]) {
+                        gap += 1;
+                    } else {
+                        end += gap + 1;
+                        gap = 0;
+                    }
+                }
+                out.push_str(&termion::cursor::Goto(start as u16 + 1, y as u16 + 1).to_string());
+                for cell in &back[start..end] {
+                    if pen != Some((cell.fg, cell.bg)) {
+                        out.push_str(&fg_escape(&cell.fg, self.color_depth));
+                        out.push_str(&bg_escape(&cell.bg, self.color_depth));
+                        pen = Some((cell.fg, cell.bg));
+                    }
+                    out.push(cell.ch);
+                }
+                for i in start..end {
+                    front[i] = Some(back[i]);
+                }
+                x = end;
+            }
+        }
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.frames += 1;
+        stats.total_bytes += out.len() as u64;
+        stats.last_frame_bytes = out.len();
+        stats.max_frame_bytes = stats.max_frame_bytes.max(out.len());
+        out.into_bytes()
+    }
+}
+
+/// index of the nearest colour in the xterm 256 colour palette
+pub fn to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    if max - min < 16 {
+        // the gray ramp 232-255 runs from 8 to 238
+        let gray = (r as u16 + g as u16 + b as u16) / 3;
+        if gray < 4 {
+            return 16;
+        } else if gray > 246 {
+            return 231;
+        }
+        return 232 + ((gray.max(8) - 8) / 10).min(23) as u8;
+    }
+    let level = |v: u8| -> u8 { ((v as u16 * 5 + 127) / 255) as u8 };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+/// index of the nearest colour of the 16 ansi colours (8-15 are the bright ones)
+pub fn to_ansi16(r: u8, g: u8, b: u8) -> u8 {
+    const PALETTE: [(u8, u8, u8); 16] = [
+        (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+        (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+    ];
+    let distance = |(pr, pg, pb): (u8, u8, u8)| -> i32 {
+        let (dr, dg, db) = (pr as i32 - r as i32, pg as i32 - g as i32, pb as i32 - b as i32);
+        dr * dr + dg * dg + db * db
+    };
+    (0..16).min_by_key(|i| distance(PALETTE[*i as usize])).unwrap()
+}
+
+fn fg_escape(color: &RenderColor, color_depth: ColorDepth) -> String {
+    use termion::color::*;
+    match color {
+        RenderColor::Black =>   { Fg(Black).to_string() },
+        RenderColor::Red =>     { Fg(Red).to_string() },
+        RenderColor::Green =>   { Fg(Green).to_string() },
+        RenderColor::Yellow =>  { Fg(Yellow).to_string() },
+        RenderColor::Blue =>    { Fg(Blue).to_string() },
+        RenderColor::Magenta => { Fg(Magenta).to_string() },
+        RenderColor::Cyan =>    { Fg(Cyan).to_string() },
+        RenderColor::White =>   { Fg(White).to_string() },
+        RenderColor::Byte(r,g,b) => {
+            match color_depth {
+                ColorDepth::Ansi256 => Fg(AnsiValue(to_ansi256(*r, *g, *b))).to_string(),
+                ColorDepth::Ansi16 => {
+                    let index = to_ansi16(*r, *g, *b);
+                    if index < 8 { format!("\x1b[{}m", 30 + index) } else { format!("\x1b[{}m", 90 + index - 8) }
+                },
+                _ => Fg(Rgb(*r,*g,*b)).to_string(),
+            }
+        },
+        RenderColor::Default => { Fg(Reset).to_string() },
+    }
+}
+
+fn bg_escape(color: &RenderColor, color_depth: ColorDepth) -> String {
+    use termion::color::*;
+    match color {
+        RenderColor::Black =>   { Bg(Black).to_string() },
+        RenderColor::Red =>     { Bg(Red).to_string() },
+        RenderColor::Green =>   { Bg(Green).to_string() },
+        RenderColor::Yellow =>  { Bg(Yellow).to_string() },
+        RenderColor::Blue =>    { Bg(Blue).to_string() },
+        RenderColor::Magenta => { Bg(Magenta).to_string() },
+        RenderColor::Cyan =>    { Bg(Cyan).to_string() },
+        RenderColor::White =>   { Bg(White).to_string() },
+        RenderColor::Byte(r,g,b) => {
+            match color_depth {
+                ColorDepth::Ansi256 => Bg(AnsiValue(to_ansi256(*r, *g, *b))).to_string(),
+                ColorDepth::Ansi16 => {
+                    let index = to_ansi16(*r, *g, *b);
+                    if index < 8 { format!("\x1b[{}m", 40 + index) } else { format!("\x1b[{}m", 100 + index - 8) }
+                },
+                _ => Bg(Rgb(*r,*g,*b)).to_string(),
+            }
+        },
+        RenderColor::Default => { Bg(Reset).to_string() },
+    }
+}
+
+#[test]
+fn renderer_works() {
+    let stats = SharedRenderStats::default();
+    let mut renderer = Renderer::new(ColorDepth::TrueColor, stats.clone());
+    let area: Vec<Vec<char>> = vec!["hello world".chars().collect(), "aquatyping".chars().collect()];
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, Some((2, 1)));
+    let first = String::from_utf8(renderer.render()).unwrap();
+    assert!(first.contains("hello world"));
+    assert!(first.contains("aquatyping"));
+
+    // nothing changed
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, Some((2, 1)));
+    assert!(renderer.render().is_empty());
+
+    // only the changed cells are written
+    let area: Vec<Vec<char>> = vec!["hello World".chars().collect(), "aquatyping".chars().collect()];
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, Some((2, 1)));
+    let third = String::from_utf8(renderer.render()).unwrap();
+    assert!(third.starts_with(&termion::cursor::Goto(9, 2).to_string()));
+    assert!(third.ends_with('W'));
+
+    // a colour change repaints the cells too
+    renderer.draw_area(&RenderColor::Blue, &RenderColor::White, &area, Some((2, 1)));
+    assert!(String::from_utf8(renderer.render()).unwrap().contains("hello World"));
+
+    let stats = stats.lock().unwrap();
+    assert_eq!(stats.frames, 4);
+    assert_eq!(stats.last_frame_bytes as u64 + third.len() as u64 + first.len() as u64, stats.total_bytes);
+    assert_eq!(stats.max_frame_bytes, first.len());
+}
+
+#[test]
+fn renderer_merges_short_gaps() {
+    let mut renderer = Renderer::new(ColorDepth::TrueColor, SharedRenderStats::default());
+    let area: Vec<Vec<char>> = vec!["abcdefghijklmnopqrst".chars().collect()];
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, None);
+    renderer.render();
+
+    let area: Vec<Vec<char>> = vec!["Abcdefghijklmnopqrst".chars().collect()];
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, None);
+    renderer.render();
+    // "A" and "D" are written in one run, "T" needs another jump
+    let area: Vec<Vec<char>> = vec!["abcDefghijklmnopqrsT".chars().collect()];
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, None);
+    let frame = String::from_utf8(renderer.render()).unwrap();
+    let expected = format!("{}{}{}abcD{}T", termion::cursor::Goto(1, 1), fg_escape(&RenderColor::Black, ColorDepth::TrueColor), bg_escape(&RenderColor::White, ColorDepth::TrueColor), termion::cursor::Goto(20, 1));
+    assert_eq!(frame, expected);
+
+    renderer.invalidate();
+    let area: Vec<Vec<char>> = vec!["abcDefghijklmnopqrsT".chars().collect()];
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, None);
+    assert!(String::from_utf8(renderer.render()).unwrap().contains("abcDefghijklmnopqrsT"));
+}
+
+#[test]
+fn color_depth_works() {
+    assert_eq!(to_ansi256(255, 0, 0), 196);
+    assert_eq!(to_ansi256(0, 0, 0), 16);
+    assert_eq!(to_ansi256(128, 128, 128), 244);
+    assert_eq!(to_ansi16(250, 10, 10), 9);
+    assert_eq!(to_ansi16(10, 10, 200), 4);
+
+    let cells = vec![vec![Cell { ch: 'x', fg: RenderColor::Byte(255, 0, 0), bg: RenderColor::Default }]];
+    let mut renderer = Renderer::new(ColorDepth::Ansi256, SharedRenderStats::default());
+    renderer.draw_cells(&cells, None);
+    assert!(String::from_utf8(renderer.render()).unwrap().contains("\x1b[38;5;196m"));
+    let mut renderer = Renderer::new(ColorDepth::Ansi16, SharedRenderStats::default());
+    renderer.draw_cells(&cells, None);
+    assert!(String::from_utf8(renderer.render()).unwrap().contains("\x1b[91m"));
+}
+
+#[test]
+fn renderer_screen_size_works() {
+    let mut renderer = Renderer::new(ColorDepth::TrueColor, SharedRenderStats::default());
+    renderer.set_screen_size(4, 1);
+    let area: Vec<Vec<char>> = vec!["abcdef".chars().collect(), "ghijkl".chars().collect()];
+    renderer.draw_area(&RenderColor::Black, &RenderColor::White, &area, Some((1, 0)));
+    let frame = String::from_utf8(renderer.render()).unwrap();
+    // nothing wraps onto the next line
+    assert!(frame.ends_with("abc"));
+    assert!(!frame.contains('g'));
+
+    renderer.set_screen_size(8, 2);
+    renderer.draw_cells(&[vec![Cell { ch: 'x', fg: RenderColor::Red, bg: RenderColor::White }; 10]], Some((0, 1)));
+    let frame = String::from_utf8(renderer.render()).unwrap();
+    // the cells kept from before are repainted too
+    assert!(frame.contains("abc"));
+    assert!(frame.ends_with("xxxxxxxx"));
+}
@@ -0,0 +1,490 @@
+//! romaji input engine which accepts every common spelling of a kana word
+
+/// spellings of each kana. the first spelling is the default one shown in the telop
+static KANA_TABLE: &[(&str, &[&str])] = &[
+    ("ア", &["a"]), ("イ", &["i", "yi"]), ("ウ", &["u", "wu", "whu"]), ("エ", &["e"]), ("オ", &["o"]),
+    ("カ", &["ka", "ca"]), ("キ", &["ki"]), ("ク", &["ku", "cu", "qu"]), ("ケ", &["ke"]), ("コ", &["ko", "co"]),
+    ("サ", &["sa"]), ("シ", &["si", "shi", "ci"]), ("ス", &["su"]), ("セ", &["se", "ce"]), ("ソ", &["so"]),
+    ("タ", &["ta"]), ("チ", &["ti", "chi"]), ("ツ", &["tu", "tsu"]), ("テ", &["te"]), ("ト", &["to"]),
+    ("ナ", &["na"]), ("ニ", &["ni"]), ("ヌ", &["nu"]), ("ネ", &["ne"]), ("ノ", &["no"]),
+    ("ハ", &["ha"]), ("ヒ", &["hi"]), ("フ", &["hu", "fu"]), ("ヘ", &["he"]), ("ホ", &["ho"]),
+    ("マ", &["ma"]), ("ミ", &["mi"]), ("ム", &["mu"]), ("メ", &["me"]), ("モ", &["mo"]),
+    ("ヤ", &["ya"]), ("ユ", &["yu"]), ("ヨ", &["yo"]),
+    ("ラ", &["ra"]), ("リ", &["ri"]), ("ル", &["ru"]), ("レ", &["re"]), ("ロ", &["ro"]),
+    ("ワ", &["wa"]), ("ヰ", &["wyi"]), ("ヱ", &["wye"]), ("ヲ", &["wo"]),
+    ("ガ", &["ga"]), ("ギ", &["gi"]), ("グ", &["gu"]), ("ゲ", &["ge"]), ("ゴ", &["go"]),
+    ("ザ", &["za"]), ("ジ", &["ji", "zi"]), ("ズ", &["zu"]), ("ゼ", &["ze"]), ("ゾ", &["zo"]),
+    ("ダ", &["da"]), ("ヂ", &["di"]), ("ヅ", &["du"]), ("デ", &["de"]), ("ド", &["do"]),
+    ("バ", &["ba"]), ("ビ", &["bi"]), ("ブ", &["bu"]), ("ベ", &["be"]), ("ボ", &["bo"]),
+    ("パ", &["pa"]), ("ピ", &["pi"]), ("プ", &["pu"]), ("ペ", &["pe"]), ("ポ", &["po"]),
+    ("ヴ", &["vu"]),
+    ("ァ", &["la", "xa"]), ("ィ", &["li", "xi"]), ("ゥ", &["lu", "xu"]), ("ェ", &["le", "xe"]), ("ォ", &["lo", "xo"]),
+    ("ャ", &["lya", "xya"]), ("ュ", &["lyu", "xyu"]), ("ョ", &["lyo", "xyo"]),
+    ("ッ", &["ltu", "xtu", "ltsu", "xtsu"]), ("ヮ", &["lwa", "xwa"]),
+    ("ー", &["-"]),
+    ("キャ", &["kya"]), ("キィ", &["kyi"]), ("キュ", &["kyu"]), ("キェ", &["kye"]), ("キョ", &["kyo"]),
+    ("シャ", &["sya", "sha"]), ("シィ", &["syi"]), ("シュ", &["syu", "shu"]), ("シェ", &["sye", "she"]), ("ショ", &["syo", "sho"]),
+    ("チャ", &["tya", "cha", "cya"]), ("チィ", &["tyi", "cyi"]), ("チュ", &["tyu", "chu", "cyu"]), ("チェ", &["tye", "che", "cye"]), ("チョ", &["tyo", "cho", "cyo"]),
+    ("ニャ", &["nya"]), ("ニィ", &["nyi"]), ("ニュ", &["nyu"]), ("ニェ", &["nye"]), ("ニョ", &["nyo"]),
+    ("ヒャ", &["hya"]), ("ヒィ", &["hyi"]), ("ヒュ", &["hyu"]), ("ヒェ", &["hye"]), ("ヒョ", &["hyo"]),
+    ("ミャ", &["mya"]), ("ミィ", &["myi"]), ("ミュ", &["myu"]), ("ミェ", &["mye"]), ("ミョ", &["myo"]),
+    ("リャ", &["rya"]), ("リィ", &["ryi"]), ("リュ", &["ryu"]), ("リェ", &["rye"]), ("リョ", &["ryo"]),
+    ("ギャ", &["gya"]), ("ギィ", &["gyi"]), ("ギュ", &["gyu"]), ("ギェ", &["gye"]), ("ギョ", &["gyo"]),
+    ("ジャ", &["jya", "ja", "zya"]), ("ジィ", &["jyi", "zyi"]), ("ジュ", &["jyu", "ju", "zyu"]), ("ジェ", &["jye", "je", "zye"]), ("ジョ", &["jyo", "jo", "zyo"]),
+    ("ヂャ", &["dya"]), ("ヂィ", &["dyi"]), ("ヂュ", &["dyu"]), ("ヂェ", &["dye"]), ("ヂョ", &["dyo"]),
+    ("ビャ", &["bya"]), ("ビィ", &["byi"]), ("ビュ", &["byu"]), ("ビェ", &["bye"]), ("ビョ", &["byo"]),
+    ("ピャ", &["pya"]), ("ピィ", &["pyi"]), ("ピュ", &["pyu"]), ("ピェ", &["pye"]), ("ピョ", &["pyo"]),
+    ("ファ", &["fa"]), ("フィ", &["fi"]), ("フェ", &["fe"]), ("フォ", &["fo"]), ("フュ", &["fyu"]),
+    ("ティ", &["thi"]), ("テュ", &["thu"]), ("ディ", &["dhi"]), ("デュ", &["dhu"]),
+    ("トゥ", &["twu"]), ("ドゥ", &["dwu"]),
+    ("ウィ", &["wi", "whi"]), ("ウェ", &["we", "whe"]), ("ウォ", &["who"]), ("イェ", &["ye"]),
+    ("ヴァ", &["va"]), ("ヴィ", &["vi"]), ("ヴェ", &["ve"]), ("ヴォ", &["vo"]),
+    ("ツァ", &["tsa"]), ("ツィ", &["tsi"]), ("ツェ", &["tse"]), ("ツォ", &["tso"]),
+    ("クァ", &["kwa", "qa"]), ("グァ", &["gwa"]),
+];
+
+const NN_SPELLINGS: [&str; 3] = ["nn", "xn", "n'"];
+
+/// one spelling which covers `kana_len` kana from the current position
+#[derive(Debug, Clone, PartialEq)]
+struct Candidate {
+    romaji: String,
+    kana_len: usize,
+}
+
+/// part of a word which has been typed completely
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedUnit {
+    /// kana or english word
+    pub text: String,
+    /// keys the player typed for the text
+    pub keys: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InputResult {
+    /// the key was a part of a valid spelling. units completed by the key are returned
+    Accepted(Vec<TypedUnit>),
+    /// the key did not match any spelling
+    Miss,
+    /// the word has already been typed
+    Finished,
+}
+
+/// state of typing one kana word
+pub struct RomajiInput {
+    kana: Vec<char>,
+    pos: usize,
+    buffer: String,
+    typed: Vec<TypedUnit>,
+}
+
+/// convert hiragana to katakana so that both can be used as a word
+pub fn to_katakana(text: &str) -> String {
+    text.chars().map(|ch| {
+        if ('\u{3041}'..='\u{3096}').contains(&ch) {
+            std::char::from_u32(ch as u32 + 0x60).unwrap_or(ch)
+        } else {
+            ch
+        }
+    }).collect()
+}
+
+fn lookup(kana: &str) -> Option<&'static [&'static str]> {
+    KANA_TABLE.iter().find(|(k, _)| *k == kana).map(|(_, spellings)| *spellings)
+}
+
+fn is_consonant(ch: char) -> bool {
+    ch.is_ascii_alphabetic() && !"aiueon".contains(ch)
+}
+
+impl RomajiInput {
+    pub fn new(kana: &str) -> Self {
+        RomajiInput {
+            kana: to_katakana(kana).chars().collect(),
+            pos: 0,
+            buffer: String::new(),
+            typed: Vec::new(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pos >= self.kana.len()
+    }
+
+    /// number of kana typed so far
+    pub fn kana_pos(&self) -> usize {
+        self.pos
+    }
+
+    /// spellings which are typed without the help of the next kana
+    fn simple_candidates(&self, pos: usize) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+        if pos >= self.kana.len() {
+            return candidates;
+        }
+        if pos + 1 < self.kana.len() {
+            let pair: String = self.kana[pos..pos + 2].iter().collect();
+            if let Some(spellings) = lookup(&pair) {
+                for s in spellings {
+                    candidates.push(Candidate { romaji: s.to_string(), kana_len: 2 });
+                }
+            }
+        }
+        let single = self.kana[pos].to_string();
+        if let Some(spellings) = lookup(&single) {
+            for s in spellings {
+                candidates.push(Candidate { romaji: s.to_string(), kana_len: 1 });
+            }
+        } else if self.kana[pos] != 'ン' {
+            // characters which are not kana are typed as they are
+            candidates.push(Candidate { romaji: single.to_lowercase(), kana_len: 1 });
+        }
+        candidates
+    }
+
+    fn candidates(&self, pos: usize) -> Vec<Candidate> {
+        if pos >= self.kana.len() {
+            return Vec::new();
+        }
+        match self.kana[pos] {
+            'ン' => {
+                let next = self.candidates(pos + 1);
+                let mut candidates: Vec<Candidate> = NN_SPELLINGS.iter()
+                    .map(|s| Candidate { romaji: s.to_string(), kana_len: 1 })
+                    .collect();
+                // a single "n" is enough unless it would be read together with the next kana
+                let ambiguous = next.iter().any(|c| c.romaji.starts_with(|ch: char| "aiueoyn".contains(ch)));
+                if !ambiguous {
+                    candidates.insert(1, Candidate { romaji: String::from("n"), kana_len: 1 });
+                }
+                if next.is_empty() {
+                    // at the end of the word "n" alone finishes the word
+                    candidates.retain(|c| c.romaji == "n" || !c.romaji.starts_with('n'));
+                }
+                candidates
+            },
+            'ッ' => {
+                let mut candidates = Vec::new();
+                for next in self.candidates(pos + 1) {
+                    if let Some(first) = next.romaji.chars().next() {
+                        if is_consonant(first) {
+                            candidates.push(Candidate {
+                                romaji: format!("{}{}", first, next.romaji),
+                                kana_len: next.kana_len + 1,
+                            });
+                            if next.romaji.starts_with("ch") {
+                                candidates.push(Candidate {
+                                    romaji: format!("t{}", next.romaji),
+                                    kana_len: next.kana_len + 1,
+                                });
+                            }
+                        }
+                    }
+                }
+                candidates.extend(self.simple_candidates(pos));
+                candidates
+            },
+            _ => self.simple_candidates(pos),
+        }
+    }
+
+    fn commit(&mut self, candidate: &Candidate) -> TypedUnit {
+        let unit = TypedUnit {
+            text: self.kana[self.pos..self.pos + candidate.kana_len].iter().collect(),
+            keys: candidate.romaji.clone(),
+        };
+        self.pos += candidate.kana_len;
+        self.buffer.clear();
+        self.typed.push(unit.clone());
+        unit
+    }
+
+    /// feed one key. the player can switch spelling at any key
+    pub fn input(&mut self, ch: char) -> InputResult {
+        if self.is_finished() {
+            return InputResult::Finished;
+        }
+        let ch = ch.to_ascii_lowercase();
+        let candidates = self.candidates(self.pos);
+        let mut next = self.buffer.clone();
+        next.push(ch);
+
+        if candidates.iter().any(|c| c.romaji.starts_with(&next)) {
+            self.buffer = next;
+            let mut committed = Vec::new();
+            let complete = candidates.iter().find(|c| c.romaji == self.buffer).cloned();
+            if let Some(candidate) = complete {
+                let longer = candidates.iter().any(|c| c.romaji.len() > self.buffer.len() && c.romaji.starts_with(&self.buffer));
+                if !longer {
+                    committed.push(self.commit(&candidate));
+                }
+            }
+            InputResult::Accepted(committed)
+        } else if let Some(candidate) = candidates.iter().find(|c| c.romaji == self.buffer).cloned() {
+            // "n" of "ン" waits for the next key to know which spelling is used
+            let (pos, buffer) = (self.pos, self.buffer.clone());
+            let unit = self.commit(&candidate);
+            match self.input(ch) {
+                InputResult::Accepted(mut committed) => {
+                    committed.insert(0, unit);
+                    InputResult::Accepted(committed)
+                },
+                _ => {
+                    // the "n" stays pending so that a miss does not commit anything
+                    self.pos = pos;
+                    self.buffer = buffer;
+                    self.typed.pop();
+                    InputResult::Miss
+                },
+            }
+        } else {
+            InputResult::Miss
+        }
+    }
+
+    /// keys which are accepted as the next input
+    pub fn expected_keys(&self) -> Vec<char> {
+        let mut keys: Vec<char> = Vec::new();
+        for c in self.candidates(self.pos) {
+            if c.romaji.starts_with(&self.buffer) {
+                if let Some(key) = c.romaji[self.buffer.len()..].chars().next() {
+                    if !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+        keys
+    }
+
+    /// kana of the unit which is being typed now
+    pub fn current_kana(&self) -> String {
+        match self.candidates(self.pos).iter().find(|c| c.romaji.starts_with(&self.buffer)) {
+            Some(c) => self.kana[self.pos..self.pos + c.kana_len].iter().collect(),
+            None => String::new(),
+        }
+    }
+
+    /// the spelling shown for `pos`. longer kana units and earlier spellings in the table win
+    fn default_candidate(&self, pos: usize, buffer: &str) -> Option<Candidate> {
+        let candidates = self.candidates(pos);
+        candidates.iter()
+            .filter(|c| c.romaji.starts_with(buffer))
+            .max_by_key(|c| (c.kana_len, std::cmp::Reverse(candidates.iter().position(|x| x == *c))))
+            .cloned()
+    }
+
+    /// spelling of the rest of the word from `pos`, one string per unit
+    fn default_spellings(&self, mut pos: usize, buffer: &str) -> Vec<String> {
+        let mut spellings = Vec::new();
+        let mut buffer = buffer;
+        while let Some(c) = self.default_candidate(pos, buffer) {
+            spellings.push(c.romaji.clone());
+            pos += c.kana_len;
+            buffer = "";
+        }
+        spellings
+    }
+
+    /// romaji shown in the telop and the number of characters already typed.
+    /// typed units keep the spelling the player chose
+    pub fn display(&self) -> (String, usize) {
+        let mut units: Vec<String> = self.typed.iter().map(|u| u.keys.clone()).collect();
+        let mut typed_len = units.iter().map(|u| u.chars().count() + 1).sum::<usize>();
+        units.extend(self.default_spellings(self.pos, &self.buffer));
+        typed_len += self.buffer.chars().count();
+        let text = units.join(" ").to_ascii_uppercase();
+        (text.clone(), typed_len.min(text.chars().count()))
+    }
+}
+
+/// generate romaji with syllable markers as written in picts_info.json.
+/// "_" ends a unit of one kana and "~" ends a unit of two kana. e.g. ニシシマドジョウ -> NI_SI_SI_MA_DO_JYO~U_
+pub fn kana_to_romaji(kana: &str) -> String {
+    let input = RomajiInput::new(kana);
+    let mut romaji = String::new();
+    let mut pos = 0;
+    while let Some(c) = input.default_candidate(pos, "") {
+        if input.kana[pos] == 'ッ' && c.kana_len > 1 {
+            // small tsu is written as the doubled consonant
+            romaji.push_str(&c.romaji[0..1]);
+            romaji.push('_');
+            pos += 1;
+            continue;
+        }
+        romaji.push_str(&c.romaji);
+        romaji.push(if c.kana_len == 1 { '_' } else { '~' });
+        pos += c.kana_len;
+    }
+    romaji.to_ascii_uppercase()
+}
+
+/// check hand-written romaji with syllable markers against the kana.
+/// returns the list of problems
+pub fn check_romaji(kana: &str, romaji: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let kana: Vec<char> = to_katakana(kana).chars().collect();
+
+    if romaji.chars().any(|ch| ch.is_ascii_lowercase()) {
+        problems.push(String::from("romaji is not written in upper case"));
+    }
+    if !romaji.ends_with(['_', '~']) {
+        problems.push(String::from("romaji does not end with a syllable marker"));
+    }
+
+    // split into units: (spelling, number of kana)
+    let mut units: Vec<(String, usize)> = Vec::new();
+    let mut unit = String::new();
+    for ch in romaji.chars() {
+        match ch {
+            '_' => units.push((unit.split_off(0).to_ascii_lowercase(), 1)),
+            '~' => units.push((unit.split_off(0).to_ascii_lowercase(), 2)),
+            _ => unit.push(ch),
+        }
+    }
+    if !unit.is_empty() {
+        units.push((unit.to_ascii_lowercase(), 1));
+    }
+
+    let marked_len: usize = units.iter().map(|(_, len)| len).sum();
+    if marked_len != kana.len() {
+        problems.push(format!("markers cover {} kana but the name has {} kana", marked_len, kana.len()));
+    }
+
+    let mut pos = 0;
+    for (i, (spelling, len)) in units.iter().enumerate() {
+        if pos + len > kana.len() {
+            break;
+        }
+        let slice: String = kana[pos..pos + len].iter().collect();
+        let valid = match slice.as_str() {
+            "ン" => spelling == "n" || NN_SPELLINGS.contains(&spelling.as_str()),
+            "ッ" => {
+                let next = units.get(i + 1).map(|(s, _)| s.as_str()).unwrap_or("");
+                (spelling.len() == 1 && spelling.chars().all(is_consonant) && next.starts_with(spelling.as_str()))
+                    || lookup(&slice).is_some_and(|s| s.contains(&spelling.as_str()))
+            },
+            _ => lookup(&slice).is_some_and(|s| s.contains(&spelling.as_str())),
+        };
+        if !valid {
+            // the following units are misaligned once one unit is wrong
+            problems.push(format!("'{}' is not a spelling of {}", spelling.to_ascii_uppercase(), slice));
+            break;
+        }
+        pos += len;
+    }
+
+    let mut input = RomajiInput::new(&kana.iter().collect::<String>());
+    let keys: String = units.iter().map(|(s, _)| s.as_str()).collect();
+    for ch in keys.chars() {
+        if input.input(ch) == InputResult::Miss {
+            problems.push(format!("'{}' can not be typed as {}", keys.to_ascii_uppercase(), kana.iter().collect::<String>()));
+            break;
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+fn type_keys(input: &mut RomajiInput, keys: &str) -> Vec<String> {
+    let mut units = Vec::new();
+    for ch in keys.chars() {
+        match input.input(ch) {
+            InputResult::Accepted(committed) => {
+                units.extend(committed.into_iter().map(|u| u.text));
+            },
+            other => panic!("key {} in {} was not accepted: {:?}", ch, keys, other),
+        }
+    }
+    units
+}
+
+#[test]
+fn romaji_accepts_every_spelling() {
+    for keys in &["nisisimadojyou", "nishishimadojou", "nisishimadozyou", "nishisimadojixyou"] {
+        let mut input = RomajiInput::new("ニシシマドジョウ");
+        type_keys(&mut input, keys);
+        assert!(input.is_finished(), "{}", keys);
+    }
+    for keys in &["tyoutinnankou", "chouchinnankou", "choutin'ankou"] {
+        let mut input = RomajiInput::new("チョウチンアンコウ");
+        type_keys(&mut input, keys);
+        assert!(input.is_finished(), "{}", keys);
+    }
+}
+
+#[test]
+fn romaji_n_and_small_tsu_works() {
+    let mut input = RomajiInput::new("メンダコ");
+    assert_eq!(type_keys(&mut input, "mendako"), ["メ", "ン", "ダ", "コ"]);
+    assert!(input.is_finished());
+
+    let mut input = RomajiInput::new("メンダコ");
+    type_keys(&mut input, "men");
+    assert_eq!(input.input('x'), InputResult::Miss);
+    assert_eq!(input.kana_pos(), 1);
+    assert_eq!(type_keys(&mut input, "dako"), ["ン", "ダ", "コ"]);
+    assert!(input.is_finished());
+
+    let mut input = RomajiInput::new("チンアナゴ");
+    type_keys(&mut input, "tin");
+    assert_eq!(input.input('a'), InputResult::Miss);
+    type_keys(&mut input, "nanago");
+    assert!(input.is_finished());
+
+    let mut input = RomajiInput::new("マッコウクジラ");
+    assert_eq!(type_keys(&mut input, "makkou"), ["マ", "ッコ", "ウ"]);
+    let mut input = RomajiInput::new("マッコウクジラ");
+    assert_eq!(type_keys(&mut input, "maltukou"), ["マ", "ッ", "コ", "ウ"]);
+
+    let mut input = RomajiInput::new("スイホウガン");
+    type_keys(&mut input, "suihougan");
+    assert!(input.is_finished());
+    assert_eq!(input.input('n'), InputResult::Finished);
+}
+
+#[test]
+fn romaji_display_follows_typed_spelling() {
+    let mut input = RomajiInput::new("シイラ");
+    assert_eq!(input.display(), (String::from("SI I RA"), 0));
+    assert_eq!(input.input('s'), InputResult::Accepted(vec![]));
+    assert_eq!(input.input('h'), InputResult::Accepted(vec![]));
+    assert_eq!(input.display(), (String::from("SHI I RA"), 2));
+    type_keys(&mut input, "i");
+    assert_eq!(input.display(), (String::from("SHI I RA"), 4));
+    assert_eq!(input.input('x'), InputResult::Miss);
+    assert_eq!(input.expected_keys(), vec!['i', 'y']);
+    assert_eq!(input.current_kana(), "イ");
+}
+
+#[test]
+fn romaji_default_spelling_finishes_every_pict() {
+    let pict_manager = super::pict::PictManager::load("./drawings/").unwrap();
+    for i in 0..pict_manager.get_pict_len() {
+        let pict = pict_manager.get_pict(i).unwrap();
+        let mut input = RomajiInput::new(&pict.ja);
+        let (romaji, _) = input.display();
+        type_keys(&mut input, &romaji.replace(" ", ""));
+        assert!(input.is_finished(), "{} {}", pict.ja, romaji);
+    }
+}
+
+#[test]
+fn kana_to_romaji_works() {
+    assert_eq!(kana_to_romaji("メガマウスザメ"), "ME_GA_MA_U_SU_ZA_ME_");
+    assert_eq!(kana_to_romaji("ニシシマドジョウ"), "NI_SI_SI_MA_DO_JYO~U_");
+    assert_eq!(kana_to_romaji("ハンマーヘッドシャーク"), "HA_NN_MA_-_HE_D_DO_SYA~-_KU_");
+    assert_eq!(kana_to_romaji("スイホウガン"), "SU_I_HO_U_GA_N_");
+}
+
+#[test]
+fn check_romaji_works() {
+    assert!(check_romaji("ニシシマドジョウ", "NI_SI_SI_MA_DO_JYO~U_").is_empty());
+    assert!(check_romaji("ニシシマドジョウ", "NI_SHI_SHI_MA_DO_JI_XYO_U_").is_empty());
+    assert!(check_romaji("ハンマーヘッドシャーク", "HA_NN_MA_-_HE_D_DO_SYA~-_KU_").is_empty());
+    assert_eq!(check_romaji("マゴチ", "ma_go_ti_").len(), 1);
+    assert!(!check_romaji("マッコウクジラ", "MA_K_KOU_KU_JI_RA_").is_empty());
+    assert!(!check_romaji("リュウグウノツカイ", "RYU~_U_GU_U_NO_TU_KA_I_").is_empty());
+    assert!(!check_romaji("ダルマオコゼ", "DA_RU_MA_O_KO_ZE").is_empty());
+}
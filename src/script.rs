@@ -0,0 +1,364 @@
+//! show sequences of stage commands with waits, loops and variables written in json
+
+use crossbeam_channel as channel;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use super::audiofuncs::AudioFuncs;
+use super::command::{Command, CommandClient, forward_resize_events, start_command_server};
+use super::config::GameConfig;
+use super::uifuncs::{UIFuncs, UIKeyEvent};
+
+/// {"wait": MSEC}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaitStep {
+    pub wait: u64,
+}
+
+/// {"loop": N, "steps": [...]}. 0 repeats the steps until the script is stopped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoopStep {
+    #[serde(rename = "loop")]
+    pub count: u32,
+    pub steps: Vec<Step>,
+}
+
+/// {"set": {"NAME": "VALUE"}}. the values may use the variables set before
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetStep {
+    pub set: BTreeMap<String, String>,
+}
+
+/// commands are kept as json so that `${NAME}` in their strings is replaced when they run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Step {
+    Wait(WaitStep),
+    Loop(LoopStep),
+    Set(SetStep),
+    Command(Value),
+}
+
+/// {"vars": {...}, "steps": [...]}. the values of vars may use the other vars
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    pub steps: Vec<Step>,
+}
+
+/// whether the script goes on after a step
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Flow {
+    Continue,
+    Stopped,
+}
+
+impl Script {
+    /// `script_dir` is set to the directory of the file so that images can be named relative to it
+    pub fn load(script_file: &str) -> Result<Script, String> {
+        let file = File::open(script_file).map_err(|e| format!("can not open script {}: {}", script_file, e))?;
+        let mut script: Script = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("invalid script {}: {}", script_file, e))?;
+        let script_dir = Path::new(script_file).parent().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+        script.vars.entry(String::from("script_dir")).or_insert(if script_dir.is_empty() { String::from(".") } else { script_dir });
+        script.check().map_err(|e| format!("invalid script {}: {}", script_file, e))?;
+        Ok(script)
+    }
+
+    pub fn parse(json: &str) -> Result<Script, String> {
+        let script: Script = serde_json::from_str(json).map_err(|e| format!("invalid script: {}", e))?;
+        script.check().map_err(|e| format!("invalid script: {}", e))?;
+        Ok(script)
+    }
+
+    /// every command must be a valid command and every endless loop must wait
+    fn check(&self) -> Result<(), String> {
+        Script::check_steps(&self.steps, "steps")
+    }
+
+    fn check_steps(steps: &[Step], path: &str) -> Result<(), String> {
+        for (i, step) in steps.iter().enumerate() {
+            let path = format!("{}[{}]", path, i);
+            match step {
+                Step::Command(value) => {
+                    serde_json::from_value::<Command>(value.clone()).map_err(|e| format!("{}: {}", path, e))?;
+                },
+                Step::Loop(looped) => {
+                    if looped.count == 0 && !Script::waits(&looped.steps) {
+                        return Err(format!("{}: an endless loop must have a wait", path));
+                    }
+                    Script::check_steps(&looped.steps, &format!("{}.steps", path))?;
+                },
+                Step::Wait(_) | Step::Set(_) => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn waits(steps: &[Step]) -> bool {
+        steps.iter().any(|step| match step {
+            Step::Wait(wait) => wait.wait > 0,
+            Step::Loop(looped) => Script::waits(&looped.steps),
+            _ => false,
+        })
+    }
+
+    /// send the commands in order until the end of the script, a shutdown command or a message on `stop_rx`
+    pub fn run(&self, client: &CommandClient, stop_rx: &channel::Receiver<()>) -> Result<(), String> {
+        let mut vars = self.expand_vars()?;
+        Script::run_steps(&self.steps, &mut vars, client, stop_rx)?;
+        Ok(())
+    }
+
+    /// `pict_dir` is set to the pict dir of the config unless the script or the command line sets it
+    pub fn with_config_vars(&self, config: &GameConfig) -> Script {
+        let mut script = self.clone();
+        script.vars.entry(String::from("pict_dir")).or_insert_with(|| config.pict_dir.to_string());
+        script
+    }
+
+    /// the values of vars with the variables they use replaced
+    pub fn expand_vars(&self) -> Result<BTreeMap<String, String>, String> {
+        let mut expanded = BTreeMap::new();
+        for name in self.vars.keys() {
+            self.expand_var(name, &mut expanded, &mut Vec::new())?;
+        }
+        Ok(expanded)
+    }
+
+    /// `expanding` holds the variables on the way to `name` so that a cycle is reported instead of looping
+    fn expand_var(&self, name: &str, expanded: &mut BTreeMap<String, String>, expanding: &mut Vec<String>) -> Result<String, String> {
+        if let Some(value) = expanded.get(name) {
+            return Ok(value.to_string());
+        }
+        if expanding.iter().any(|n| n == name) {
+            return Err(format!("variable '{}' refers to itself", name));
+        }
+        let raw = self.vars.get(name).ok_or_else(|| format!("undefined variable '{}'", name))?;
+        expanding.push(name.to_string());
+        let value = substitute_with(raw, &mut |reference| self.expand_var(reference, expanded, expanding))?;
+        expanding.pop();
+        expanded.insert(name.to_string(), value.to_string());
+        Ok(value)
+    }
+
+    fn run_steps(steps: &[Step], vars: &mut BTreeMap<String, String>, client: &CommandClient, stop_rx: &channel::Receiver<()>) -> Result<Flow, String> {
+        for step in steps.iter() {
+            if stop_rx.try_recv().is_ok() {
+                return Ok(Flow::Stopped);
+            }
+            let flow = match step {
+                Step::Wait(wait) => Script::wait(wait.wait, stop_rx),
+                Step::Loop(looped) => {
+                    let mut flow = Flow::Continue;
+                    let mut count = 0;
+                    while flow == Flow::Continue && (looped.count == 0 || count < looped.count) {
+                        flow = Script::run_steps(&looped.steps, vars, client, stop_rx)?;
+                        count += 1;
+                    }
+                    flow
+                },
+                Step::Set(set) => {
+                    for (name, value) in set.set.iter() {
+                        let value = substitute(value, vars)?;
+                        vars.insert(name.to_string(), value);
+                    }
+                    Flow::Continue
+                },
+                Step::Command(value) => {
+                    let command: Command = serde_json::from_value(substitute_value(value, vars)?).map_err(|e| e.to_string())?;
+                    let shutdown = command == Command::Shutdown;
+                    client.send(command)?;
+                    if shutdown { Flow::Stopped } else { Flow::Continue }
+                },
+            };
+            if flow == Flow::Stopped {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn wait(msec: u64, stop_rx: &channel::Receiver<()>) -> Flow {
+        match stop_rx.recv_timeout(Duration::from_millis(msec)) {
+            Ok(()) => Flow::Stopped,
+            Err(channel::RecvTimeoutError::Timeout) => Flow::Continue,
+            Err(channel::RecvTimeoutError::Disconnected) => {
+                // nobody can stop the script any more
+                thread::sleep(Duration::from_millis(msec));
+                Flow::Continue
+            },
+        }
+    }
+}
+
+/// replace `${NAME}` with the value of the variable
+pub fn substitute(text: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    substitute_with(text, &mut |name| vars.get(name).cloned().ok_or_else(|| format!("undefined variable '{}'", name)))
+}
+
+fn substitute_with(text: &str, value_of: &mut dyn FnMut(&str) -> Result<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| format!("unterminated variable in '{}'", text))?;
+        result.push_str(&value_of(&rest[start + 2..start + end])?);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn substitute_value(value: &Value, vars: &BTreeMap<String, String>) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(text) => Value::String(substitute(text, vars)?),
+        Value::Array(values) => Value::Array(values.iter().map(|value| substitute_value(value, vars)).collect::<Result<_, _>>()?),
+        Value::Object(map) => Value::Object(map.iter()
+            .map(|(key, value)| substitute_value(value, vars).map(|value| (key.to_string(), value)))
+            .collect::<Result<_, _>>()?),
+        _ => value.clone(),
+    })
+}
+
+/// play the script on the stage. ESC stops it
+pub fn play_script(mut uifuncs: Box<dyn UIFuncs>, audiofuncs: Box<dyn AudioFuncs>, config: &GameConfig, script: &Script) -> Result<(), String> {
+    let script = script.with_config_vars(config);
+    let graphics = uifuncs.get_graphics();
+    let keyevent_rx = uifuncs.start_keyevent_thread();
+    let client = start_command_server(graphics, config.stage_config(), config.color, audiofuncs);
+    forward_resize_events(uifuncs.resize_events(), &client);
+
+    let (stop_tx, stop_rx) = channel::unbounded::<()>();
+    thread::spawn(move || {
+        while let Ok(event) = keyevent_rx.recv() {
+            if event == UIKeyEvent::ESC {
+                let _ = stop_tx.send(());
+                break;
+            }
+        }
+    });
+    let result = script.run(&client, &stop_rx);
+    // the server thread may have gone with a shutdown command already
+    let _ = client.shutdown();
+    result
+}
+
+#[test]
+fn substitute_works() {
+    let mut vars = BTreeMap::new();
+    vars.insert(String::from("name"), String::from("MEGAMOUSE SHARK"));
+    vars.insert(String::from("dir"), String::from("./drawings"));
+    assert_eq!(substitute("${dir}/001.png", &vars), Ok(String::from("./drawings/001.png")));
+    assert_eq!(substitute("I caught ${name}!", &vars), Ok(String::from("I caught MEGAMOUSE SHARK!")));
+    assert_eq!(substitute("no variables", &vars), Ok(String::from("no variables")));
+    assert!(substitute("${nothing}", &vars).unwrap_err().contains("nothing"));
+    assert!(substitute("${name", &vars).is_err());
+}
+
+#[test]
+fn expand_vars_works() {
+    // the order of the declarations does not matter
+    let script = Script::parse(r#"{"vars": {"a": "${b}/a.png", "b": "${c}/b", "c": "./c"}, "steps": []}"#).unwrap();
+    let vars = script.expand_vars().unwrap();
+    assert_eq!(vars["a"], "./c/b/a.png");
+    assert_eq!(vars["b"], "./c/b");
+    let script = Script::parse(r#"{"vars": {"a": "${b}", "b": "x${a}"}, "steps": []}"#).unwrap();
+    assert!(script.expand_vars().unwrap_err().contains("refers to itself"));
+    let script = Script::parse(r#"{"vars": {"a": "${nothing}"}, "steps": []}"#).unwrap();
+    assert!(script.expand_vars().unwrap_err().contains("nothing"));
+}
+
+#[test]
+fn demo_script_works() {
+    use super::command::AppearCommand;
+    fn appear(steps: &[Step], vars: &BTreeMap<String, String>) -> Option<AppearCommand> {
+        steps.iter().find_map(|step| match step {
+            Step::Loop(looped) => appear(&looped.steps, vars),
+            Step::Command(value) => match serde_json::from_value(substitute_value(value, vars).unwrap()).unwrap() {
+                Command::Appear(appear) => Some(appear),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+    let script = Script::load("./scripts/demo.json").unwrap().with_config_vars(&GameConfig::default());
+    let vars = script.expand_vars().unwrap();
+    let appear = appear(&script.steps, &vars).unwrap();
+    assert!(Path::new(&appear.filename).is_file(), "{}", appear.filename);
+    assert_eq!(appear.name, "MEGAMOUSE SHARK");
+}
+
+#[test]
+fn script_parse_works() {
+    let script = Script::parse(r#"{
+        "vars": {"name": "SHARK"},
+        "steps": [
+            {"command": "telop", "text": "${name}"},
+            {"wait": 100},
+            {"loop": 0, "steps": [{"set": {"name": "FISH"}}, {"wait": 10}]}
+        ]
+    }"#).unwrap();
+    assert_eq!(script.steps.len(), 3);
+    assert_eq!(script.steps[1], Step::Wait(WaitStep { wait: 100 }));
+    assert!(matches!(&script.steps[2], Step::Loop(LoopStep { count: 0, steps }) if steps.len() == 2));
+
+    let err = Script::parse(r#"{"steps": [{"loop": 2, "steps": [{"command": "telop", "txt": "typo"}]}]}"#).unwrap_err();
+    assert!(err.contains("steps[0].steps[0]"));
+    assert!(Script::parse(r#"{"steps": [{"wait": 10, "extra": 1}]}"#).is_err());
+    assert!(Script::parse(r#"{"steps": [{"loop": 0, "steps": [{"command": "clear"}]}]}"#).unwrap_err().contains("endless"));
+}
+
+#[test]
+fn script_run_works() {
+    use super::audiofuncs::{AudioCall, AudioFuncsType, generate_audiofuncs};
+    let funcs = super::uifuncs::generate_uifuncs(super::uifuncs::UIFuncsType::DEBUG, super::renderer::ColorDepth::Auto);
+    let audio = generate_audiofuncs(AudioFuncsType::Recording, &Default::default(), &Default::default(), Default::default());
+    let log = audio.audio_log().unwrap();
+    let config = GameConfig { stage_wxh: (64, 18), aa_width: 32, ..GameConfig::default() };
+    let client = start_command_server(funcs.get_graphics(), config.stage_config(), config.color, audio);
+
+    let script = Script::parse(r#"{
+        "vars": {"name": "one"},
+        "steps": [
+            {"loop": 2, "steps": [
+                {"command": "speech", "text": "${name}"},
+                {"set": {"name": "${name} more"}}
+            ]},
+            {"wait": 10},
+            {"command": "speech", "text": "${nothing}"}
+        ]
+    }"#).unwrap();
+    let (_stop_tx, stop_rx) = channel::unbounded::<()>();
+    assert!(script.run(&client, &stop_rx).unwrap_err().contains("nothing"));
+
+    // an endless loop ends with a stop
+    let script = Script::parse(r#"{"steps": [{"loop": 0, "steps": [{"command": "play", "name": "click"}, {"wait": 10}]}]}"#).unwrap();
+    let (stop_tx, stop_rx) = channel::unbounded::<()>();
+    let runner = thread::spawn(move || {
+        let result = script.run(&client, &stop_rx);
+        let _ = client.shutdown();
+        result
+    });
+    thread::sleep(Duration::from_millis(100));
+    stop_tx.send(()).unwrap();
+    runner.join().unwrap().unwrap();
+
+    let log = log.lock().unwrap();
+    let spoken: Vec<&str> = log.iter().filter_map(|event| match &event.call {
+        AudioCall::Speak { text, .. } => Some(text.as_str()),
+        _ => None,
+    }).collect();
+    assert_eq!(spoken, ["one", "one more"]);
+    assert!(log.iter().filter(|event| matches!(&event.call, AudioCall::Play(_))).count() >= 2);
+}
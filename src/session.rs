@@ -0,0 +1,439 @@
+//! record the keys of a play session with everything needed to play it again the same way.
+//! the replay sends the keys and resizes at their recorded times, so the telops, the order of the creatures
+//! and what is caught come out the same. the creatures move with the frame clock, which the session does not
+//! record, so where a creature is when a key comes can differ by a frame or so
+
+use crossbeam_channel as channel;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use super::collection::Collection;
+use super::config::GameConfig;
+use super::history::{RoundRecord, append_record, load_records};
+use super::renderer::SharedRenderStats;
+use super::capture::SharedCapture;
+use super::uifuncs::{UIFuncs, UIGraphics, UIKeyEvent};
+
+/// version 2 added the resize lines. a file of version 1 has keys only
+pub const SESSION_VERSION: u32 = 2;
+
+/// first line of a session file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionHeader {
+    pub version: u32,
+    /// seed of the random order of the round and the animations of the stage
+    pub seed: u64,
+    pub config: GameConfig,
+    /// rounds of the player before the session. the practice mode picks creatures by them
+    pub history: Vec<RoundRecord>,
+    pub collection: Collection,
+}
+
+/// a key and msec since the key event thread started
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub time: u64,
+    pub key: UIKeyEvent,
+}
+
+/// a new screen size in characters and msec since the key event thread started.
+/// the first one is the size of the terminal when the session started
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResizeRecord {
+    pub time: u64,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// a line after the header
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SessionLine {
+    Key(KeyRecord),
+    Resize(ResizeRecord),
+}
+
+/// a session file is the header followed by one key or resize per line
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub header: SessionHeader,
+    pub keys: Vec<KeyRecord>,
+    /// the stage is fitted in these sizes so that it is drawn as it was recorded whatever the terminal is
+    pub resizes: Vec<ResizeRecord>,
+}
+
+impl Session {
+    /// a file cut off by a crash is played up to the last complete line. a broken line before it is an error
+    pub fn load(session_file: &str) -> Result<Session, String> {
+        let file = File::open(session_file).map_err(|e| format!("can not open session file {}: {}", session_file, e))?;
+        let mut lines = BufReader::new(file).lines();
+        let header_line = lines.next()
+            .ok_or_else(|| format!("session file {} is empty", session_file))?
+            .map_err(|e| format!("can not read session file {}: {}", session_file, e))?;
+        let header: SessionHeader = serde_json::from_str(&header_line)
+            .map_err(|e| format!("invalid session file {}: {}", session_file, e))?;
+        if header.version == 0 || header.version > SESSION_VERSION {
+            return Err(format!("session file {} has unsupported version {}", session_file, header.version));
+        }
+        let mut keys = Vec::new();
+        let mut resizes = Vec::new();
+        let mut lines = lines.enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            let parsed = line.map_err(|e| e.to_string())
+                .and_then(|line| serde_json::from_str(&line).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(SessionLine::Key(key)) => keys.push(key),
+                Ok(SessionLine::Resize(resize)) => resizes.push(resize),
+                Err(_) if lines.peek().is_none() => break,
+                // the header is line 1
+                Err(e) => return Err(format!("invalid line {} of session file {}: {}", i + 2, session_file, e)),
+            }
+        }
+        Ok(Session { header, keys, resizes })
+    }
+
+    /// the config to play the session with. history and collection are written to `dir` so that
+    /// the replay starts from the same state and leaves the real files alone
+    pub fn replay_config(&self, dir: &TempDir) -> Result<GameConfig, String> {
+        let mut config = self.header.config.clone();
+        config.round.seed = Some(self.header.seed);
+        config.record_file = None;
+        config.cast_file = None;
+        config.frames_path = None;
+        config.history_file = dir.path().join("history.jsonl").to_string_lossy().to_string();
+        config.collection_dir = dir.path().join("collection").to_string_lossy().to_string();
+        for record in self.header.history.iter() {
+            append_record(&config.history_file, record)?;
+        }
+        self.header.collection.save(&config.collection_dir, &config.player)?;
+        Ok(config)
+    }
+}
+
+/// start recording into the file. the seed is fixed here when the config leaves it random
+pub fn start_recording(uifuncs: Box<dyn UIFuncs>, config: &GameConfig, session_file: &str) -> Result<(Box<dyn UIFuncs>, GameConfig), String> {
+    let mut config = config.clone();
+    let seed = config.round.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    config.round.seed = Some(seed);
+    let header = SessionHeader {
+        version: SESSION_VERSION,
+        seed,
+        config: config.clone(),
+        history: load_records(&config.history_file)?.into_iter().filter(|record| record.player == config.player).collect(),
+        collection: Collection::load(&config.collection_dir, &config.player)?,
+    };
+    let file = File::create(session_file).map_err(|e| format!("can not create session file {}: {}", session_file, e))?;
+    let mut writer = BufWriter::new(file);
+    let line = serde_json::to_string(&header).map_err(|e| e.to_string())?;
+    writeln!(writer, "{}", line).and_then(|_| writer.flush())
+        .map_err(|e| format!("can not write session file {}: {}", session_file, e))?;
+    Ok((Box::new(RecordingUIFuncs { inner: uifuncs, writer: Arc::new(Mutex::new(Some(writer))), start: None }), config))
+}
+
+/// the keys and resizes are written by their own threads
+type SharedWriter = Arc<Mutex<Option<BufWriter<File>>>>;
+
+/// each line is flushed so that the lines before a crash are kept. writing stops at the first error
+fn write_line(writer: &SharedWriter, line: &SessionLine) {
+    let mut writer = writer.lock().unwrap();
+    if let Some(w) = writer.as_mut() {
+        let written = serde_json::to_string(line).map_err(|e| e.to_string())
+            .and_then(|line| writeln!(w, "{}", line).and_then(|_| w.flush()).map_err(|e| e.to_string()));
+        if written.is_err() {
+            *writer = None;
+        }
+    }
+}
+
+/// writes every key the inner backend reads and every resize of the screen to the session file
+pub struct RecordingUIFuncs {
+    inner: Box<dyn UIFuncs>,
+    writer: SharedWriter,
+    /// times are counted from the first of the key event thread and the resize events
+    start: Option<Instant>,
+}
+
+impl UIFuncs for RecordingUIFuncs {
+    fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent> {
+        let inner_rx = self.inner.start_keyevent_thread();
+        let writer = self.writer.clone();
+        let (chan_tx, chan_rx) = channel::unbounded::<UIKeyEvent>();
+        let start = *self.start.get_or_insert_with(Instant::now);
+        thread::spawn(move || {
+            for key in inner_rx.iter() {
+                write_line(&writer, &SessionLine::Key(KeyRecord { time: start.elapsed().as_millis() as u64, key }));
+                if chan_tx.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        chan_rx
+    }
+
+    fn get_graphics(&self) -> Box<dyn UIGraphics> {
+        self.inner.get_graphics()
+    }
+
+    fn render_stats(&self) -> Option<SharedRenderStats> {
+        self.inner.render_stats()
+    }
+
+    fn capture(&self) -> Option<SharedCapture> {
+        self.inner.capture()
+    }
+
+    fn resize_events(&mut self) -> Option<channel::Receiver<(usize, usize)>> {
+        let inner_rx = self.inner.resize_events()?;
+        let writer = self.writer.clone();
+        let (chan_tx, chan_rx) = channel::unbounded::<(usize, usize)>();
+        let start = *self.start.get_or_insert_with(Instant::now);
+        thread::spawn(move || {
+            for (width, height) in inner_rx.iter() {
+                write_line(&writer, &SessionLine::Resize(ResizeRecord { time: start.elapsed().as_millis() as u64, width, height }));
+                if chan_tx.send((width, height)).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(chan_rx)
+    }
+}
+
+/// sleep until `time` msec since `start`
+fn wait_until(start: Instant, time: u64) {
+    let elapsed = start.elapsed().as_millis() as u64;
+    if time > elapsed {
+        thread::sleep(Duration::from_millis(time - elapsed));
+    }
+}
+
+/// plays the recorded keys and resizes at their times instead of reading the keyboard and the terminal.
+/// ESC on the keyboard ends the replay
+pub struct ReplayUIFuncs {
+    inner: Box<dyn UIFuncs>,
+    keys: Vec<KeyRecord>,
+    resizes: Vec<ResizeRecord>,
+    start: Option<Instant>,
+}
+
+impl ReplayUIFuncs {
+    pub fn new(inner: Box<dyn UIFuncs>, session: &Session) -> Self {
+        ReplayUIFuncs { inner, keys: session.keys.clone(), resizes: session.resizes.clone(), start: None }
+    }
+}
+
+impl UIFuncs for ReplayUIFuncs {
+    fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent> {
+        let inner_rx = self.inner.start_keyevent_thread();
+        let keys = std::mem::take(&mut self.keys);
+        let (chan_tx, chan_rx) = channel::unbounded::<UIKeyEvent>();
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let replay_tx = chan_tx.clone();
+        thread::spawn(move || {
+            for record in keys {
+                wait_until(start, record.time);
+                if replay_tx.send(record.key).is_err() {
+                    break;
+                }
+            }
+        });
+        thread::spawn(move || {
+            for key in inner_rx.iter() {
+                if key == UIKeyEvent::ESC {
+                    let _ = chan_tx.send(key);
+                    break;
+                }
+            }
+        });
+        chan_rx
+    }
+
+    fn get_graphics(&self) -> Box<dyn UIGraphics> {
+        self.inner.get_graphics()
+    }
+
+    fn render_stats(&self) -> Option<SharedRenderStats> {
+        self.inner.render_stats()
+    }
+
+    fn capture(&self) -> Option<SharedCapture> {
+        self.inner.capture()
+    }
+
+    /// the recorded sizes are used instead of the terminal's. a session recorded without them follows the terminal
+    fn resize_events(&mut self) -> Option<channel::Receiver<(usize, usize)>> {
+        if self.resizes.is_empty() {
+            return self.inner.resize_events();
+        }
+        let resizes = std::mem::take(&mut self.resizes);
+        let (chan_tx, chan_rx) = channel::unbounded::<(usize, usize)>();
+        let start = *self.start.get_or_insert_with(Instant::now);
+        thread::spawn(move || {
+            for record in resizes {
+                wait_until(start, record.time);
+                if chan_tx.send((record.width, record.height)).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(chan_rx)
+    }
+}
+
+#[test]
+fn session_record_works() {
+    use super::uifuncs::DebugUIFuncs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let session_file = dir.path().join("session.jsonl").to_string_lossy().to_string();
+    let config = GameConfig {
+        history_file: dir.path().join("history.jsonl").to_string_lossy().to_string(),
+        collection_dir: dir.path().join("collection").to_string_lossy().to_string(),
+        ..GameConfig::default()
+    };
+    let mut caught = Collection::new();
+    caught.record_catch("001_megamouse_shark.png", 1000, 0, 0);
+    caught.save(&config.collection_dir, &config.player).unwrap();
+
+    let script = vec![(50, UIKeyEvent::Char('m')), (50, UIKeyEvent::Enter), (50, UIKeyEvent::ESC)];
+    let (mut uifuncs, recorded_config) = start_recording(Box::new(DebugUIFuncs::scripted(script)), &config, &session_file).unwrap();
+    assert!(recorded_config.round.seed.is_some());
+    let keyevent_rx = uifuncs.start_keyevent_thread();
+    let keys: Vec<UIKeyEvent> = keyevent_rx.iter().collect();
+    assert_eq!(keys, [UIKeyEvent::Char('m'), UIKeyEvent::Enter, UIKeyEvent::ESC]);
+
+    let session = Session::load(&session_file).unwrap();
+    assert_eq!(session.header.seed, recorded_config.round.seed.unwrap());
+    assert_eq!(session.keys.iter().map(|record| record.key).collect::<Vec<_>>(), keys);
+    assert!(session.keys.windows(2).all(|w| w[0].time < w[1].time));
+
+    let replay_dir = tempfile::tempdir().unwrap();
+    let replay_config = session.replay_config(&replay_dir).unwrap();
+    assert_eq!(replay_config.round.seed, recorded_config.round.seed);
+    assert!(replay_config.collection_dir.starts_with(replay_dir.path().to_str().unwrap()));
+    assert_eq!(Collection::load(&replay_config.collection_dir, &replay_config.player).unwrap(), caught);
+
+    // the keys come at the recorded times
+    let start = Instant::now();
+    let mut replay = ReplayUIFuncs::new(Box::new(DebugUIFuncs::scripted(Vec::new())), &session);
+    let replayed: Vec<UIKeyEvent> = replay.start_keyevent_thread().iter().take(3).collect();
+    assert_eq!(replayed, keys);
+    assert!(start.elapsed().as_millis() as u64 >= session.keys[2].time);
+
+    // a line cut off by a crash is ignored
+    let mut text = std::fs::read_to_string(&session_file).unwrap();
+    text.push_str(r#"{"time": 9"#);
+    std::fs::write(&session_file, text).unwrap();
+    assert_eq!(Session::load(&session_file).unwrap().keys.len(), 3);
+
+    // a broken line with keys after it is not
+    let mut lines: Vec<String> = std::fs::read_to_string(&session_file).unwrap().lines().map(|line| line.to_string()).collect();
+    lines.pop();
+    lines.insert(2, String::from(r#"{"time": 9"#));
+    std::fs::write(&session_file, lines.join("\n")).unwrap();
+    assert!(Session::load(&session_file).unwrap_err().contains("invalid line 3"));
+}
+
+/// telop texts and then subtelop texts in the order they were drawn on a screen of `wxh`. blank ones are left out
+/// so that it does not matter whether the title came before the first resize. the creature is left out too
+/// because its animation follows the frame clock rather than the keys
+#[cfg(test)]
+fn telop_progress(capture: &SharedCapture, config: &GameConfig, wxh: (usize, usize)) -> Vec<String> {
+    let stage = super::stage::Stage::new(super::stage::StageConfig { stage_wxh: wxh, ..config.stage_config() });
+    let capture = capture.lock().unwrap();
+    let mut progress = Vec::new();
+    for rows in [stage.telop_offset()..stage.subtelop_offset(), stage.subtelop_offset()..wxh.1].iter() {
+        let mut texts: Vec<String> = capture.frames.iter()
+            .filter(|frame| frame.cells.len() == wxh.1)
+            .map(|frame| frame.text_of_rows(rows.clone()))
+            .filter(|text| !text.trim().is_empty())
+            .collect();
+        texts.dedup();
+        progress.extend(texts);
+    }
+    progress
+}
+
+#[test]
+fn session_replay_works() {
+    use super::audiofuncs::{AudioFuncsType, generate_audiofuncs};
+    use super::capture::{CaptureUIFuncs, wait_for_capture};
+    use super::pict::{PictOrder, RoundConfig};
+
+    let timeout = Duration::from_secs(20);
+    let dir = tempfile::tempdir().unwrap();
+    let session_file = dir.path().join("session.jsonl").to_string_lossy().to_string();
+    let config = GameConfig {
+        stage_wxh: (120, 36),
+        aa_width: 60,
+        num_of_targets: 2,
+        round: RoundConfig { order: PictOrder::Shuffled, ..RoundConfig::default() },
+        history_file: dir.path().join("history.jsonl").to_string_lossy().to_string(),
+        collection_dir: dir.path().join("collection").to_string_lossy().to_string(),
+        ..GameConfig::default()
+    };
+    let null_audio = || generate_audiofuncs(AudioFuncsType::Null, &Default::default(), &Default::default(), Default::default());
+
+    // the terminal is smaller than the stage, so the stage is fitted in it
+    let (key_tx, key_rx) = channel::unbounded::<UIKeyEvent>();
+    let (resize_tx, resize_rx) = channel::unbounded::<(usize, usize)>();
+    resize_tx.send((100, 28)).unwrap();
+    let funcs = CaptureUIFuncs::keyed(key_rx).with_resizes(resize_rx);
+    let capture = funcs.capture().unwrap();
+    let (funcs, recorded_config) = start_recording(Box::new(funcs), &config, &session_file).unwrap();
+
+    // start a round after the title, catch the first creature and leave
+    let keys = super::controller::first_name_keys(&recorded_config);
+    let fitted = super::stage::Stage::new(super::stage::StageConfig { stage_wxh: (100, 28), ..config.stage_config() });
+    let (telop, subtelop) = (fitted.telop_offset()..fitted.subtelop_offset(), fitted.subtelop_offset()..28);
+    let driver = {
+        let capture = capture.clone();
+        let (collection_dir, player) = (config.collection_dir.to_string(), config.player.to_string());
+        thread::spawn(move || {
+            let title = wait_for_capture(&capture, timeout, |capture| {
+                capture.frames.iter().position(|frame| frame.cells.len() == 28 && !frame.text_of_rows(subtelop.clone()).trim().is_empty())
+                    .is_some_and(|i| i + 1 < capture.frames.len())
+            }).unwrap().text_of_rows(telop.clone());
+            // the replay sends the keys at the same times. the waits leave it room to be slower
+            thread::sleep(Duration::from_millis(300));
+            key_tx.send(UIKeyEvent::Char(' ')).unwrap();
+            wait_for_capture(&capture, timeout, |capture| {
+                let name = capture.last_frame().map(|frame| frame.text_of_rows(telop.clone())).unwrap_or_default();
+                name != title && !name.trim().is_empty()
+            }).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            for key in keys {
+                key_tx.send(UIKeyEvent::Char(key)).unwrap();
+            }
+            // the collection is saved at the catch
+            wait_for_capture(&capture, timeout, |_| Collection::load(&collection_dir, &player).is_ok_and(|caught| caught.len() > 0)).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            key_tx.send(UIKeyEvent::ESC).unwrap();
+        })
+    };
+    super::controller::control(funcs, null_audio(), &recorded_config).unwrap();
+    driver.join().unwrap();
+    let recorded = telop_progress(&capture, &config, (100, 28));
+
+    let session = Session::load(&session_file).unwrap();
+    assert_eq!(session.resizes.iter().map(|record| (record.width, record.height)).collect::<Vec<_>>(), [(100, 28)]);
+    assert_eq!(session.keys.last().map(|record| record.key), Some(UIKeyEvent::ESC));
+
+    // the replay has no terminal size of its own. the recorded one fits the stage the same way
+    let replay_dir = tempfile::tempdir().unwrap();
+    let replay_config = session.replay_config(&replay_dir).unwrap();
+    let replay = ReplayUIFuncs::new(Box::new(CaptureUIFuncs::new()), &session);
+    let replay_capture = replay.capture().unwrap();
+    super::controller::control(Box::new(replay), null_audio(), &replay_config).unwrap();
+    let replayed = telop_progress(&replay_capture, &config, (100, 28));
+
+    // the title, the name as it is typed up to the end and the caught creature
+    assert!(recorded.iter().any(|text| text.contains('@') && !text.contains('-')), "{:?}", recorded);
+    assert_eq!(replayed, recorded);
+    assert_eq!(replay_capture.lock().unwrap().last_frame().unwrap().cells.len(), 28);
+}
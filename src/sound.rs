@@ -0,0 +1,202 @@
+//! sound effects named in sounds.json next to picts_info.json
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// names of the sounds the game plays
+pub const CLICK: &str = "click";
+pub const TYPO: &str = "typo";
+pub const CATCH: &str = "catch";
+pub const AMBIENT: &str = "ambient";
+
+/// wav file of each sound. relative paths in sounds.json are relative to the pict dir
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SoundLibrary {
+    pub sounds: BTreeMap<String, PathBuf>,
+}
+
+impl SoundLibrary {
+    pub fn new() -> Self {
+        SoundLibrary::default()
+    }
+
+    /// a pict dir without sounds.json has no sounds
+    pub fn load(pict_dir: &str) -> Result<Self, String> {
+        let sound_json = Path::new(pict_dir).join("sounds.json");
+        let file = match File::open(&sound_json) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(SoundLibrary::new()),
+            Err(e) => return Err(format!("can not open {}: {}", sound_json.display(), e)),
+        };
+        let sounds: BTreeMap<String, String> = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("invalid {}: {}", sound_json.display(), e))?;
+        Ok(SoundLibrary {
+            sounds: sounds.into_iter().map(|(name, file)| (name, Path::new(pict_dir).join(file))).collect(),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        self.sounds.get(name).map(|path| path.as_path())
+    }
+
+    /// render the effects sounds.json does not name into `dir`
+    pub fn synthesize_missing(&mut self, dir: &Path) -> Result<(), String> {
+        for name in super::synth::EFFECTS.iter() {
+            if self.sounds.contains_key(*name) {
+                continue;
+            }
+            if let Some(wav) = super::synth::render_wav(name) {
+                let path = dir.join(format!("{}.wav", name));
+                std::fs::write(&path, wav).map_err(|e| format!("can not write sound file {}: {}", path.display(), e))?;
+                self.sounds.insert(name.to_string(), path);
+            }
+        }
+        Ok(())
+    }
+
+    /// names whose wav file does not exist
+    pub fn missing(&self) -> Vec<&str> {
+        self.sounds.iter().filter(|(_, path)| !path.is_file()).map(|(name, _)| name.as_str()).collect()
+    }
+}
+
+/// plays the wav files of a library with a player command run by `sh -c`
+pub struct SoundPlayer {
+    library: SoundLibrary,
+    script: Option<String>,
+    /// set to stop everything started under the name so far
+    stops: RefCell<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl SoundPlayer {
+    const POLL_MSEC: u64 = 20;
+
+    /// without a player command every sound is silent
+    pub fn new(library: SoundLibrary, script: Option<String>) -> Self {
+        SoundPlayer {
+            library,
+            script,
+            stops: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn play(&self, name: &str) {
+        self.start(name, false);
+    }
+
+    /// play the sound again and again until it is stopped
+    pub fn play_loop(&self, name: &str) {
+        self.start(name, true);
+    }
+
+    pub fn stop(&self, name: &str) {
+        if let Some(stop) = self.stops.borrow_mut().remove(name) {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stop_all(&self) {
+        for (_, stop) in self.stops.borrow_mut().drain() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn start(&self, name: &str, repeat: bool) {
+        let (script, file) = match (&self.script, self.library.get(name)) {
+            (Some(script), Some(file)) => (script.to_string(), file.to_path_buf()),
+            _ => return,
+        };
+        let stop = self.stops.borrow_mut().entry(name.to_string()).or_default().clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let child = Command::new("sh")
+                    .arg("-c").arg(&script).arg("aquatyping").arg(&file)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                match child {
+                    Ok(mut child) => {
+                        if !SoundPlayer::wait_child(&mut child, &stop) {
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+                if !repeat {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// returns false when the player failed or the sound was stopped
+    fn wait_child(child: &mut Child, stop: &AtomicBool) -> bool {
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => return status.success(),
+                Ok(None) => {},
+                Err(_) => return false,
+            }
+            if stop.load(Ordering::Relaxed) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return false;
+            }
+            thread::sleep(Duration::from_millis(SoundPlayer::POLL_MSEC));
+        }
+    }
+}
+
+#[test]
+fn sound_library_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let pict_dir = dir.path().to_str().unwrap();
+    assert!(SoundLibrary::load(pict_dir).unwrap().sounds.is_empty());
+
+    std::fs::write(dir.path().join("sounds.json"), r#"{"click": "sounds/click.wav", "typo": "/no/such/typo.wav"}"#).unwrap();
+    std::fs::create_dir(dir.path().join("sounds")).unwrap();
+    std::fs::write(dir.path().join("sounds/click.wav"), b"RIFF").unwrap();
+    let library = SoundLibrary::load(pict_dir).unwrap();
+    assert_eq!(library.get(CLICK), Some(dir.path().join("sounds/click.wav").as_path()));
+    assert_eq!(library.get(CATCH), None);
+    assert_eq!(library.missing(), [TYPO]);
+
+    let mut synthesized = library.clone();
+    synthesized.synthesize_missing(dir.path()).unwrap();
+    assert_eq!(synthesized.get(CLICK), library.get(CLICK));
+    assert_eq!(synthesized.get(CATCH), Some(dir.path().join("catch.wav").as_path()));
+    assert_eq!(synthesized.missing(), [TYPO]);
+
+    std::fs::write(dir.path().join("sounds.json"), "[]").unwrap();
+    assert!(SoundLibrary::load(pict_dir).is_err());
+}
+
+#[test]
+fn sound_player_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("played.log");
+    let mut library = SoundLibrary::new();
+    library.sounds.insert(String::from(AMBIENT), PathBuf::from("ambient.wav"));
+    // the "player" writes the file name to the log
+    let script = format!(r#"echo "$1" >> {}; sleep 0.05"#, log.display());
+    let player = SoundPlayer::new(library, Some(script));
+    player.play(CLICK);
+    player.play_loop(AMBIENT);
+    thread::sleep(Duration::from_millis(400));
+    player.stop(AMBIENT);
+    thread::sleep(Duration::from_millis(100));
+    let played = std::fs::read_to_string(&log).unwrap();
+    assert!(played.lines().count() >= 2);
+    assert!(played.lines().all(|line| line == "ambient.wav"));
+
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), played);
+}
@@ -0,0 +1,298 @@
+//! queue of utterances spoken by tts processes with priorities and cancellation
+
+use serde::{Deserialize, Serialize};
+use std::process::Child;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// announcements interrupt names and syllables. names interrupt nothing but go first
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechPriority {
+    #[default]
+    Syllable,
+    Name,
+    Announcement,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UtteranceState {
+    Queued,
+    Speaking,
+    Done,
+    /// removed from the queue because it was stale or a more important utterance came
+    Dropped,
+    Cancelled,
+}
+
+impl UtteranceState {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, UtteranceState::Queued | UtteranceState::Speaking)
+    }
+}
+
+struct HandleState {
+    state: UtteranceState,
+    cancel_requested: bool,
+}
+
+/// reports how an utterance went. the clones share the same state
+#[derive(Clone)]
+pub struct UtteranceHandle {
+    inner: Arc<(Mutex<HandleState>, Condvar)>,
+}
+
+impl Default for UtteranceHandle {
+    fn default() -> Self {
+        UtteranceHandle::new()
+    }
+}
+
+impl UtteranceHandle {
+    pub fn new() -> Self {
+        UtteranceHandle {
+            inner: Arc::new((Mutex::new(HandleState { state: UtteranceState::Queued, cancel_requested: false }), Condvar::new())),
+        }
+    }
+
+    pub fn state(&self) -> UtteranceState {
+        self.inner.0.lock().unwrap().state
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state().is_finished()
+    }
+
+    /// stop the utterance whether it is queued or being spoken
+    pub fn cancel(&self) {
+        self.inner.0.lock().unwrap().cancel_requested = true;
+    }
+
+    /// wait until the utterance finishes. returns false on timeout
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (lock, condvar) = &*self.inner;
+        let guard = lock.lock().unwrap();
+        let (guard, _) = condvar.wait_timeout_while(guard, timeout, |s| !s.state.is_finished()).unwrap();
+        guard.state.is_finished()
+    }
+
+    fn is_cancel_requested(&self) -> bool {
+        self.inner.0.lock().unwrap().cancel_requested
+    }
+
+    /// used by backends which do not go through a SpeechQueue
+    pub fn set_state(&self, state: UtteranceState) {
+        let (lock, condvar) = &*self.inner;
+        lock.lock().unwrap().state = state;
+        condvar.notify_all();
+    }
+}
+
+/// starts the tts process of an utterance. None when nothing can speak it
+pub type SpawnFn = Box<dyn Fn(&str, &str) -> Option<Child> + Send + Sync>;
+
+struct Pending {
+    text: String,
+    lang: String,
+    priority: SpeechPriority,
+    queued_at: Instant,
+    handle: UtteranceHandle,
+}
+
+struct QueueState {
+    pending: Vec<Pending>,
+    speaking: Vec<(SpeechPriority, UtteranceHandle)>,
+}
+
+/// utterances are spoken by a fixed number of workers, the most important one first
+pub struct SpeechQueue {
+    state: Arc<(Mutex<QueueState>, Condvar)>,
+}
+
+impl SpeechQueue {
+    /// syllables waiting longer than this are not spoken any more
+    pub const STALE_MSEC: u128 = 1000;
+    /// only the latest syllables are kept in the queue
+    pub const MAX_PENDING_SYLLABLES: usize = 2;
+    const POLL_MSEC: u64 = 20;
+
+    /// `max_concurrent` tts processes run at the same time at most
+    pub fn new(max_concurrent: usize, spawn: SpawnFn) -> Self {
+        let state = Arc::new((Mutex::new(QueueState { pending: Vec::new(), speaking: Vec::new() }), Condvar::new()));
+        let spawn = Arc::new(spawn);
+        for _ in 0..max_concurrent.max(1) {
+            let state = state.clone();
+            let spawn = spawn.clone();
+            thread::spawn(move || SpeechQueue::work(&state, &spawn));
+        }
+        SpeechQueue {
+            state,
+        }
+    }
+
+    pub fn push(&self, text: &str, lang: &str, priority: SpeechPriority, handle: UtteranceHandle) {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if priority == SpeechPriority::Announcement {
+            for (_, speaking) in state.speaking.iter().filter(|(p, _)| *p < priority) {
+                speaking.cancel();
+            }
+            SpeechQueue::drop_pending(&mut state, |p| p.priority < priority);
+        }
+        if priority == SpeechPriority::Syllable {
+            let syllables = state.pending.iter().filter(|p| p.priority == priority).count();
+            let mut excess = (syllables + 1).saturating_sub(SpeechQueue::MAX_PENDING_SYLLABLES);
+            SpeechQueue::drop_pending(&mut state, |p| {
+                if p.priority == priority && excess > 0 {
+                    excess -= 1;
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+        state.pending.push(Pending {
+            text: text.to_string(),
+            lang: lang.to_string(),
+            priority,
+            queued_at: Instant::now(),
+            handle,
+        });
+        condvar.notify_one();
+    }
+
+    /// cancel every queued and speaking utterance
+    pub fn clear(&self) {
+        let mut state = self.state.0.lock().unwrap();
+        for (_, speaking) in state.speaking.iter() {
+            speaking.cancel();
+        }
+        for pending in state.pending.drain(..) {
+            pending.handle.set_state(UtteranceState::Cancelled);
+        }
+    }
+
+    fn drop_pending<F: FnMut(&Pending) -> bool>(state: &mut QueueState, mut f: F) {
+        let mut kept = Vec::new();
+        for pending in state.pending.drain(..) {
+            if f(&pending) {
+                pending.handle.set_state(UtteranceState::Dropped);
+            } else {
+                kept.push(pending);
+            }
+        }
+        state.pending = kept;
+    }
+
+    /// the most important utterance first. the oldest one wins a tie
+    fn next(state: &mut QueueState) -> Option<Pending> {
+        let index = state.pending.iter().enumerate()
+            .max_by(|(i, a), (j, b)| a.priority.cmp(&b.priority).then(j.cmp(i)))
+            .map(|(i, _)| i)?;
+        Some(state.pending.remove(index))
+    }
+
+    fn work(state: &(Mutex<QueueState>, Condvar), spawn: &SpawnFn) {
+        loop {
+            let pending = {
+                let (lock, condvar) = state;
+                let mut queue = condvar.wait_while(lock.lock().unwrap(), |q| q.pending.is_empty()).unwrap();
+                let pending = SpeechQueue::next(&mut queue).unwrap();
+                if pending.handle.is_cancel_requested() {
+                    pending.handle.set_state(UtteranceState::Cancelled);
+                    continue;
+                }
+                if pending.priority == SpeechPriority::Syllable && pending.queued_at.elapsed().as_millis() > SpeechQueue::STALE_MSEC {
+                    pending.handle.set_state(UtteranceState::Dropped);
+                    continue;
+                }
+                pending.handle.set_state(UtteranceState::Speaking);
+                queue.speaking.push((pending.priority, pending.handle.clone()));
+                pending
+            };
+
+            let finished = match spawn(&pending.text, &pending.lang) {
+                Some(mut child) => SpeechQueue::wait_child(&mut child, &pending.handle),
+                None => UtteranceState::Done,
+            };
+
+            let mut queue = state.0.lock().unwrap();
+            queue.speaking.retain(|(_, handle)| !Arc::ptr_eq(&handle.inner, &pending.handle.inner));
+            pending.handle.set_state(finished);
+        }
+    }
+
+    fn wait_child(child: &mut Child, handle: &UtteranceHandle) -> UtteranceState {
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) | Err(_) => return UtteranceState::Done,
+                Ok(None) => {},
+            }
+            if handle.is_cancel_requested() {
+                let _ = child.kill();
+                let _ = child.wait();
+                return UtteranceState::Cancelled;
+            }
+            thread::sleep(Duration::from_millis(SpeechQueue::POLL_MSEC));
+        }
+    }
+}
+
+#[cfg(test)]
+fn sleeping_queue(spoken: Arc<Mutex<Vec<String>>>) -> SpeechQueue {
+    SpeechQueue::new(1, Box::new(move |text: &str, _lang: &str| {
+        spoken.lock().unwrap().push(text.to_string());
+        std::process::Command::new("sleep").arg("0.3").spawn().ok()
+    }))
+}
+
+#[test]
+fn speechqueue_priority_works() {
+    let spoken = Arc::new(Mutex::new(Vec::new()));
+    let queue = sleeping_queue(spoken.clone());
+    let first = UtteranceHandle::new();
+    queue.push("first", "en", SpeechPriority::Name, first.clone());
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(first.state(), UtteranceState::Speaking);
+
+    // the syllables wait while the name is spoken. only the latest ones are kept
+    let syllables: Vec<UtteranceHandle> = (0..3).map(|_| UtteranceHandle::new()).collect();
+    for (i, handle) in syllables.iter().enumerate() {
+        queue.push(&format!("syllable{}", i), "ja", SpeechPriority::Syllable, handle.clone());
+    }
+    let name = UtteranceHandle::new();
+    queue.push("name", "ja", SpeechPriority::Name, name.clone());
+    assert_eq!(syllables[0].state(), UtteranceState::Dropped);
+
+    assert!(name.wait(Duration::from_secs(5)));
+    assert!(syllables[2].wait(Duration::from_secs(5)));
+    assert_eq!(first.state(), UtteranceState::Done);
+    assert_eq!(*spoken.lock().unwrap(), ["first", "name", "syllable1", "syllable2"]);
+}
+
+#[test]
+fn speechqueue_announcement_interrupts() {
+    let spoken = Arc::new(Mutex::new(Vec::new()));
+    let queue = sleeping_queue(spoken.clone());
+    let syllable = UtteranceHandle::new();
+    queue.push("syllable", "ja", SpeechPriority::Syllable, syllable.clone());
+    thread::sleep(Duration::from_millis(50));
+    let waiting = UtteranceHandle::new();
+    queue.push("waiting", "ja", SpeechPriority::Syllable, waiting.clone());
+
+    let announcement = UtteranceHandle::new();
+    queue.push("I caught it!", "en", SpeechPriority::Announcement, announcement.clone());
+    assert!(syllable.wait(Duration::from_secs(5)));
+    assert_eq!(syllable.state(), UtteranceState::Cancelled);
+    assert_eq!(waiting.state(), UtteranceState::Dropped);
+    assert!(announcement.wait(Duration::from_secs(5)));
+    assert_eq!(announcement.state(), UtteranceState::Done);
+
+    let cancelled = UtteranceHandle::new();
+    queue.push("cancelled", "en", SpeechPriority::Name, cancelled.clone());
+    cancelled.cancel();
+    assert!(cancelled.wait(Duration::from_secs(5)));
+    assert_eq!(cancelled.state(), UtteranceState::Cancelled);
+    assert_eq!(*spoken.lock().unwrap(), ["syllable", "I caught it!"]);
+}
@@ -1,4 +1,5 @@
-use rand::{thread_rng, Rng};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use image2ascii::{Char2DArray, CharPosition};
 use super::asciiart::{AsciiArt, AsciiArtContext, AsciiArtState};
 use super::background::{Background, BackgroundType, generate_background_randomly};
@@ -6,7 +7,23 @@ use super::effector::EffectorType;
 use super::behavior::BehaviorType;
 use image2ascii::string2ascii;
 use super::character::{Character, CharacterConfig, CharacterStatus};
+use super::cellart::Cell2DArray;
+use super::uifuncs::RenderColor;
+
+/// font which has the japanese glyphs. it is not a part of the repository
+const TELOP_FONT: &str = "./font/wqy-microhei/WenQuanYiMicroHei.ttf";
+
+/// the font built into image2ascii is used when TELOP_FONT is not installed
+fn telop_font() -> Option<&'static str> {
+    if std::path::Path::new(TELOP_FONT).is_file() {
+        Some(TELOP_FONT)
+    } else {
+        None
+    }
+}
 
+/// below this the screen shows a notice instead of the stage. a stage configured smaller only needs its own size
+pub const MIN_STAGE_WXH: (usize, usize) = (40, 12);
 
 pub struct Stage {
     pict_area: Char2DArray,
@@ -17,7 +34,16 @@ pub struct Stage {
     config: StageConfig,
     //typed_char: Option<char>,
     typed_char: Option<Character>,
-
+    /// every random choice of the stage comes from here
+    rng: StdRng,
+    /// the size given at start. `config` has the size fitted in the screen
+    base: StageConfig,
+    screen_wxh: (usize, usize),
+    /// the art was made by `title` and is half as wide as the stage
+    title_art: bool,
+    /// text and pos of the telops shown now. drawn again after a resize
+    telop: (String, usize),
+    subtelop: (String, usize),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -25,32 +51,126 @@ pub struct StageConfig {
     pub stage_wxh: (usize, usize),
     pub aa_width: usize,
     pub framerate: u32,
+    /// colour the ascii art with the colours of the image
+    pub color_art: bool,
+    /// the same seed and commands draw the same frames. random when omitted
+    pub seed: Option<u64>,
 }
 
 impl Stage {
 
     pub fn new(config: StageConfig) -> Self {
-        let pict_area_height = ((config.stage_wxh.1 as f32 / 6.0) * 4.0) as usize;
-        let telop_area_height = ((config.stage_wxh.1 - pict_area_height) as f32 / 2.0) as usize;
-        let subtelop_area_height = config.stage_wxh.1 - pict_area_height - telop_area_height;
+        let (pict_area, telop_area, subtelop_area) = Stage::areas(config.stage_wxh);
         Stage {
-            pict_area: Char2DArray::new(config.stage_wxh.0, pict_area_height),
-            telop_area: Char2DArray::new(config.stage_wxh.0, telop_area_height),
-            subtelop_area: Char2DArray::new(config.stage_wxh.0, subtelop_area_height),
+            pict_area,
+            telop_area,
+            subtelop_area,
             ascii_art: Option::None,
             background: Option::None,
             config: config,
             typed_char: Option::None,
+            rng: match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            base: config,
+            screen_wxh: config.stage_wxh,
+            title_art: false,
+            telop: (String::new(), 0),
+            subtelop: (String::new(), 0),
+        }
+    }
+
+    /// pict, telop and subtelop areas from the top
+    fn areas(stage_wxh: (usize, usize)) -> (Char2DArray, Char2DArray, Char2DArray) {
+        let pict_area_height = ((stage_wxh.1 as f32 / 6.0) * 4.0) as usize;
+        let telop_area_height = ((stage_wxh.1 - pict_area_height) as f32 / 2.0) as usize;
+        let subtelop_area_height = stage_wxh.1 - pict_area_height - telop_area_height;
+        (
+            Char2DArray::new(stage_wxh.0, pict_area_height),
+            Char2DArray::new(stage_wxh.0, telop_area_height),
+            Char2DArray::new(stage_wxh.0, subtelop_area_height),
+        )
+    }
+
+    /// fit the stage in a screen of `screen_wxh`. it is never bigger than the configured size and the
+    /// ascii art is made again at a width scaled with it. false while the screen is too small
+    pub fn resize(&mut self, screen_wxh: (usize, usize)) -> bool {
+        self.screen_wxh = screen_wxh;
+        if self.is_too_small() {
+            return false;
+        }
+        let stage_wxh = (self.base.stage_wxh.0.min(screen_wxh.0), self.base.stage_wxh.1.min(screen_wxh.1));
+        if stage_wxh == self.config.stage_wxh {
+            return true;
+        }
+        self.config.stage_wxh = stage_wxh;
+        self.config.aa_width = ((self.base.aa_width as f32 * self.scale()) as usize).max(1);
+        let (pict_area, telop_area, subtelop_area) = Stage::areas(stage_wxh);
+        self.pict_area = pict_area;
+        self.telop_area = telop_area;
+        self.subtelop_area = subtelop_area;
+        // the typed character goes away in a moment anyway
+        self.typed_char = None;
+        let context = AsciiArtContext {
+            stage_wxh,
+            aa_width: if self.title_art { self.title_aa_width() } else { self.config.aa_width },
+            framerate: self.config.framerate,
+        };
+        if let Some(aa) = &mut self.ascii_art {
+            if aa.resize(&context).is_err() {
+                self.ascii_art = None;
+            }
+        }
+        true
+    }
+
+    pub fn is_too_small(&self) -> bool {
+        let (min_w, min_h) = self.min_wxh();
+        self.screen_wxh.0 < min_w || self.screen_wxh.1 < min_h
+    }
+
+    fn min_wxh(&self) -> (usize, usize) {
+        (MIN_STAGE_WXH.0.min(self.base.stage_wxh.0), MIN_STAGE_WXH.1.min(self.base.stage_wxh.1))
+    }
+
+    /// covers the whole screen while it is too small for the stage
+    pub fn too_small_notice(&self) -> Char2DArray {
+        let (width, height) = self.screen_wxh;
+        let (min_w, min_h) = self.min_wxh();
+        let lines = [String::from("terminal too small"), format!("{}x{} < {}x{}", width, height, min_w, min_h)];
+        let mut notice = Char2DArray::new(width, height);
+        let top = height.saturating_sub(lines.len()) / 2;
+        for (i, line) in lines.iter().enumerate().take(height) {
+            let left = width.saturating_sub(line.chars().count()) / 2;
+            for (x, ch) in line.chars().enumerate().take(width - left) {
+                notice.buffer[top + i][left + x] = ch;
+            }
         }
+        notice
+    }
+
+    /// how much smaller the stage is than the configured one
+    fn scale(&self) -> f32 {
+        let (width, height) = self.config.stage_wxh;
+        (width as f32 / self.base.stage_wxh.0 as f32).min(height as f32 / self.base.stage_wxh.1 as f32)
+    }
+
+    fn title_aa_width(&self) -> usize {
+        ((self.base.stage_wxh.0 as f32 * self.scale()) as usize / 2).max(1)
     }
 
     pub fn title(&mut self, image_file: &str) {
         let context = AsciiArtContext{
             stage_wxh: self.config.stage_wxh,
-            aa_width: self.config.stage_wxh.0 / 2,
+            aa_width: self.title_aa_width(),
             framerate: self.config.framerate,
         };
-        if let Ok(aa) = AsciiArt::from_image(image_file, &context, EffectorType::NO, EffectorType::NO, BehaviorType::NO) {
+        self.title_art = true;
+        if let Ok(mut aa) = AsciiArt::from_image(image_file, &context, EffectorType::NO, EffectorType::NO, BehaviorType::NO, &mut self.rng) {
+            if self.config.color_art {
+                let _ = aa.load_colors();
+            }
             self.ascii_art = Option::Some(aa);
         } else {
             self.ascii_art = Option::None;
@@ -58,6 +178,14 @@ impl Stage {
         self.background = None;
     }
 
+    /// title image painted in one character. used for creatures not caught yet
+    pub fn silhouette(&mut self, image_file: &str) {
+        self.title(image_file);
+        if let Some(aa) = &mut self.ascii_art {
+            aa.silhouette('#');
+        }
+    }
+
     pub fn telop_offset(&self) -> usize {
         self.pict_area.height()
     }
@@ -72,14 +200,25 @@ impl Stage {
             aa_width: self.config.aa_width,
             framerate: self.config.framerate,
         };
-        if let Ok(aa) = AsciiArt::from_image_easy(image_file, &context) {
+        self.title_art = false;
+        if let Ok(mut aa) = AsciiArt::from_image_easy(image_file, &context, &mut self.rng) {
+            if self.config.color_art {
+                let _ = aa.load_colors();
+            }
             self.ascii_art = Option::Some(aa);
         } else {
             self.ascii_art = Option::None;
         }
-        self.background = Some(generate_background_randomly());
+        self.background = Some(generate_background_randomly(&mut self.rng));
     }
     
+    /// nothing but blank areas remain
+    pub fn clear(&mut self) {
+        self.ascii_art = None;
+        self.background = None;
+        self.typed_char = None;
+    }
+
     pub fn disappear(&mut self){
         if let Some(aa) = &mut self.ascii_art {
             aa.disapper();
@@ -89,11 +228,11 @@ impl Stage {
     
 
     pub fn update_telop(&mut self, text: &str, pos: usize) -> &Char2DArray {
-        let mut rng = thread_rng();
+        self.telop = (text.to_string(), pos);
         let mut clear = Char2DArray::new(self.config.stage_wxh.0, self.telop_area.height());
         clear.overwrite_char_all(' ');
         self.telop_area.overwrite_rect(&clear, CharPosition{x:0,y:0}, Option::None);
-        if let Ok(telop) = string2ascii(text, self.telop_area.height() as f32, '@', Some((pos, '-')), Some("./font/wqy-microhei/WenQuanYiMicroHei.ttf")) {
+        if let Ok(telop) = string2ascii(text, self.telop_area.height() as f32, '@', Some((pos, '-')), telop_font()) {
             self.telop_area.overwrite_rect(&telop, CharPosition{x:0, y:0}, Option::None);
             //self.telop_area = telop;
         } else {
@@ -125,14 +264,26 @@ impl Stage {
             duration_ms: 500,
             framerate: self.config.framerate,
         };
-        self.typed_char = Some(Character::new(ch, config));
+        self.typed_char = Some(Character::new(ch, config, &mut self.rng));
+    }
+
+    /// the telop shown now drawn at the size of the stage
+    pub fn refresh_telop(&mut self) -> &Char2DArray {
+        let (text, pos) = self.telop.clone();
+        self.update_telop(&text, pos)
+    }
+
+    pub fn refresh_subtelop(&mut self) -> &Char2DArray {
+        let (text, pos) = self.subtelop.clone();
+        self.update_subtelop(&text, pos)
     }
 
     pub fn update_subtelop(&mut self, text: &str, pos: usize) -> &Char2DArray {
+        self.subtelop = (text.to_string(), pos);
         let mut clear = Char2DArray::new(self.config.stage_wxh.0, self.subtelop_area.height());
         clear.overwrite_char_all(' ');
         self.subtelop_area.overwrite_rect(&clear, CharPosition{x:0,y:0}, Option::None);
-        if let Ok(telop) = string2ascii(text, self.subtelop_area.height() as f32, '@', Some((pos, '-')), Some("./font/wqy-microhei/WenQuanYiMicroHei.ttf")) {
+        if let Ok(telop) = string2ascii(text, self.subtelop_area.height() as f32, '@', Some((pos, '-')), telop_font()) {
             self.subtelop_area.overwrite_rect(&telop, CharPosition{x:0, y:0}, Option::None);
         } else {
             self.subtelop_area.overwrite_char_all(' ');
@@ -196,6 +347,25 @@ impl Stage {
 
     }
 
+    /// pict area with the colours of the ascii art. the other characters get `fg` and `bg`
+    pub fn update_pict_cells(&mut self, fg: RenderColor, bg: RenderColor) -> Cell2DArray {
+        self.update_pict();
+        let mut cells = Cell2DArray::from_chars(&self.pict_area, fg, bg);
+        if let Some(aa) = &self.ascii_art {
+            if let Some(colors) = aa.get_colors() {
+                let pos = aa.get_position();
+                cells.overwrite_fg_center(aa.get_data(), colors, CharPosition{x: pos.0, y: pos.1}, Some(' '));
+                // the typed character is drawn over the art
+                if let Some(ch) = &self.typed_char {
+                    let data = ch.get_data();
+                    let plain = vec![vec![fg; data.width()]; data.height()];
+                    cells.overwrite_fg(data, &plain, ch.get_position(), Some(' '));
+                }
+            }
+        }
+        cells
+    }
+
     pub fn has_typed_char(&self) -> bool {
         match self.typed_char {
             Some(_) => true,
@@ -211,10 +381,11 @@ fn stage_new_works() {
         stage_wxh: (600, 150),
         aa_width: 250,
         framerate: 10,
-
+        color_art: false,
+        seed: None,
     };
     let mut stage = Stage::new(config);
-    stage.appear("/Users/shizuku/drawings/001_megamouse_shark.png");
+    stage.appear("./drawings/001_megamouse_shark.png");
     
     stage.pict_area.debug_print();
     stage.update_telop("MEGAMOUSE SHARK", 0).debug_print();
@@ -315,3 +486,65 @@ fn stage_new_works() {
 
 
 }
+
+#[test]
+fn stage_seed_works() {
+    let config = StageConfig {
+        stage_wxh: (200, 60),
+        aa_width: 80,
+        framerate: 10,
+        color_art: false,
+        seed: Some(42),
+    };
+    let frames = |config: StageConfig| -> Vec<Vec<Vec<char>>> {
+        let mut stage = Stage::new(config);
+        stage.appear("./drawings/001_megamouse_shark.png");
+        stage.update_character('A');
+        (0..30).map(|_| stage.update_pict().buffer.clone()).collect()
+    };
+    assert_eq!(frames(config), frames(config));
+}
+
+#[test]
+fn stage_resize_works() {
+    let config = StageConfig {
+        stage_wxh: (200, 60),
+        aa_width: 80,
+        framerate: 10,
+        color_art: true,
+        seed: Some(1),
+    };
+    let mut stage = Stage::new(config);
+    stage.appear("./drawings/001_megamouse_shark.png");
+    stage.update_telop("SHARK", 2);
+    let wide = stage.ascii_art.as_ref().unwrap().get_data().width();
+    stage.update_pict();
+
+    // a wider screen does not make the stage bigger than configured
+    assert!(stage.resize((300, 80)));
+    assert_eq!(stage.pict_area.width(), 200);
+
+    assert!(stage.resize((100, 45)));
+    assert_eq!((stage.pict_area.width(), stage.subtelop_offset() + stage.subtelop_area.height()), (100, 45));
+    assert_eq!(stage.telop_offset(), 30);
+    // the art keeps its size against the stage
+    let aa = stage.ascii_art.as_ref().unwrap();
+    assert_eq!(aa.get_data().width(), wide / 2);
+    assert_eq!(aa.get_colors().unwrap()[0].len(), wide / 2);
+    assert!(stage.refresh_telop().buffer.iter().flatten().any(|ch| *ch == '@'));
+    for _ in 0..20 {
+        assert_eq!(stage.update_pict_cells(RenderColor::Black, RenderColor::White).width(), 100);
+    }
+
+    assert!(!stage.resize((30, 45)));
+    assert!(stage.is_too_small());
+    let notice = stage.too_small_notice();
+    assert_eq!((notice.width(), notice.height()), (30, 45));
+    assert!(notice.to_lines().iter().any(|line| line.contains("terminal too small")));
+    assert!(notice.to_lines().iter().any(|line| line.contains("30x45 < 40x12")));
+    // the stage is as it was before
+    assert_eq!(stage.pict_area.width(), 100);
+    assert!(stage.resize((200, 60)));
+    assert!(!stage.is_too_small());
+    assert_eq!(stage.ascii_art.as_ref().unwrap().get_data().width(), wide);
+}
@@ -0,0 +1,231 @@
+//! small synthesiser which renders the sound effects so that no asset files have to be shipped
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::sound::{CLICK, TYPO, CATCH, AMBIENT};
+
+pub const SAMPLE_RATE: u32 = 22050;
+
+/// effects the synthesiser can render
+pub const EFFECTS: [&str; 4] = [CLICK, TYPO, CATCH, AMBIENT];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    /// random values held for one period of the frequency
+    Noise,
+}
+
+/// attack, decay and release are seconds. sustain is the level held after the decay
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Envelope {
+    /// level at `t` seconds of a note lasting `duration` seconds including the release
+    pub fn level(&self, t: f32, duration: f32) -> f32 {
+        let level = if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        };
+        let release_start = duration - self.release;
+        if t > release_start {
+            level * ((duration - t) / self.release).max(0.0)
+        } else {
+            level
+        }
+    }
+}
+
+/// one note whose pitch sweeps from `start_hz` to `end_hz`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub start_hz: f32,
+    pub end_hz: f32,
+    pub duration: f32,
+    pub volume: f32,
+    pub envelope: Envelope,
+}
+
+impl Tone {
+    pub fn render(&self, rng: &mut StdRng) -> Vec<f32> {
+        let num_of_samples = (self.duration * SAMPLE_RATE as f32) as usize;
+        let mut phase = 0.0f32;
+        let mut noise = 0.0f32;
+        (0..num_of_samples).map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            // an exponential sweep sounds even to the ear
+            let hz = self.start_hz * (self.end_hz / self.start_hz).powf(t / self.duration);
+            phase += hz / SAMPLE_RATE as f32;
+            if phase >= 1.0 {
+                phase -= 1.0;
+                noise = rng.gen_range(-1.0, 1.0);
+            }
+            let value = match self.waveform {
+                Waveform::Sine => (phase * 2.0 * PI).sin(),
+                Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+                Waveform::Noise => noise,
+            };
+            value * self.volume * self.envelope.level(t, self.duration)
+        }).collect()
+    }
+}
+
+/// tones mixed together. each one starts at its offset in seconds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sound {
+    pub tones: Vec<(f32, Tone)>,
+}
+
+impl Sound {
+    pub fn new() -> Self {
+        Sound::default()
+    }
+
+    pub fn tone(mut self, offset: f32, tone: Tone) -> Self {
+        self.tones.push((offset, tone));
+        self
+    }
+
+    /// noise is seeded so that the same sound is rendered every time
+    pub fn render(&self, seed: u64) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut samples: Vec<f32> = Vec::new();
+        for (offset, tone) in self.tones.iter() {
+            let start = (offset * SAMPLE_RATE as f32) as usize;
+            let rendered = tone.render(&mut rng);
+            if samples.len() < start + rendered.len() {
+                samples.resize(start + rendered.len(), 0.0);
+            }
+            for (i, value) in rendered.iter().enumerate() {
+                samples[start + i] += value;
+            }
+        }
+        samples.iter().map(|value| value.clamp(-1.0, 1.0)).collect()
+    }
+}
+
+/// 16 bit mono pcm wav file
+pub fn to_wav(samples: &[f32]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // pcm, 1 channel
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for value in samples {
+        wav.extend_from_slice(&((value * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    wav
+}
+
+const PLUCK: Envelope = Envelope { attack: 0.002, decay: 0.03, sustain: 0.5, release: 0.03 };
+
+/// the sound of an effect. None for names the synthesiser does not know
+pub fn effect(name: &str) -> Option<Sound> {
+    match name {
+        // a short rising chirp for each correct key
+        CLICK => Some(Sound::new()
+            .tone(0.0, Tone { waveform: Waveform::Square, start_hz: 600.0, end_hz: 1400.0, duration: 0.06, volume: 0.2, envelope: PLUCK })),
+        // a low thud for a typo
+        TYPO => Some(Sound::new()
+            .tone(0.0, Tone { waveform: Waveform::Sine, start_hz: 160.0, end_hz: 50.0, duration: 0.2, volume: 0.8,
+                envelope: Envelope { attack: 0.005, decay: 0.05, sustain: 0.6, release: 0.1 } })
+            .tone(0.0, Tone { waveform: Waveform::Noise, start_hz: 800.0, end_hz: 200.0, duration: 0.08, volume: 0.25, envelope: PLUCK })),
+        // c e g c arpeggio when a creature is caught
+        CATCH => Some([523.25, 659.25, 783.99].iter().enumerate()
+            .fold(Sound::new(), |sound, (i, hz)| sound.tone(i as f32 * 0.1,
+                Tone { waveform: Waveform::Square, start_hz: *hz, end_hz: *hz, duration: 0.12, volume: 0.25, envelope: PLUCK }))
+            .tone(0.3, Tone { waveform: Waveform::Square, start_hz: 1046.5, end_hz: 1046.5, duration: 0.4, volume: 0.25,
+                envelope: Envelope { attack: 0.005, decay: 0.1, sustain: 0.6, release: 0.2 } })),
+        // murmur of the sea with a few bubbles. it fades in and out so that it loops without a click
+        AMBIENT => Some([0.3, 0.9, 1.2, 2.1, 2.6].iter()
+            .fold(Sound::new()
+                .tone(0.0, Tone { waveform: Waveform::Noise, start_hz: 300.0, end_hz: 300.0, duration: 3.0, volume: 0.06,
+                    envelope: Envelope { attack: 0.5, decay: 0.0, sustain: 1.0, release: 0.5 } }),
+                |sound, offset| sound.tone(*offset,
+                    Tone { waveform: Waveform::Sine, start_hz: 300.0, end_hz: 900.0, duration: 0.08, volume: 0.15, envelope: PLUCK }))),
+        _ => None,
+    }
+}
+
+/// wav file of an effect rendered in memory
+pub fn render_wav(name: &str) -> Option<Vec<u8>> {
+    let seed = EFFECTS.iter().position(|effect| *effect == name).unwrap_or(0) as u64;
+    effect(name).map(|sound| to_wav(&sound.render(seed)))
+}
+
+/// write every effect to `dir` as NAME.wav
+pub fn dump_effects(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("can not create sound dir {}: {}", dir.display(), e))?;
+    let mut paths = Vec::new();
+    for name in EFFECTS.iter() {
+        let path = dir.join(format!("{}.wav", name));
+        let wav = render_wav(name).unwrap_or_default();
+        fs::write(&path, wav).map_err(|e| format!("can not write sound file {}: {}", path.display(), e))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[test]
+fn envelope_works() {
+    let envelope = Envelope { attack: 0.1, decay: 0.1, sustain: 0.5, release: 0.2 };
+    assert_eq!(envelope.level(0.0, 1.0), 0.0);
+    assert!((envelope.level(0.05, 1.0) - 0.5).abs() < 1e-6);
+    assert!((envelope.level(0.1, 1.0) - 1.0).abs() < 1e-6);
+    assert!((envelope.level(0.5, 1.0) - 0.5).abs() < 1e-6);
+    assert!((envelope.level(0.9, 1.0) - 0.25).abs() < 1e-6);
+    assert_eq!(envelope.level(1.0, 1.0), 0.0);
+}
+
+#[test]
+fn synth_works() {
+    let tone = Tone { waveform: Waveform::Square, start_hz: 100.0, end_hz: 100.0, duration: 0.5, volume: 0.5,
+        envelope: Envelope { attack: 0.0, decay: 0.0, sustain: 1.0, release: 0.0 } };
+    let samples = Sound::new().tone(0.0, tone).tone(0.25, tone).render(0);
+    assert_eq!(samples.len(), (0.75 * SAMPLE_RATE as f32) as usize);
+    // the overlapping square waves are clipped
+    assert!(samples.iter().all(|value| value.abs() <= 1.0));
+    assert!(samples.iter().any(|value| *value == 1.0));
+
+    for name in EFFECTS.iter() {
+        let wav = render_wav(name).unwrap();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        let data_len = u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize;
+        assert!(data_len > 0);
+        assert_eq!(wav.len(), 44 + data_len);
+        assert_eq!(render_wav(name), Some(wav));
+    }
+    assert_eq!(render_wav("no_such_sound"), None);
+}
+
+#[test]
+fn dump_effects_works() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = dump_effects(&dir.path().join("sounds")).unwrap();
+    assert_eq!(paths.len(), EFFECTS.len());
+    assert!(paths.iter().all(|path| path.is_file()));
+}
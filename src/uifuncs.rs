@@ -8,13 +8,19 @@ use std::io::{stdin, stdout, Write, Stdout};
 use termion::{clear};
 use termion::event::{Event, Key};
 use termion::input::TermRead;
-use termion::raw::IntoRawMode;
+use termion::raw::{IntoRawMode, RawTerminal};
 use termion::color::Rgb;
 
 use crossbeam_channel as channel;
 use std::time::Duration;
-
-#[derive(Debug, Copy, Clone)]
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use super::renderer::{Renderer, SharedRenderStats, ColorDepth};
+use super::cellart::Cell;
+use super::capture::{CaptureUIFuncs, SharedCapture};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RenderColor {
     Black,
     Red,
@@ -28,7 +34,9 @@ pub enum RenderColor {
     Default,
 }
 
-#[derive(Debug,Eq, PartialEq, Copy, Clone)]
+/// serialized as {"char": "a"}, "esc", "enter" and so on
+#[derive(Debug,Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum UIKeyEvent {
     Char(char),
     ESC,
@@ -44,27 +52,48 @@ pub enum UIKeyEvent {
 pub trait UIGraphics: Send {
     //fn draw(&self, x: usize, y: usize, color: &RenderColor, bgcolor: &RenderColor, text: &str);
     fn draw_area(&self, color: &RenderColor, bgcolor: &RenderColor, rect: &Vec<Vec<char>>, offset: Option<(usize, usize)>);
+    /// draw cells which have their own colours
+    fn draw_cells(&self, rect: &[Vec<Cell>], offset: Option<(usize, usize)>);
     fn flush(&self);
+    /// the screen has a new size. what was drawn is lost and has to be drawn again
+    fn resize(&self, _width: usize, _height: usize) {}
 }
 
 
 pub trait UIFuncs {
     fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent>;
     fn get_graphics(&self) -> Box<dyn UIGraphics>;
+    /// bytes written to the terminal. None when the backend does not render to a terminal
+    fn render_stats(&self) -> Option<SharedRenderStats>;
+    /// frames drawn so far. None when the backend does not keep them
+    fn capture(&self) -> Option<SharedCapture> {
+        None
+    }
+    /// width and height of the screen now and after each change. None when the backend has no screen
+    fn resize_events(&mut self) -> Option<channel::Receiver<(usize, usize)>> {
+        None
+    }
 }
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum UIFuncsType {
     DEBUG,
     TUI,
+    /// keeps the frames in memory instead of drawing them
+    Capture,
 }
-pub fn generate_uifuncs(uifuncs_type: UIFuncsType) -> Box<dyn UIFuncs> {
+pub fn generate_uifuncs(uifuncs_type: UIFuncsType, color_depth: ColorDepth) -> Box<dyn UIFuncs> {
     match uifuncs_type {
         UIFuncsType::DEBUG => {
             Box::new(DebugUIFuncs::new())
         },
         UIFuncsType::TUI => {
-            Box::new(TUIFuncs::new())
-        }
+            Box::new(TUIFuncs::new(color_depth))
+        },
+        UIFuncsType::Capture => {
+            Box::new(CaptureUIFuncs::new())
+        },
     }
 }
 
@@ -72,12 +101,18 @@ struct DebugGraphics {}
 pub struct DebugUIFuncs {
     keyevent_thread: Option<thread::JoinHandle<()>>,
     dummy_tx: Option<channel::Sender::<UIKeyEvent>>,
+    /// keys sent after waiting the msec of each one
+    script: Vec<(u64, UIKeyEvent)>,
 }
 
 impl UIGraphics for DebugGraphics {
     fn draw_area(&self, color: &RenderColor, bgcolor: &RenderColor, rect: &Vec<Vec<char>>, offset: Option<(usize, usize)>) {
         println!("draw_area -> fg: {:?} bg: {:?} (w: {}, h: {})", color, bgcolor, rect[0].len(), rect.len());
     }
+
+    fn draw_cells(&self, rect: &[Vec<Cell>], _offset: Option<(usize, usize)>) {
+        println!("draw_cells -> (w: {}, h: {})", rect.first().map_or(0, |line| line.len()), rect.len());
+    }
     
     fn flush(&self) {
         println!("flush");
@@ -90,6 +125,14 @@ impl DebugUIFuncs {
         DebugUIFuncs {
             keyevent_thread: Option::None,
             dummy_tx: Option::None,
+            script: Vec::new(),
+        }
+    }
+    /// plays the keys as if they were typed. end the script with ESC to leave the game
+    pub fn scripted(script: Vec<(u64, UIKeyEvent)>) -> Self {
+        DebugUIFuncs {
+            script,
+            ..DebugUIFuncs::new()
         }
     }
     fn dummy_keyevent(&self, keyevent: UIKeyEvent) {
@@ -104,6 +147,16 @@ impl UIFuncs for DebugUIFuncs {
     fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent>{
         let (chan_tx, chan_rx) = channel::unbounded::<UIKeyEvent>();
         let (dummy_tx, dummy_rx) = channel::unbounded::<UIKeyEvent>();
+        let script = std::mem::take(&mut self.script);
+        let script_tx = dummy_tx.clone();
+        thread::spawn(move || {
+            for (wait, keyevent) in script {
+                thread::sleep(Duration::from_millis(wait));
+                if script_tx.send(keyevent).is_err() {
+                    break;
+                }
+            }
+        });
         self.dummy_tx = Some(dummy_tx);
 
         let th = thread::spawn(move || {
@@ -113,7 +166,10 @@ impl UIFuncs for DebugUIFuncs {
                         match received {
                             Ok(keyevent) => {
                                 match keyevent {
-                                    UIKeyEvent::ESC => { break; },
+                                    UIKeyEvent::ESC => {
+                                        let _ = chan_tx.send(keyevent);
+                                        break;
+                                    },
                                     _ => {
                                         if let Err(_) = chan_tx.send(keyevent) {
                                         break;
@@ -136,107 +192,123 @@ impl UIFuncs for DebugUIFuncs {
     fn get_graphics(&self) -> Box<dyn UIGraphics> {
         Box::new(DebugGraphics{})
     }
+    fn render_stats(&self) -> Option<SharedRenderStats> {
+        None
+    }
 
 }
+/// draws into a back buffer and writes the changed cells once a frame on `flush`
 pub struct TUIGraphics {
-}
-
-impl TUIGraphics {
-    fn draw(&self, stdout: &mut Stdout, x: usize, y: usize, color: &RenderColor, bgcolor: &RenderColor, text: &str){
-        write!(stdout, "{}{}{}", 
-            termion::cursor::Goto(x as u16, y as u16), 
-            &TUIFuncs::to_termion_color(color),
-            //&TUIFuncs::to_termion_bgcolor(bgcolor),
-            //termion::color::Fg(termion::color::Rgb(0,0,255)),
-            //termion::color::Fg(termion::color::Blue),
-            //termion::color::Bg(TUIFuncs::to_termion_color(bgcolor)),
-            text
-        ).unwrap();
-    }
+    renderer: RefCell<Renderer>,
 }
 
 impl UIGraphics for TUIGraphics { 
 
     fn draw_area(&self, color: &RenderColor, bgcolor: &RenderColor, area: &Vec<Vec<char>>, offset: Option<(usize,usize)>) {
-        let mut stdout = stdout().into_raw_mode().unwrap();
-
-        let (offset_x, offset_y) = offset.unwrap_or((0, 0));
+        self.renderer.borrow_mut().draw_area(color, bgcolor, area, offset);
+    }
 
-        for (i, line) in area.iter().enumerate() {
-            let mut line_string = String::new();
-            for ch in line {
-                line_string.push(*ch);
-            }
-            self.draw(&mut stdout, offset_x, offset_y + i, color, bgcolor, &line_string);
-        }
-        stdout.flush().unwrap();
+    fn draw_cells(&self, area: &[Vec<Cell>], offset: Option<(usize, usize)>) {
+        self.renderer.borrow_mut().draw_cells(area, offset);
     }
 
     fn flush(&self) {
+        let frame = self.renderer.borrow_mut().render();
+        if !frame.is_empty() {
+            let stdout = stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(&frame).unwrap();
+            stdout.flush().unwrap();
+        }
+    }
 
+    fn resize(&self, width: usize, height: usize) {
+        self.renderer.borrow_mut().set_screen_size(width, height);
+        // the terminal has rewrapped the old frame
+        let stdout = stdout();
+        let mut stdout = stdout.lock();
+        let _ = write!(stdout, "{}", clear::All);
+        let _ = stdout.flush();
     }
 
 }
 
 pub struct TUIFuncs {
     keyevent_thread: Option<thread::JoinHandle<()>>,
+    /// the terminal stays in raw mode until TUIFuncs is dropped
+    raw_stdout: RawTerminal<Stdout>,
+    color_depth: ColorDepth,
+    render_stats: SharedRenderStats,
+    /// closed on drop so that the SIGWINCH thread ends
+    signals: Option<signal_hook::iterator::Handle>,
+}
+
+/// columns and rows of the terminal
+fn terminal_size() -> Option<(usize, usize)> {
+    termion::terminal_size().ok().map(|(width, height)| (width as usize, height as usize))
 }
 
 impl TUIFuncs {
-    pub fn new() -> TUIFuncs {
-        let mut stdout = stdout().into_raw_mode().unwrap();
-        write!(stdout, "{}{}", termion::clear::All, termion::cursor::Hide);
-        stdout.flush().unwrap();
+    pub fn new(color_depth: ColorDepth) -> TUIFuncs {
+        let mut raw_stdout = stdout().into_raw_mode().unwrap();
+        write!(raw_stdout, "{}{}", termion::clear::All, termion::cursor::Hide).unwrap();
+        raw_stdout.flush().unwrap();
         TUIFuncs {
             keyevent_thread: Option::None,
+            raw_stdout,
+            color_depth,
+            render_stats: SharedRenderStats::default(),
+            signals: None,
         }
     }
+}
 
-    fn to_termion_color(color: &RenderColor) -> String { //termion::color::Rgb {
-
-        match color {
-            RenderColor::Black =>   { format!("{}", termion::color::Fg(termion::color::Black)) },
-            RenderColor::Red =>     { format!("{}", termion::color::Fg(termion::color::Red)) },
-            RenderColor::Green =>   { format!("{}", termion::color::Fg(termion::color::Green)) },
-            RenderColor::Yellow =>  { format!("{}", termion::color::Fg(termion::color::Yellow)) },
-            RenderColor::Blue =>    { format!("{}", termion::color::Fg(termion::color::Blue)) },
-            RenderColor::Magenta => { format!("{}", termion::color::Fg(termion::color::Magenta)) },
-            RenderColor::Cyan =>    { format!("{}", termion::color::Fg(termion::color::Cyan)) },
-            RenderColor::White =>   { format!("{}", termion::color::Fg(termion::color::White)) },
-            RenderColor::Byte(r,g,b) => { format!("{}", termion::color::Fg(termion::color::Rgb(*r,*g,*b))) },
-            RenderColor::Default => { format!("{}", termion::color::Fg(termion::color::Black)) },
-            _ => { format!("{}", termion::color::Fg(termion::color::Black)) },
-        }
-
-    }
-
-    fn to_termion_bgcolor(color: &RenderColor) -> String { //termion::color::Rgb {
-
-        match color {
-            RenderColor::Black =>   { format!("{}", termion::color::Bg(termion::color::Black)) },
-            RenderColor::Red =>     { format!("{}", termion::color::Bg(termion::color::Red)) },
-            RenderColor::Green =>   { format!("{}", termion::color::Bg(termion::color::Green)) },
-            RenderColor::Yellow =>  { format!("{}", termion::color::Bg(termion::color::Yellow)) },
-            RenderColor::Blue =>    { format!("{}", termion::color::Bg(termion::color::Blue)) },
-            RenderColor::Magenta => { format!("{}", termion::color::Bg(termion::color::Magenta)) },
-            RenderColor::Cyan =>    { format!("{}", termion::color::Bg(termion::color::Cyan)) },
-            RenderColor::White =>   { format!("{}", termion::color::Bg(termion::color::White)) },
-            RenderColor::Byte(r,g,b) => { format!("{}", termion::color::Bg(termion::color::Rgb(*r,*g,*b))) },
-            RenderColor::Default => { format!("{}", termion::color::Bg(termion::color::Black)) },
-            _ => { format!("{}", termion::color::Bg(termion::color::Black)) },
+impl Drop for TUIFuncs {
+    fn drop(&mut self) {
+        if let Some(signals) = &self.signals {
+            signals.close();
         }
-
+        let _ = write!(self.raw_stdout, "{}{}{}", termion::color::Fg(termion::color::Reset), termion::color::Bg(termion::color::Reset), termion::cursor::Show);
+        let _ = self.raw_stdout.flush();
     }
 }
 
 impl UIFuncs for TUIFuncs {
 
     fn get_graphics(&self) -> Box<dyn UIGraphics> {
+        let mut renderer = Renderer::new(self.color_depth, self.render_stats.clone());
+        if let Some((width, height)) = terminal_size() {
+            renderer.set_screen_size(width, height);
+        }
         let graphics = TUIGraphics {
+            renderer: RefCell::new(renderer),
         };
         Box::new(graphics)
     }
 
+    fn render_stats(&self) -> Option<SharedRenderStats> {
+        Some(self.render_stats.clone())
+    }
+
+    fn resize_events(&mut self) -> Option<channel::Receiver<(usize, usize)>> {
+        let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGWINCH]).ok()?;
+        self.signals = Some(signals.handle());
+        let (chan_tx, chan_rx) = channel::unbounded::<(usize, usize)>();
+        if let Some(size) = terminal_size() {
+            let _ = chan_tx.send(size);
+        }
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                if let Some(size) = terminal_size() {
+                    if chan_tx.send(size).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Some(chan_rx)
+    }
+
 
     fn start_keyevent_thread(&mut self) -> channel::Receiver<UIKeyEvent> {
         let (chan_tx, chan_rx) = channel::unbounded::<UIKeyEvent>();
@@ -246,12 +318,17 @@ impl UIFuncs for TUIFuncs {
             for c in stdin.keys() {
                 match c.unwrap() {
                     Key::Char(ch) => {
-                        println!("{}", ch);
                         if let Err(_) = chan_tx.send(UIKeyEvent::Char(ch)) {
                             break;
                         }
                     },
-                    Key::Esc => { break; },
+                    Key::Esc => {
+                        // the controller shuts the server down on ESC. the channel closes after it
+                        let _ = chan_tx.send(UIKeyEvent::ESC);
+                        break;
+                    },
+                    Key::Left if chan_tx.send(UIKeyEvent::Left).is_err() => break,
+                    Key::Right if chan_tx.send(UIKeyEvent::Right).is_err() => break,
                     _ => {},
                 }
             }
@@ -0,0 +1,208 @@
+//! typing targets for each typing mode
+
+use serde::{Deserialize, Serialize};
+use super::romaji::{RomajiInput, InputResult, TypedUnit};
+use super::pict::Pict;
+
+/// which name of a pict the player types
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TypingMode {
+    /// type the kana name in romaji
+    #[serde(rename = "ja")]
+    Japanese,
+    /// type the english name
+    #[serde(rename = "en")]
+    English,
+}
+
+pub trait WordInput: Send {
+    fn input(&mut self, ch: char) -> InputResult;
+    fn is_finished(&self) -> bool;
+    /// text of the telop and the number of characters already typed
+    fn telop(&self) -> (String, usize);
+    /// text of the subtelop and its progress
+    fn subtelop(&self) -> (String, usize);
+    /// keys which are accepted as the next input
+    fn expected_keys(&self) -> Vec<char>;
+    /// syllable which is being typed now. a kana unit or a letter
+    fn current_syllable(&self) -> String;
+    /// language used to speak the units of the word
+    fn lang(&self) -> &'static str;
+    /// text spoken when the pict appears
+    fn name(&self) -> &str;
+}
+
+pub fn generate_word_input(mode: TypingMode, pict: &Pict) -> Box<dyn WordInput> {
+    match mode {
+        TypingMode::Japanese => {
+            Box::new(JapaneseInput::new(&pict.ja))
+        },
+        TypingMode::English => {
+            Box::new(EnglishInput::new(&pict.en))
+        },
+    }
+}
+
+struct JapaneseInput {
+    words_ja: String,
+    romaji_input: RomajiInput,
+}
+
+impl JapaneseInput {
+    fn new(words_ja: &str) -> Self {
+        JapaneseInput {
+            words_ja: words_ja.to_string(),
+            romaji_input: RomajiInput::new(words_ja),
+        }
+    }
+}
+
+impl WordInput for JapaneseInput {
+    fn input(&mut self, ch: char) -> InputResult {
+        self.romaji_input.input(ch)
+    }
+    fn is_finished(&self) -> bool {
+        self.romaji_input.is_finished()
+    }
+    fn telop(&self) -> (String, usize) {
+        self.romaji_input.display()
+    }
+    fn subtelop(&self) -> (String, usize) {
+        (self.words_ja.to_string(), self.romaji_input.kana_pos())
+    }
+    fn expected_keys(&self) -> Vec<char> {
+        self.romaji_input.expected_keys()
+    }
+    fn current_syllable(&self) -> String {
+        self.romaji_input.current_kana()
+    }
+    fn lang(&self) -> &'static str {
+        "ja"
+    }
+    fn name(&self) -> &str {
+        &self.words_ja
+    }
+}
+
+/// english name typed word by word. the space between words can be skipped
+pub struct EnglishInput {
+    words: String,
+    chars: Vec<char>,
+    pos: usize,
+    word_start: usize,
+    word_end: usize,
+    typed: String,
+}
+
+impl EnglishInput {
+    pub fn new(words: &str) -> Self {
+        let words = words.split_whitespace().collect::<Vec<&str>>().join(" ");
+        EnglishInput {
+            chars: words.chars().collect(),
+            words,
+            pos: 0,
+            word_start: 0,
+            word_end: 0,
+            typed: String::new(),
+        }
+    }
+
+    fn matches(expected: char, ch: char) -> bool {
+        expected.eq_ignore_ascii_case(&ch)
+    }
+
+    fn advance(&mut self, ch: char) -> Vec<TypedUnit> {
+        if ch != ' ' {
+            self.typed.push(ch);
+        }
+        self.pos += 1;
+        let mut committed = Vec::new();
+        if ch != ' ' && (self.pos >= self.chars.len() || self.chars[self.pos] == ' ') {
+            self.word_end = self.pos;
+            committed.push(TypedUnit {
+                text: self.chars[self.word_start..self.pos].iter().collect(),
+                keys: self.typed.split_off(0),
+            });
+        }
+        if self.pos < self.chars.len() && self.chars[self.pos - 1] == ' ' {
+            self.word_start = self.pos;
+        }
+        committed
+    }
+}
+
+impl WordInput for EnglishInput {
+    fn input(&mut self, ch: char) -> InputResult {
+        if self.is_finished() {
+            return InputResult::Finished;
+        }
+        let expected = self.chars[self.pos];
+        if Self::matches(expected, ch) {
+            InputResult::Accepted(self.advance(ch))
+        } else if expected == ' ' && self.pos + 1 < self.chars.len() && Self::matches(self.chars[self.pos + 1], ch) {
+            self.advance(' ');
+            InputResult::Accepted(self.advance(ch))
+        } else {
+            InputResult::Miss
+        }
+    }
+    fn is_finished(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+    fn telop(&self) -> (String, usize) {
+        (self.words.to_string(), self.pos)
+    }
+    fn subtelop(&self) -> (String, usize) {
+        (self.words.to_string(), self.word_end)
+    }
+    fn expected_keys(&self) -> Vec<char> {
+        match self.chars.get(self.pos) {
+            Some(' ') => {
+                let mut keys = vec![' '];
+                if let Some(next) = self.chars.get(self.pos + 1) {
+                    keys.push(next.to_ascii_lowercase());
+                }
+                keys
+            },
+            Some(ch) => vec![ch.to_ascii_lowercase()],
+            None => Vec::new(),
+        }
+    }
+    fn current_syllable(&self) -> String {
+        self.chars[self.pos..].iter().find(|ch| **ch != ' ').map(|ch| ch.to_string()).unwrap_or_default()
+    }
+    fn lang(&self) -> &'static str {
+        "en"
+    }
+    fn name(&self) -> &str {
+        &self.words
+    }
+}
+
+#[test]
+fn englishinput_works() {
+    let mut input = EnglishInput::new("MEGAMOUSE SHARK");
+    let mut words = Vec::new();
+    for ch in "megamouseshark".chars() {
+        match input.input(ch) {
+            InputResult::Accepted(committed) => words.extend(committed.into_iter().map(|u| u.text)),
+            other => panic!("{} was not accepted: {:?}", ch, other),
+        }
+    }
+    assert_eq!(words, ["MEGAMOUSE", "SHARK"]);
+    assert!(input.is_finished());
+    assert_eq!(input.input('a'), InputResult::Finished);
+
+    let mut input = EnglishInput::new("SEA LION");
+    assert_eq!(input.input('x'), InputResult::Miss);
+    for ch in "sea".chars() {
+        input.input(ch);
+    }
+    assert_eq!(input.subtelop(), (String::from("SEA LION"), 3));
+    assert_eq!(input.expected_keys(), vec![' ', 'l']);
+    assert_eq!(input.current_syllable(), "L");
+    assert_eq!(input.input(' '), InputResult::Accepted(vec![]));
+    assert_eq!(input.telop(), (String::from("SEA LION"), 4));
+    assert_eq!(input.input('l'), InputResult::Accepted(vec![]));
+    assert_eq!(input.subtelop(), (String::from("SEA LION"), 3));
+}